@@ -0,0 +1,415 @@
+//! Cleanup of stale repositories, which were neither committed to nor
+//! visited for a while.
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, FileContext, Result};
+use crate::history::History;
+use crate::index::{Index, APP};
+use crate::path_util::is_within;
+use crate::repo::RepoInfo;
+use crate::report::table;
+use crate::safety::SafetyReport;
+use app_dirs::{get_app_dir, AppDataType};
+use chrono::{Local, TimeZone, Utc};
+use console::{Style, Term};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use walkdir::WalkDir;
+
+/// Default age of stale repos, see `query::parse_hours`.
+pub const DEFAULT_AGE: &str = "6m";
+
+/// A repository without recent commits or visits.
+pub struct StaleRepo {
+    pub path: String,
+    pub display: String,
+    /// Age of the last commit in hours, `i64::MAX` if unknown.
+    pub hours_since_last_commit: i64,
+    /// Unix timestamp of the last visit.
+    pub last_visit: Option<i64>,
+    /// Size of the working tree including `.git`, in bytes.
+    pub size: u64,
+    /// Whether the repo and the repos inside it can be deleted without
+    /// losing work, see `safety`.
+    pub safe: bool,
+    /// Short description of what is not synchronized.
+    pub sync: String,
+    /// Indexed repos inside the repo, which are removed along with it.
+    pub nested: Vec<String>,
+}
+
+/// Returns the repos without commits and visits in the last `max_hours`
+/// hours, largest first.
+pub fn find_stale(repos: &[RepoInfo], history: &History, max_hours: i64) -> Vec<StaleRepo> {
+    let now = Utc::now().timestamp();
+    let mut stale = repos
+        .iter()
+        .filter(|repo| repo.hours_since_last_commit >= max_hours)
+        .filter_map(|repo| {
+            let last_visit = history.visits(&repo.path).map(|v| v.last_visit);
+            if let Some(last_visit) = last_visit {
+                if (now - last_visit) / 3600 < max_hours {
+                    return None;
+                }
+            }
+            let (mut safe, mut sync) = match SafetyReport::new(&repo.path) {
+                Ok(report) => (report.is_safe(), sync_summary(&report)),
+                Err(e) => (false, format!("error: {}", e.message())),
+            };
+            let nested = repos
+                .iter()
+                .filter(|r| r.path != repo.path && is_within(&r.path, &repo.path))
+                .map(|r| r.path.clone())
+                .collect::<Vec<_>>();
+            if !nested.is_empty() {
+                let unsafe_nested = nested
+                    .iter()
+                    .filter(|path| !SafetyReport::new(path).is_ok_and(|r| r.is_safe()))
+                    .count();
+                safe &= unsafe_nested == 0;
+                sync = match unsafe_nested {
+                    0 => format!("{}, {} nested", sync, nested.len()),
+                    n => format!("{}, {} nested ({} not synced)", sync, nested.len(), n),
+                };
+            }
+            Some(StaleRepo {
+                path: repo.path.clone(),
+                display: repo.display.clone(),
+                hours_since_last_commit: repo.hours_since_last_commit,
+                last_visit,
+                size: disk_usage(Path::new(&repo.path)),
+                safe,
+                sync,
+                nested,
+            })
+        })
+        .collect::<Vec<_>>();
+    stale.sort_by_key(|repo| std::cmp::Reverse(repo.size));
+    stale
+}
+
+/// Summarizes what would be lost, e.g. `2 unpushed, 3 untracked`.
+fn sync_summary(report: &SafetyReport) -> String {
+    let counts = [
+        (report.unsynced.len(), "unpushed"),
+        (report.changed.len(), "changed"),
+        (report.untracked.len(), "untracked"),
+    ];
+    let parts = counts
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, what)| format!("{} {}", n, what))
+        .collect::<Vec<_>>();
+    if parts.is_empty() {
+        "synced".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// Returns the total size of the files below `path`, in bytes.
+pub fn disk_usage(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Formats a size in bytes like `4.2 MB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Formats a unix timestamp as a local date.
+pub fn format_date(timestamp: i64) -> String {
+    Local.timestamp(timestamp, 0).format("%Y-%m-%d").to_string()
+}
+
+/// Formats the stale repos as a table.
+pub fn format_table(stale: &[StaleRepo]) -> Vec<String> {
+    let rows = stale
+        .iter()
+        .map(|repo| {
+            let last_commit = if repo.hours_since_last_commit == i64::MAX {
+                "never".to_string()
+            } else {
+                format_date(Utc::now().timestamp() - repo.hours_since_last_commit * 3600)
+            };
+            vec![
+                repo.display.clone(),
+                format_size(repo.size),
+                last_commit,
+                repo.last_visit
+                    .map(format_date)
+                    .unwrap_or_else(|| "never".to_string()),
+                repo.sync.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    table(
+        &["Repository", "Size", "Last commit", "Last visit", "Status"],
+        &rows,
+    )
+}
+
+/// Asks for every stale repo whether to archive, delete or keep it. Removed
+/// repos and the repos inside them are removed from the index and the
+/// history, too.
+pub fn interact(
+    stale: &[StaleRepo],
+    index: &mut Index,
+    history: &mut History,
+    archive_dir: &Path,
+) -> Result<()> {
+    let term = Term::stderr();
+    let warning = Style::new().red();
+    let mut freed = 0;
+    for repo in stale {
+        // Removed along with a stale repo around it
+        if !Path::new(&repo.path).exists() {
+            continue;
+        }
+        let status = if repo.safe {
+            repo.sync.clone()
+        } else {
+            format!("{}", warning.apply_to(&repo.sync))
+        };
+        term.write_str(&format!(
+            "{} ({}, {}): [a]rchive, [d]elete, [s]kip or [q]uit? ",
+            repo.display,
+            format_size(repo.size),
+            status
+        ))?;
+        let action = term.read_char()?;
+        term.write_line(&action.to_string())?;
+        let question = match action {
+            'a' => format!("Archive to {} and remove", archive_dir.display()),
+            'd' => "Permanently delete".to_string(),
+            'q' => break,
+            _ => continue,
+        };
+        if !repo.safe {
+            for line in SafetyReport::new(&repo.path)?.lines(&repo.display) {
+                term.write_line(&format!("  {}", line))?;
+            }
+            for path in &repo.nested {
+                match SafetyReport::new(path) {
+                    Ok(report) if report.is_safe() => {}
+                    Ok(report) => {
+                        for line in report.lines(path) {
+                            term.write_line(&format!("  {}", line))?;
+                        }
+                    }
+                    Err(e) => term.write_line(&format!("  {}: {}", path, e.message()))?,
+                }
+            }
+        }
+        if !repo.nested.is_empty() {
+            term.write_line(&format!(
+                "  Repositories inside {}: {}",
+                repo.display,
+                repo.nested.join(", ")
+            ))?;
+        }
+        term.write_str(&format!("{} {}? [y/N] ", question, repo.display))?;
+        let confirmed = term.read_char()?;
+        term.write_line(&confirmed.to_string())?;
+        if confirmed != 'y' {
+            continue;
+        }
+        if action == 'a' {
+            let tarball = archive(&repo.path, archive_dir)?;
+            term.write_line(&format!("Archived to {}", tarball.display()))?;
+        } else {
+            delete(&repo.path)?;
+            term.write_line(&format!("Deleted {}", repo.display))?;
+        }
+        index.remove_repo(&repo.path)?;
+        history.remove_repo(&repo.path);
+        history.save()?;
+        freed += repo.size;
+    }
+    if freed > 0 {
+        term.write_line(&format!("Freed {}.", format_size(freed)))?;
+    }
+    Ok(())
+}
+
+/// Writes the repository at `path` to a tarball in `archive_dir` and
+/// removes it. Returns the path of the tarball.
+pub fn archive(path: &str, archive_dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(archive_dir).file_context(archive_dir)?;
+    let name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "repo".to_string());
+    let date = Local::now().format("%Y%m%d");
+    let mut tarball = archive_dir.join(format!("{}-{}.tar.gz", name, date));
+    let mut n = 2;
+    while tarball.exists() {
+        tarball = archive_dir.join(format!("{}-{}-{}.tar.gz", name, date, n));
+        n += 1;
+    }
+    let file = File::create(&tarball).file_context(&tarball)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    builder.follow_symlinks(false);
+    let result = builder
+        .append_dir_all(&name, path)
+        .and_then(|_| builder.into_inner())
+        .and_then(|encoder| encoder.finish());
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tarball);
+        return Err(e).file_context(&tarball);
+    }
+    delete(path)?;
+    Ok(tarball)
+}
+
+/// Returns the default directory of archived repos, in the user's data
+/// directory.
+pub fn default_archive_dir() -> Result<PathBuf> {
+    get_app_dir(AppDataType::UserData, &APP, "archive").map_err(|_| Error::NoDataDir)
+}
+
+/// Removes the repository at `path`.
+pub fn delete(path: &str) -> Result<()> {
+    fs::remove_dir_all(path).file_context(Path::new(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{commit, head_branch, init, push};
+
+    const MONTH: i64 = 24 * 30;
+
+    /// Returns a repo at `dir` without commits in a year.
+    fn old_repo(dir: &Path) -> RepoInfo {
+        let mut repo = RepoInfo::new(dir.to_str().unwrap());
+        repo.display = dir.file_name().unwrap().to_string_lossy().to_string();
+        repo.hours_since_last_commit = 12 * MONTH;
+        repo
+    }
+
+    /// Creates a repo with a pushed commit.
+    fn pushed_repo(dir: &Path) {
+        let git2_repo = init(dir);
+        let tip = commit(&git2_repo, "README");
+        push(&git2_repo, &head_branch(&git2_repo), tip);
+    }
+
+    #[test]
+    fn recent_commits_and_visits_are_not_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::load_file(&dir.path().join("history.txt"));
+        let mut repos = ["api", "web", "docs"]
+            .iter()
+            .map(|name| {
+                pushed_repo(&dir.path().join(name));
+                old_repo(&dir.path().join(name))
+            })
+            .collect::<Vec<_>>();
+        repos[1].hours_since_last_commit = MONTH;
+        history.record_visit(&repos[2].path);
+        let stale = find_stale(&repos, &history, 6 * MONTH);
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].display, "api");
+        assert!(stale[0].safe);
+        assert_eq!(stale[0].sync, "synced");
+        assert!(stale[0].nested.is_empty());
+        assert!(stale[0].size > 0);
+    }
+
+    #[test]
+    fn nested_repos_are_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::load_file(&dir.path().join("history.txt"));
+        let api = dir.path().join("api");
+        pushed_repo(&api);
+        pushed_repo(&api.join("vendor/lib"));
+        let repos = vec![old_repo(&api), old_repo(&api.join("vendor/lib"))];
+        let stale = find_stale(&repos, &history, 6 * MONTH);
+        let outer = stale.iter().find(|repo| repo.display == "api").unwrap();
+        assert_eq!(outer.nested, vec![repos[1].path.clone()]);
+        assert!(outer.sync.ends_with(", 1 nested"));
+        assert!(!outer.safe);
+
+        // Commits only in the nested repo
+        commit(&git2::Repository::open(&repos[1].path).unwrap(), "README");
+        let stale = find_stale(&repos, &history, 6 * MONTH);
+        let outer = stale.iter().find(|repo| repo.display == "api").unwrap();
+        assert!(outer.sync.ends_with(", 1 nested (1 not synced)"));
+    }
+
+    #[test]
+    fn summary_of_unsynced_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = init(dir.path());
+        commit(&git2_repo, "README");
+        std::fs::write(dir.path().join("README"), "changed").unwrap();
+        std::fs::write(dir.path().join("a.txt"), "").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "").unwrap();
+        let report = SafetyReport::new(dir.path().to_str().unwrap()).unwrap();
+        assert_eq!(sync_summary(&report), "1 unpushed, 1 changed, 2 untracked");
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GB");
+        assert_eq!(format_size(u64::MAX), "16777216.0 TB");
+    }
+
+    #[test]
+    fn archive_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_dir = dir.path().join("archive");
+        let api = dir.path().join("api");
+        for n in 1..=2 {
+            pushed_repo(&api);
+            let tarball = archive(api.to_str().unwrap(), &archive_dir).unwrap();
+            assert!(!api.exists());
+            let name = tarball.file_name().unwrap().to_string_lossy().to_string();
+            let date = Local::now().format("%Y%m%d");
+            if n == 1 {
+                assert_eq!(name, format!("api-{}.tar.gz", date));
+            } else {
+                assert_eq!(name, format!("api-{}-2.tar.gz", date));
+            }
+        }
+        let tarball = File::open(
+            archive_dir
+                .read_dir()
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap()
+                .path(),
+        );
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tarball.unwrap()));
+        let paths = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert!(paths.contains(&"api/README".to_string()));
+        assert!(paths.iter().any(|path| path.starts_with("api/.git/")));
+    }
+}
@@ -0,0 +1,134 @@
+//! User configuration, read from `config.toml` in the user's config
+//! directory.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::index::APP;
+use app_dirs::{get_app_root, AppDataType};
+use serde::Deserialize;
+
+const CONFIG_FILE: &str = "config.toml";
+
+/// Assigns `tag` to every repo matching all given patterns.
+#[derive(Deserialize)]
+pub struct GroupRule {
+    pub tag: String,
+    /// Glob matched against the repo path, e.g. `~/ops/**`.
+    pub path: Option<String>,
+    /// Glob matched against the remote URL, e.g. `*github.com/acme-infra/*`.
+    pub remote: Option<String>,
+}
+
+/// Key bindings of the picker, see `keymap`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct KeysConfig {
+    /// One of `default`, `vim` or `emacs`.
+    pub preset: Option<String>,
+    /// Bindings of the insert mode, mapping keys to actions.
+    pub insert: BTreeMap<String, String>,
+    /// Bindings of the normal mode of the `vim` preset.
+    pub normal: BTreeMap<String, String>,
+}
+
+/// Styles of the picker, see `theme`. Styles are written like `cyan.bold`
+/// or `black.on_yellow`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// One of `dark` or `light`.
+    pub preset: Option<String>,
+    /// The selected item.
+    pub selected: Option<String>,
+    /// Items other than the selected one.
+    pub unselected: Option<String>,
+    /// The selection and pin markers.
+    pub indicator: Option<String>,
+    /// Characters matching the query.
+    pub highlight: Option<String>,
+    /// Prompts and headings.
+    pub prompt: Option<String>,
+    /// The footer and status columns.
+    pub status: Option<String>,
+}
+
+/// Settings of `git quick cleanup`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct CleanupConfig {
+    /// Repos without commits and visits for this long are stale, e.g. `6m`.
+    pub older_than: Option<String>,
+    /// Directory of the tarballs of archived repos.
+    pub archive_dir: Option<String>,
+}
+
+/// Settings of `git quick clone`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct CloneConfig {
+    /// Directory below which repos are cloned as `<host>/<owner>/<repo>`.
+    pub root: Option<String>,
+}
+
+/// Settings of `git quick relocate`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RelocateConfig {
+    /// Location of repos, with the placeholders `{host}`, `{owner}` and
+    /// `{name}` of the remote URL, e.g. `~/src/{host}/{owner}/{name}`.
+    pub template: Option<String>,
+}
+
+/// Settings of `mine:` queries.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct MineConfig {
+    /// Author emails of your commits, in addition to `user.email` of the
+    /// git config.
+    pub emails: Vec<String>,
+    /// Default period of `mine:` queries, e.g. `30d`.
+    pub within: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    /// Tags assigned to repo paths.
+    pub tags: BTreeMap<String, Vec<String>>,
+    /// Rules assigning tags to groups of repos.
+    pub groups: Vec<GroupRule>,
+    pub keys: KeysConfig,
+    pub theme: ThemeConfig,
+    pub cleanup: CleanupConfig,
+    pub clone: CloneConfig,
+    pub relocate: RelocateConfig,
+    pub mine: MineConfig,
+}
+
+impl Config {
+    /// Reads the config file. A missing file yields the default config.
+    pub fn load() -> Config {
+        let config_file = match config_file() {
+            Some(path) => path,
+            None => return Config::default(),
+        };
+        match fs::read_to_string(&config_file) {
+            Ok(content) => match toml::from_str(&content) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Ignoring {}: {}", config_file.display(), e);
+                    Config::default()
+                }
+            },
+            Err(_) => Config::default(),
+        }
+    }
+}
+
+/// Returns the path of the config file.
+pub fn config_file() -> Option<PathBuf> {
+    get_app_root(AppDataType::UserConfig, &APP)
+        .ok()
+        .map(|dir| dir.join(CONFIG_FILE))
+}
@@ -0,0 +1,185 @@
+//! Error type of git-quick.
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The home directory, which is scanned for repos, is unknown.
+    NoHomeDir,
+    /// The cache directory, which holds the index and the history, is
+    /// unknown.
+    NoCacheDir,
+    /// The data directory, which holds the archived repos, is unknown.
+    NoDataDir,
+    /// Reading or writing a file failed.
+    File(PathBuf, io::Error),
+    /// The given path is not inside a git repository.
+    NotARepository(String),
+    /// The remote URL has no repository name.
+    InvalidUrl(String),
+    /// A manifest of `git quick export` could not be parsed.
+    InvalidManifest(PathBuf, String),
+    /// The path template of `git quick relocate` has no `{name}`.
+    InvalidTemplate(String),
+    /// The `[keys]` section of the config has invalid or conflicting
+    /// bindings.
+    InvalidKeys(Vec<String>),
+    /// The `[theme]` section of the config is invalid.
+    InvalidTheme(String),
+    /// The `[mine]` section of the config is invalid.
+    InvalidMine(String),
+    /// Your commits were asked for, but you have no email configured.
+    NoEmail,
+    /// Another process holds the lock file.
+    Locked(PathBuf),
+    /// Unknown subcommand or invalid arguments.
+    Usage,
+    /// `git quick check` found work which would be lost.
+    Unsafe(String),
+    /// Some repos of the manifest could not be restored, see the log.
+    NotRestored(usize, PathBuf),
+    /// Some of the planned moves of `git quick relocate` failed.
+    NotMoved(usize, usize),
+    /// An external command exited with an error.
+    CommandFailed(String),
+    Io(io::Error),
+    Git(git2::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NoHomeDir => write!(f, "could not determine the home directory"),
+            Error::NoCacheDir => write!(f, "could not determine the cache directory"),
+            Error::NoDataDir => write!(
+                f,
+                "could not determine the data directory, use --archive-dir"
+            ),
+            Error::File(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::NotARepository(path) => write!(f, "{} is not a git repository", path),
+            Error::InvalidUrl(url) => write!(f, "invalid repository URL `{}`", url),
+            Error::InvalidManifest(path, e) => {
+                write!(f, "{}: invalid manifest: {}", path.display(), e)
+            }
+            Error::InvalidTemplate(template) => {
+                write!(f, "path template `{}` has no `{{name}}`", template)
+            }
+            Error::InvalidKeys(errors) => {
+                write!(f, "invalid key bindings: {}", errors.join("; "))
+            }
+            Error::InvalidTheme(e) => write!(f, "invalid theme: {}", e),
+            Error::InvalidMine(e) => write!(f, "invalid [mine] config: {}", e),
+            Error::NoEmail => write!(f, "no user.email configured and no [mine] emails"),
+            Error::Locked(path) => write!(f, "{}: locked by another process", path.display()),
+            Error::Usage => write!(f, "invalid arguments"),
+            Error::Unsafe(path) => write!(f, "{} is not safe to delete", path),
+            Error::NotRestored(failed, log) => write!(
+                f,
+                "{} repositories could not be restored, see {}. Run the restore again to retry.",
+                failed,
+                log.display()
+            ),
+            Error::NotMoved(failed, total) => {
+                write!(f, "{} of {} repositories were not moved", failed, total)
+            }
+            Error::CommandFailed(cmd) => write!(f, "`{}` failed", cmd),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Git(e) => write!(f, "{}", e.message()),
+        }
+    }
+}
+
+impl Error {
+    /// Returns the exit status for the error: 2 for usage errors, 1
+    /// otherwise.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<git2::Error> for Error {
+    fn from(e: git2::Error) -> Error {
+        Error::Git(e)
+    }
+}
+
+/// Adds the path of the file to I/O errors.
+pub trait FileContext<T> {
+    fn file_context(self, path: &std::path::Path) -> Result<T>;
+}
+
+impl<T> FileContext<T> for io::Result<T> {
+    fn file_context(self, path: &std::path::Path) -> Result<T> {
+        self.map_err(|e| Error::File(path.to_path_buf(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn config_errors() {
+        let error = Error::InvalidKeys(vec![
+            "unknown key `hyper-x`".to_string(),
+            "unknown action `fly`".to_string(),
+        ]);
+        assert_eq!(
+            error.to_string(),
+            "invalid key bindings: unknown key `hyper-x`; unknown action `fly`"
+        );
+        let error = Error::InvalidTheme("unknown preset `solarized`".to_string());
+        assert_eq!(
+            error.to_string(),
+            "invalid theme: unknown preset `solarized`"
+        );
+        let error = Error::InvalidMine("within = `soon` is not a period like `2w`".to_string());
+        assert_eq!(
+            error.to_string(),
+            "invalid [mine] config: within = `soon` is not a period like `2w`"
+        );
+        assert_eq!(
+            Error::NoEmail.to_string(),
+            "no user.email configured and no [mine] emails"
+        );
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(Error::Usage.exit_code(), 2);
+        assert_eq!(Error::Unsafe("/work/api".to_string()).exit_code(), 1);
+        assert_eq!(
+            Error::NotRestored(2, PathBuf::from("repos.toml.log")).to_string(),
+            "2 repositories could not be restored, see repos.toml.log. \
+             Run the restore again to retry."
+        );
+    }
+
+    #[test]
+    fn missing_data_dir_names_the_option() {
+        assert!(Error::NoDataDir.to_string().ends_with("use --archive-dir"));
+    }
+
+    #[test]
+    fn file_errors_name_the_file() {
+        let path = Path::new("/nonexistent/repos.txt");
+        let error = std::fs::read(path).file_context(path).unwrap_err();
+        assert!(matches!(error, Error::File(ref p, _) if p == path));
+        assert!(error.to_string().starts_with("/nonexistent/repos.txt: "));
+    }
+}
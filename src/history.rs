@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::error::{Error, FileContext, Result};
+use crate::index::{cache_path, create_parent_dir};
+use crate::path_util::{is_within, rebase_path};
+use chrono::Utc;
+
+const HISTORY_FILE: &str = "history.txt";
+const QUERIES_FILE: &str = "queries.txt";
+/// Maximal number of remembered queries.
+const MAX_QUERIES: usize = 100;
+/// How long `save` waits for another process to finish writing the history.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Age after which a lock file is considered left over by a crashed process.
+/// Saving takes a fraction of this.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(30);
+
+/// Visit statistics of a single repository.
+#[derive(Clone, Copy)]
+pub struct Visits {
+    /// Number of recorded visits.
+    pub count: u32,
+    /// Unix timestamp of the most recent visit.
+    pub last_visit: i64,
+}
+
+impl Visits {
+    /// Combines visit count and recency into a single ranking value.
+    ///
+    /// Recent visits are weighted higher, similar to `z` and `autojump`.
+    pub fn frecency(&self, now: i64) -> f64 {
+        let age_h = (now - self.last_visit) / 3600;
+        let weight = if age_h < 1 {
+            4.0
+        } else if age_h < 24 {
+            2.0
+        } else if age_h < 24 * 7 {
+            0.5
+        } else {
+            0.25
+        };
+        self.count as f64 * weight
+    }
+}
+
+/// A modification of the history which is not saved yet.
+#[derive(Clone)]
+enum Change {
+    Visit(String, i64),
+    Move(String, String),
+    Remove(String),
+}
+
+/// Visit history shared by the picker and the query server.
+pub struct History {
+    /// Path of the history file.
+    ///
+    /// Default: `history.txt` in the user's XDG cache directory.
+    pub history_file: PathBuf,
+    visits: HashMap<String, Visits>,
+    /// Changes since loading, replayed onto the file when saving.
+    changes: Vec<Change>,
+}
+
+impl History {
+    /// Reads the history file. A missing file yields an empty history.
+    pub fn load() -> Result<History> {
+        Ok(History::load_file(&cache_path(HISTORY_FILE)?))
+    }
+
+    /// Reads the history file at `history_file`.
+    pub fn load_file(history_file: &Path) -> History {
+        History {
+            history_file: history_file.to_path_buf(),
+            visits: read_visits(history_file),
+            changes: Vec::new(),
+        }
+    }
+
+    /// Writes the history file.
+    ///
+    /// Other processes may have saved visits since loading, so the file is
+    /// read again and the changes of this history are applied to it, while
+    /// holding a lock file.
+    pub fn save(&mut self) -> Result<()> {
+        create_parent_dir(&self.history_file)?;
+        let _lock = FileLock::acquire(&self.history_file)?;
+        let mut visits = read_visits(&self.history_file);
+        for change in self.changes.drain(..) {
+            apply(&mut visits, change);
+        }
+        self.visits = visits;
+        // Readers never see a partially written file
+        let file = &self.history_file;
+        let mut tmp = file.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut f = File::create(&tmp).file_context(&tmp)?;
+        for (path, v) in self.visits.iter() {
+            writeln!(f, "{}\t{}\t{}", v.count, v.last_visit, path).file_context(&tmp)?;
+        }
+        fs::rename(&tmp, file).file_context(file)
+    }
+
+    /// Registers a visit of `repo` now.
+    pub fn record_visit(&mut self, repo: &str) {
+        self.change(Change::Visit(repo.to_string(), Utc::now().timestamp()));
+    }
+
+    /// Moves the visits of `from`, and of the repos inside it, to `to`.
+    pub fn move_repo(&mut self, from: &str, to: &str) {
+        self.change(Change::Move(from.to_string(), to.to_string()));
+    }
+
+    /// Removes the visits of `repo` and of the repos inside it.
+    pub fn remove_repo(&mut self, repo: &str) {
+        self.change(Change::Remove(repo.to_string()));
+    }
+
+    fn change(&mut self, change: Change) {
+        self.changes.push(change.clone());
+        apply(&mut self.visits, change);
+    }
+
+    /// Returns the visit statistics of `repo`, if it was ever visited.
+    pub fn visits(&self, repo: &str) -> Option<Visits> {
+        self.visits.get(repo).copied()
+    }
+
+    /// Returns the frecency of `repo`, 0 for repos never visited.
+    pub fn frecency(&self, repo: &str) -> f64 {
+        let now = Utc::now().timestamp();
+        self.visits(repo).map(|v| v.frecency(now)).unwrap_or(0.0)
+    }
+}
+
+/// Applies a change to the visits.
+fn apply(visits: &mut HashMap<String, Visits>, change: Change) {
+    match change {
+        Change::Visit(repo, time) => {
+            let v = visits.entry(repo).or_insert(Visits {
+                count: 0,
+                last_visit: time,
+            });
+            v.count += 1;
+            v.last_visit = v.last_visit.max(time);
+        }
+        Change::Move(from, to) => {
+            let moved = visits
+                .keys()
+                .filter_map(|path| Some((path.clone(), rebase_path(path, &from, &to)?)))
+                .collect::<Vec<_>>();
+            for (old, new) in moved {
+                if let Some(v) = visits.remove(&old) {
+                    visits.insert(new, v);
+                }
+            }
+        }
+        Change::Remove(repo) => visits.retain(|path, _| !is_within(path, &repo)),
+    }
+}
+
+/// Reads the visits of the history file, if it exists.
+fn read_visits(history_file: &Path) -> HashMap<String, Visits> {
+    let mut visits = HashMap::new();
+    if let Ok(f) = File::open(history_file) {
+        let reader = BufReader::new(f);
+        for line in reader.lines().map_while(io::Result::ok) {
+            // <count>\t<last_visit>\t<path>
+            let mut fields = line.splitn(3, '\t');
+            if let (Some(count), Some(last_visit), Some(path)) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                if let (Ok(count), Ok(last_visit)) = (count.parse(), last_visit.parse()) {
+                    visits.insert(path.to_string(), Visits { count, last_visit });
+                }
+            }
+        }
+    }
+    visits
+}
+
+/// Exclusive lock of a file, held by creating `<file>.lock` containing the
+/// process ID of the owner.
+struct FileLock(PathBuf);
+
+impl FileLock {
+    /// Waits until no other process holds the lock of `file`. Locks of
+    /// processes which are gone, or older than `STALE_LOCK_AGE`, are left
+    /// over by crashed processes and taken over. Fails if the lock is still
+    /// held after `LOCK_TIMEOUT`.
+    fn acquire(file: &Path) -> Result<FileLock> {
+        let mut lock = file.as_os_str().to_owned();
+        lock.push(".lock");
+        let lock = PathBuf::from(lock);
+        let start = SystemTime::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(mut f) => {
+                    let lock = FileLock(lock);
+                    write!(f, "{}", std::process::id()).file_context(&lock.0)?;
+                    return Ok(lock);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if is_abandoned(&lock) {
+                        let _ = fs::remove_file(&lock);
+                    } else if start.elapsed().unwrap_or_default() > LOCK_TIMEOUT {
+                        return Err(Error::Locked(lock));
+                    } else {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
+                Err(e) => return Err(e).file_context(&lock),
+            }
+        }
+    }
+}
+
+/// Returns `true` if the owner of the `lock` file is gone or the lock is
+/// older than `STALE_LOCK_AGE`.
+fn is_abandoned(lock: &Path) -> bool {
+    let stale = fs::metadata(lock)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|time| time.elapsed().ok())
+        .is_some_and(|age| age > STALE_LOCK_AGE);
+    let owner = fs::read_to_string(lock)
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok());
+    stale || owner.is_some_and(|pid| !is_running(pid))
+}
+
+/// Returns `true` if the process `pid` is running, or if that can't be told.
+fn is_running(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        true
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Returns the queries of previous selections, oldest first.
+pub fn load_queries() -> Result<Vec<String>> {
+    match File::open(cache_path(QUERIES_FILE)?) {
+        Ok(f) => Ok(BufReader::new(f)
+            .lines()
+            .map_while(io::Result::ok)
+            .collect()),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Appends `query` to the remembered queries.
+pub fn save_query(query: &str) -> Result<()> {
+    if query.trim().is_empty() {
+        return Ok(());
+    }
+    let mut queries = load_queries()?;
+    queries.retain(|q| q != query);
+    queries.push(query.to_string());
+    let skip = queries.len().saturating_sub(MAX_QUERIES);
+    let queries_file = cache_path(QUERIES_FILE)?;
+    create_parent_dir(&queries_file)?;
+    let mut f = File::create(&queries_file).file_context(&queries_file)?;
+    for query in queries.iter().skip(skip) {
+        writeln!(f, "{}", query).file_context(&queries_file)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frecency_prefers_recent_visits() {
+        let now = 1_000_000;
+        let visits = |count, hours_ago: i64| Visits {
+            count,
+            last_visit: now - hours_ago * 3600,
+        };
+        assert_eq!(visits(1, 0).frecency(now), 4.0);
+        assert_eq!(visits(1, 5).frecency(now), 2.0);
+        assert_eq!(visits(4, 48).frecency(now), 2.0);
+        assert_eq!(visits(4, 24 * 30).frecency(now), 1.0);
+    }
+
+    #[test]
+    fn save_keeps_visits_saved_by_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("cache").join("history.txt");
+        let mut first = History::load_file(&file);
+        let mut second = History::load_file(&file);
+        first.record_visit("/repos/a");
+        second.record_visit("/repos/a");
+        second.record_visit("/repos/b");
+        first.save().unwrap();
+        second.save().unwrap();
+        let history = History::load_file(&file);
+        assert_eq!(history.visits("/repos/a").map(|v| v.count), Some(2));
+        assert_eq!(history.visits("/repos/b").map(|v| v.count), Some(1));
+        // The saving history sees the merged visits, too
+        assert_eq!(second.visits("/repos/a").map(|v| v.count), Some(2));
+        assert!(!file.with_extension("txt.lock").exists());
+    }
+
+    #[test]
+    fn moves_are_replayed_on_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("history.txt");
+        let mut other = History::load_file(&file);
+        other.record_visit("/old/api");
+        other.record_visit("/old/api/vendor/lib");
+        other.record_visit("/older");
+        other.save().unwrap();
+        let mut history = History::load_file(&dir.path().join("missing.txt"));
+        history.history_file = file.clone();
+        history.move_repo("/old/api", "/new/api");
+        history.save().unwrap();
+        let history = History::load_file(&file);
+        assert!(history.visits("/old/api").is_none());
+        assert!(history.visits("/new/api").is_some());
+        assert!(history.visits("/new/api/vendor/lib").is_some());
+        assert!(history.visits("/older").is_some());
+    }
+
+    #[test]
+    fn removals_are_replayed_on_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("history.txt");
+        let mut history = History::load_file(&file);
+        history.remove_repo("/repos/api");
+        let mut other = History::load_file(&file);
+        other.record_visit("/repos/api");
+        other.record_visit("/repos/api/vendor/lib");
+        other.record_visit("/repos/api-docs");
+        other.save().unwrap();
+        history.save().unwrap();
+        let history = History::load_file(&file);
+        assert!(history.visits("/repos/api").is_none());
+        assert!(history.visits("/repos/api/vendor/lib").is_none());
+        assert!(history.visits("/repos/api-docs").is_some());
+    }
+
+    #[test]
+    fn stale_lock_is_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("history.txt");
+        let lock = dir.path().join("history.txt.lock");
+        File::create(&lock)
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * STALE_LOCK_AGE)
+            .unwrap();
+        let mut history = History::load_file(&file);
+        history.record_visit("/repos/a");
+        history.save().unwrap();
+        assert!(!lock.exists());
+        assert!(History::load_file(&file).visits("/repos/a").is_some());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn lock_of_a_finished_process_is_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("history.txt");
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        fs::write(dir.path().join("history.txt.lock"), child.id().to_string()).unwrap();
+        let mut history = History::load_file(&file);
+        history.record_visit("/repos/a");
+        history.save().unwrap();
+        assert!(History::load_file(&file).visits("/repos/a").is_some());
+    }
+
+    #[test]
+    fn held_lock_is_not_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("history.txt");
+        let lock = dir.path().join("history.txt.lock");
+        fs::write(&lock, std::process::id().to_string()).unwrap();
+        let mut history = History::load_file(&file);
+        history.record_visit("/repos/a");
+        assert!(matches!(history.save(), Err(Error::Locked(_))));
+        assert!(lock.exists());
+        fs::remove_file(&lock).unwrap();
+        // The visit is saved with the next save
+        history.save().unwrap();
+        assert!(History::load_file(&file).visits("/repos/a").is_some());
+    }
+}
@@ -1,19 +1,26 @@
-use git2;
-use std::fs::{remove_file, File};
-use std::io::{BufRead, BufReader, Write};
-use std::path::PathBuf;
-
-use crate::path_util::is_hidden;
-use crate::repo::num_hours_since_last_commit;
-use app_dirs::{app_dir, get_app_dir, AppDataType, AppInfo};
+use std::cmp::Ordering;
+use std::fs::{self, remove_file, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::error::{Error, FileContext, Result};
+use crate::history::History;
+use crate::path_util::{display_path, is_hidden, is_within, rebase_path};
+use crate::remote::find_duplicates;
+use crate::repo::{Identity, RepoInfo};
+use crate::tags::Tags;
+use app_dirs::{get_app_dir, AppDataType, AppInfo};
 use dirs::home_dir;
 use walkdir::{DirEntry, WalkDir};
 
-const APP: AppInfo = AppInfo {
+pub(crate) const APP: AppInfo = AppInfo {
     name: "git-shell",
     author: "pka",
 };
-const CACHE_FILE: &'static str = "repos.txt";
+const CACHE_FILE: &str = "repos.txt";
+const PINNED_FILE: &str = "pinned.txt";
 
 type Repo = String;
 
@@ -27,63 +34,146 @@ pub struct Index {
     ///
     /// Default: `repos.txt` in the user's XDG cache directory.
     pub cache_file: PathBuf,
+
+    /// Path of the list of pinned repos.
+    ///
+    /// Default: `pinned.txt` in the user's XDG cache directory.
+    pub pinned_file: PathBuf,
 }
 
 impl Index {
-    pub fn new() -> Index {
+    pub fn new() -> Result<Index> {
         // Find the user's home directory.
-        let homedir = home_dir().expect("Could not determine home directory.");
+        let homedir = home_dir().ok_or(Error::NoHomeDir)?;
         // Set the options that aren't user-configurable.
-        let cache_file = match get_app_dir(AppDataType::UserCache, &APP, "cache") {
-            Ok(mut dir) => {
-                dir.push(CACHE_FILE);
-                dir
-            }
-            Err(_) => panic!("TODO: work without XDG"),
-        };
-        Index {
+        let cache_file = cache_path(CACHE_FILE)?;
+        let pinned_file = cache_path(PINNED_FILE)?;
+        Ok(Index {
             basedir: homedir,
-            cache_file: cache_file,
-        }
+            cache_file,
+            pinned_file,
+        })
     }
 
     /// Returns all known git repos, populating the cache first, if necessary.
-    pub fn get_repos(&mut self) -> Vec<Repo> {
+    pub fn get_repos(&mut self) -> Result<Vec<Repo>> {
         if !self.has_cache() {
             let repos = self.find_repos();
-            self.cache_repos(&repos);
+            self.cache_repos(&repos)?;
         }
-        self.get_cached_repos()
+        Ok(self.get_cached_repos())
     }
 
-    pub fn get_repos_by_last_commit(&mut self) -> Vec<Repo> {
-        let repos = self.get_repos();
-        let mut repos_with_last_commit = repos
+    /// Returns all known git repos, most recently committed to first.
+    pub fn get_repos_by_last_commit(&mut self) -> Result<Vec<RepoInfo>> {
+        let mut repos = self
+            .get_repos()?
             .iter()
-            .enumerate()
-            .map(|(i, p)| {
-                if let Ok(repo) = git2::Repository::open(&p) {
-                    (i, num_hours_since_last_commit(&repo))
-                } else {
-                    (i, i64::max_value())
-                }
-            })
+            .map(|p| RepoInfo::new(p))
             .collect::<Vec<_>>();
-        repos_with_last_commit.sort_by(|a, b| a.1.cmp(&b.1));
-        // return sorted repo list
-        repos_with_last_commit
-            .iter()
-            .map(|(i, _)| repos[*i].clone())
-            .collect()
+        repos.sort_by_key(|r| r.hours_since_last_commit);
+        Ok(repos)
+    }
+
+    /// Returns all known git repos, most frecently visited first.
+    ///
+    /// Repos with equal frecency are ordered by the age of their last commit.
+    pub fn get_repos_by_frecency(&mut self, history: &History) -> Result<Vec<RepoInfo>> {
+        let mut repos = self.get_repos_by_last_commit()?;
+        rank_by_frecency(&mut repos, history);
+        Ok(repos)
+    }
+
+    /// Returns all known git repos ranked for display, with their tags.
+    pub fn get_ranked_repos(&mut self, config: &Config) -> Result<Vec<RepoInfo>> {
+        let mut repos = self.get_repos_by_frecency(&History::load()?)?;
+        Tags::load()?.apply(&mut repos, config);
+        let pinned = self.get_pinned();
+        let identity = Arc::new(Identity::load(config));
+        for repo in repos.iter_mut() {
+            repo.identity = Some(identity.clone());
+            repo.pinned = pinned.contains(&repo.path);
+            repo.display = display_path(&repo.path, &self.basedir);
+        }
+        for group in find_duplicates(&repos) {
+            for &idx in &group.repos {
+                repos[idx].clones = group.repos.len();
+            }
+        }
+        Ok(repos)
+    }
+
+    /// Returns the pinned repos.
+    pub fn get_pinned(&self) -> Vec<Repo> {
+        read_lines(&self.pinned_file)
+    }
+
+    /// Pins `repo` if it isn't pinned yet, unpins it otherwise.
+    ///
+    /// Returns `true` if the repo is pinned now.
+    pub fn toggle_pinned(&mut self, repo: &str) -> Result<bool> {
+        let mut pinned = self.get_pinned();
+        let was_pinned = pinned.iter().any(|p| p == repo);
+        if was_pinned {
+            pinned.retain(|p| p != repo);
+        } else {
+            pinned.push(repo.to_string());
+        }
+        create_parent_dir(&self.pinned_file)?;
+        write_lines(&self.pinned_file, &pinned)?;
+        Ok(!was_pinned)
+    }
+
+    /// Adds a new repo to the cache, without a re-scan.
+    pub fn add_repo(&mut self, repo: &str) -> Result<()> {
+        let mut repos = self.get_repos()?;
+        if !repos.iter().any(|r| r == repo) {
+            repos.push(repo.to_string());
+            self.cache_repos(&repos)?;
+        }
+        Ok(())
+    }
+
+    /// Removes a deleted repo, and the repos inside it, from the cache and
+    /// the pinned repos, without a re-scan.
+    pub fn remove_repo(&mut self, repo: &str) -> Result<()> {
+        let mut repos = self.get_repos()?;
+        repos.retain(|r| !is_within(r, repo));
+        self.cache_repos(&repos)?;
+        let mut pinned = self.get_pinned();
+        if pinned.iter().any(|p| is_within(p, repo)) {
+            pinned.retain(|p| !is_within(p, repo));
+            write_lines(&self.pinned_file, &pinned)?;
+        }
+        Ok(())
+    }
+
+    /// Updates the paths of a repo moved from `from` to `to`, and of the
+    /// repos inside it, in the cache and the pinned repos.
+    pub fn move_repo(&mut self, from: &str, to: &str) -> Result<()> {
+        let rebase = |repos: Vec<Repo>| {
+            repos
+                .into_iter()
+                .map(|repo| rebase_path(&repo, from, to).unwrap_or(repo))
+                .collect::<Vec<_>>()
+        };
+        let repos = rebase(self.get_repos()?);
+        self.cache_repos(&repos)?;
+        let pinned = self.get_pinned();
+        if pinned.iter().any(|p| rebase_path(p, from, to).is_some()) {
+            write_lines(&self.pinned_file, &rebase(pinned))?;
+        }
+        Ok(())
     }
 
     /// Clears the cache of known git repos, forcing a re-scan on the next
     /// `get_repos()` call.
     #[allow(dead_code)]
-    pub fn clear_cache(&mut self) {
+    pub fn clear_cache(&mut self) -> Result<()> {
         if self.has_cache() {
-            remove_file(&self.cache_file).expect("Failed to delete cache file.");
+            remove_file(&self.cache_file).file_context(&self.cache_file)?;
         }
+        Ok(())
     }
 
     /// Returns boolean indicating if the cache file exists.
@@ -99,7 +189,7 @@ impl Index {
     /// Walks the configured base directory, looking for git repos.
     fn find_repos(&self) -> Vec<Repo> {
         let mut repos = Vec::new();
-        println!(
+        eprintln!(
             "Scanning for git repos under {}; this may take a while...",
             self.basedir.display()
         );
@@ -108,14 +198,13 @@ impl Index {
             .same_file_system(true)
             .into_iter()
             .filter_entry(|e| self.filter_dirs(e))
+            .flatten()
         {
-            if let Ok(entry) = entry {
-                if entry.file_name() == ".git" {
-                    let parent_path = entry.path().parent().expect("Could not determine parent.");
-                    if let Some(path) = parent_path.to_str() {
-                        if git2::Repository::open(&path).is_ok() {
-                            repos.push(path.to_string());
-                        }
+            if entry.file_name() == ".git" {
+                let parent_path = entry.path().parent().expect("Could not determine parent.");
+                if let Some(path) = parent_path.to_str() {
+                    if git2::Repository::open(path).is_ok() {
+                        repos.push(path.to_string());
                     }
                 }
             }
@@ -124,37 +213,147 @@ impl Index {
     }
 
     /// Writes the given repo paths to the cache file.
-    fn cache_repos(&self, repos: &Vec<Repo>) {
-        if !self.cache_file.as_path().exists() {
-            // Try to create the cache directory if the cache *file* doesn't
-            // exist; app_dir() handles an existing directory just fine.
-            match app_dir(AppDataType::UserCache, &APP, "cache") {
-                Ok(_) => (),
-                Err(e) => panic!("Could not create cache directory: {}", e),
-            }
-        }
-        let mut f = File::create(&self.cache_file).expect("Could not create cache file.");
-        for repo in repos.iter() {
-            match writeln!(f, "{}", repo) {
-                Ok(_) => (),
-                Err(e) => panic!("Problem writing cache file: {}", e),
-            }
-        }
+    fn cache_repos(&self, repos: &[Repo]) -> Result<()> {
+        create_parent_dir(&self.cache_file)?;
+        write_lines(&self.cache_file, repos)
     }
 
     /// Returns the list of repos found in the cache file.
     fn get_cached_repos(&self) -> Vec<Repo> {
-        let mut repos = Vec::new();
-        if self.cache_file.exists() {
-            let f = File::open(&self.cache_file).expect("Could not open cache file.");
-            let reader = BufReader::new(f);
-            for line in reader.lines() {
-                match line {
-                    Ok(repo_path) => repos.push(repo_path),
-                    Err(_) => (), // TODO: handle errors
-                }
-            }
+        read_lines(&self.cache_file)
+    }
+}
+
+/// Sorts `repos` by frecency, most frecently visited first. Repos with equal
+/// frecency are ordered by the age of their last commit.
+pub fn rank_by_frecency(repos: &mut [RepoInfo], history: &History) {
+    repos.sort_by_key(|r| r.hours_since_last_commit);
+    // sort_by is stable, so the last commit order is kept for ties
+    repos.sort_by(|a, b| {
+        history
+            .frecency(&b.path)
+            .partial_cmp(&history.frecency(&a.path))
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+/// Writes one repo path per line to the given file.
+fn write_lines(file: &Path, repos: &[Repo]) -> Result<()> {
+    let mut f = File::create(file).file_context(file)?;
+    for repo in repos.iter() {
+        writeln!(f, "{}", repo).file_context(file)?;
+    }
+    Ok(())
+}
+
+/// Reads one repo path per line from the given file, if it exists.
+fn read_lines(file: &Path) -> Vec<Repo> {
+    let mut repos = Vec::new();
+    if let Ok(f) = File::open(file) {
+        let reader = BufReader::new(f);
+        for repo_path in reader.lines().map_while(io::Result::ok) {
+            repos.push(repo_path);
         }
-        repos
+    }
+    repos
+}
+
+/// Returns the user's XDG cache directory.
+fn cache_dir() -> Result<PathBuf> {
+    get_app_dir(AppDataType::UserCache, &APP, "cache").map_err(|_| Error::NoCacheDir)
+}
+
+/// Returns the path of `file_name` in the cache directory.
+pub(crate) fn cache_path(file_name: &str) -> Result<PathBuf> {
+    Ok(cache_dir()?.join(file_name))
+}
+
+/// Creates the directory of `file`, e.g. the cache directory, if it doesn't
+/// exist yet.
+pub(crate) fn create_parent_dir(file: &Path) -> Result<()> {
+    match file.parent() {
+        Some(dir) if !dir.exists() => fs::create_dir_all(dir).file_context(dir),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an index of `repos` with its files in `dir`.
+    fn index(dir: &Path, repos: &[&str]) -> Index {
+        let index = Index {
+            basedir: dir.to_path_buf(),
+            cache_file: dir.join("cache").join(CACHE_FILE),
+            pinned_file: dir.join("cache").join(PINNED_FILE),
+        };
+        let repos = repos.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        index.cache_repos(&repos).unwrap();
+        index
+    }
+
+    #[test]
+    fn toggle_pinned() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = index(dir.path(), &["/work/api", "/work/web"]);
+        assert!(index.toggle_pinned("/work/web").unwrap());
+        assert!(index.toggle_pinned("/work/api").unwrap());
+        assert_eq!(index.get_pinned(), vec!["/work/web", "/work/api"]);
+        assert!(!index.toggle_pinned("/work/web").unwrap());
+        assert_eq!(index.get_pinned(), vec!["/work/api"]);
+    }
+
+    #[test]
+    fn add_and_remove_repos() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = index(dir.path(), &["/work/api"]);
+        index.add_repo("/work/web").unwrap();
+        index.add_repo("/work/api").unwrap();
+        assert_eq!(index.get_repos().unwrap(), vec!["/work/api", "/work/web"]);
+        index.add_repo("/work/api/vendor/lib").unwrap();
+        index.add_repo("/work/api-docs").unwrap();
+        index.toggle_pinned("/work/api/vendor/lib").unwrap();
+        index.remove_repo("/work/api").unwrap();
+        // Repos inside are removed, too
+        assert_eq!(
+            index.get_repos().unwrap(),
+            vec!["/work/web", "/work/api-docs"]
+        );
+        assert!(index.get_pinned().is_empty());
+    }
+
+    #[test]
+    fn move_repo_moves_nested_and_pinned_repos() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = index(
+            dir.path(),
+            &["/old/api", "/old/api/vendor/lib", "/old/api-docs"],
+        );
+        index.toggle_pinned("/old/api/vendor/lib").unwrap();
+        index.move_repo("/old/api", "/new/api").unwrap();
+        assert_eq!(
+            index.get_repos().unwrap(),
+            vec!["/new/api", "/new/api/vendor/lib", "/old/api-docs"]
+        );
+        assert_eq!(index.get_pinned(), vec!["/new/api/vendor/lib"]);
+    }
+
+    #[test]
+    fn frecency_before_last_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut repos = [("/old", 500), ("/visited", 100), ("/recent", 1)]
+            .iter()
+            .map(|&(path, hours)| {
+                let mut repo = RepoInfo::new(path);
+                repo.hours_since_last_commit = hours;
+                repo
+            })
+            .collect::<Vec<_>>();
+        let mut history = History::load_file(&dir.path().join("history.txt"));
+        history.record_visit("/visited");
+        rank_by_frecency(&mut repos, &history);
+        let paths = repos.iter().map(|r| r.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["/visited", "/recent", "/old"]);
     }
 }
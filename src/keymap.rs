@@ -0,0 +1,855 @@
+//! Mapping of key presses to named actions.
+//!
+//! Keys are written like `ctrl-p`, `alt-v`, `G`, `enter` or `pagedown`.
+//! Sequences of keys are separated by spaces, e.g. `g g`.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::config::KeysConfig;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Named action of the picker.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    First,
+    Last,
+    Select,
+    Quit,
+    CursorLeft,
+    CursorRight,
+    CursorStart,
+    CursorEnd,
+    Backspace,
+    Delete,
+    DeleteWord,
+    KillLine,
+    KillToEnd,
+    ClearQuery,
+    RecallQuery,
+    /// Switch from normal to insert mode to edit the query.
+    Search,
+    /// Switch from insert to normal mode.
+    NormalMode,
+    Pull,
+    Status,
+    TogglePin,
+    /// Check whether the selected repository can be deleted without losing
+    /// work.
+    CheckDelete,
+    /// Group the list by the host or owner of the remote repository, or
+    /// stop grouping.
+    Group,
+    /// Show the key bindings, bound to `f1` and to `?` in the normal mode
+    /// of the vim preset.
+    Help,
+    /// Run a user-defined shell command in the selected repository.
+    Run(String),
+}
+
+const ACTION_NAMES: &[(&str, Action)] = &[
+    ("up", Action::Up),
+    ("down", Action::Down),
+    ("page-up", Action::PageUp),
+    ("page-down", Action::PageDown),
+    ("first", Action::First),
+    ("last", Action::Last),
+    ("select", Action::Select),
+    ("quit", Action::Quit),
+    ("cursor-left", Action::CursorLeft),
+    ("cursor-right", Action::CursorRight),
+    ("cursor-start", Action::CursorStart),
+    ("cursor-end", Action::CursorEnd),
+    ("backspace", Action::Backspace),
+    ("delete", Action::Delete),
+    ("delete-word", Action::DeleteWord),
+    ("kill-line", Action::KillLine),
+    ("kill-to-end", Action::KillToEnd),
+    ("clear-query", Action::ClearQuery),
+    ("recall-query", Action::RecallQuery),
+    ("search", Action::Search),
+    ("normal-mode", Action::NormalMode),
+    ("pull", Action::Pull),
+    ("status", Action::Status),
+    ("toggle-pin", Action::TogglePin),
+    ("check-delete", Action::CheckDelete),
+    ("group", Action::Group),
+    ("help", Action::Help),
+];
+
+impl Action {
+    fn parse(name: &str) -> Result<Action, String> {
+        if let Some(cmd) = name.strip_prefix("run:") {
+            return Ok(Action::Run(cmd.trim().to_string()));
+        }
+        ACTION_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, action)| action.clone())
+            .ok_or_else(|| format!("unknown action `{}`", name))
+    }
+
+    /// Returns `true` for actions on the selected repository, which are
+    /// executed by the caller of `Select`.
+    pub fn is_command(&self) -> bool {
+        matches!(
+            self,
+            Action::Pull
+                | Action::Status
+                | Action::TogglePin
+                | Action::CheckDelete
+                | Action::Group
+                | Action::Run(_)
+        )
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Action::Run(cmd) = self {
+            return write!(f, "run:{}", cmd);
+        }
+        let name = ACTION_NAMES
+            .iter()
+            .find(|(_, action)| action == self)
+            .map(|(n, _)| *n)
+            .unwrap_or("?");
+        write!(f, "{}", name)
+    }
+}
+
+/// A single key press.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyPress {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("enter", KeyCode::Enter),
+    ("esc", KeyCode::Esc),
+    ("tab", KeyCode::Tab),
+    ("backtab", KeyCode::BackTab),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("space", KeyCode::Char(' ')),
+];
+
+impl KeyPress {
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> KeyPress {
+        let mut key = KeyPress { code, modifiers };
+        // Shift is part of the character, e.g. `G`
+        if let KeyCode::Char(_) = code {
+            key.modifiers.remove(KeyModifiers::SHIFT);
+        }
+        key
+    }
+
+    pub fn from_event(event: &KeyEvent) -> KeyPress {
+        KeyPress::new(event.code, event.modifiers)
+    }
+
+    fn parse(name: &str) -> Result<KeyPress, String> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = name;
+        loop {
+            let lower = rest.to_ascii_lowercase();
+            if rest.len() > 1 && lower.starts_with("ctrl-") {
+                modifiers |= KeyModifiers::CONTROL;
+                rest = &rest[5..];
+            } else if rest.len() > 1 && lower.starts_with("alt-") {
+                modifiers |= KeyModifiers::ALT;
+                rest = &rest[4..];
+            } else {
+                break;
+            }
+        }
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(ch), None) if modifiers.contains(KeyModifiers::CONTROL) => {
+                KeyCode::Char(ch.to_ascii_lowercase())
+            }
+            (Some(ch), None) => KeyCode::Char(ch),
+            _ => {
+                let lower = rest.to_ascii_lowercase();
+                if let Some(code) = KEY_NAMES.iter().find(|(n, _)| *n == lower) {
+                    code.1
+                } else if let Some(Ok(n)) = lower.strip_prefix('f').map(str::parse) {
+                    KeyCode::F(n)
+                } else {
+                    return Err(format!("unknown key `{}`", name));
+                }
+            }
+        };
+        Ok(KeyPress::new(code, modifiers))
+    }
+
+    /// Returns the character to insert into the query, if any.
+    pub fn text(&self) -> Option<char> {
+        match self.code {
+            KeyCode::Char(ch) if self.modifiers.is_empty() => Some(ch),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(ch) => write!(f, "{}", ch),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            code => {
+                let name = KEY_NAMES
+                    .iter()
+                    .find(|(_, c)| *c == code)
+                    .map(|(n, _)| *n)
+                    .unwrap_or("?");
+                write!(f, "{}", name)
+            }
+        }
+    }
+}
+
+/// Formats a key sequence like `g g`.
+pub fn format_keys(keys: &[KeyPress]) -> String {
+    keys.iter()
+        .map(|k| k.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_keys(name: &str) -> Result<Vec<KeyPress>, String> {
+    let keys = name
+        .split_whitespace()
+        .map(KeyPress::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        Err("empty key".to_string())
+    } else {
+        Ok(keys)
+    }
+}
+
+/// Input mode of the picker.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Keys not bound to an action edit the query.
+    Insert,
+    /// Keys are only used for actions.
+    Normal,
+}
+
+/// Result of looking up a key sequence.
+pub enum Lookup<'a> {
+    Action(&'a Action),
+    /// The keys are the start of a longer sequence.
+    Pending,
+    Unbound,
+}
+
+pub type Bindings = Vec<(Vec<KeyPress>, Action)>;
+
+pub struct Keymap {
+    pub insert: Bindings,
+    /// Bindings of the normal mode. Keymaps without a normal mode always
+    /// edit the query.
+    pub normal: Option<Bindings>,
+}
+
+const DEFAULT_INSERT: &[(&str, &str)] = &[
+    ("up", "up"),
+    ("down", "down"),
+    ("pagedown", "page-down"),
+    ("pageup", "page-up"),
+    ("tab", "page-down"),
+    ("backtab", "page-up"),
+    ("home", "first"),
+    ("end", "last"),
+    ("enter", "select"),
+    ("esc", "quit"),
+    ("ctrl-c", "quit"),
+    ("left", "cursor-left"),
+    ("right", "cursor-right"),
+    ("ctrl-a", "cursor-start"),
+    ("ctrl-e", "cursor-end"),
+    ("backspace", "backspace"),
+    // Sent by the backspace key of some terminals
+    ("ctrl-h", "backspace"),
+    ("delete", "delete"),
+    ("ctrl-w", "delete-word"),
+    ("ctrl-u", "kill-line"),
+    ("ctrl-r", "recall-query"),
+    ("ctrl-p", "pull"),
+    ("ctrl-s", "status"),
+    ("ctrl-f", "toggle-pin"),
+    ("ctrl-x", "check-delete"),
+    ("ctrl-g", "group"),
+    ("f1", "help"),
+];
+
+const EMACS_INSERT: &[(&str, &str)] = &[
+    ("up", "up"),
+    ("down", "down"),
+    ("ctrl-p", "up"),
+    ("ctrl-n", "down"),
+    ("ctrl-v", "page-down"),
+    ("alt-v", "page-up"),
+    ("pagedown", "page-down"),
+    ("pageup", "page-up"),
+    ("alt-<", "first"),
+    ("alt->", "last"),
+    ("home", "first"),
+    ("end", "last"),
+    ("enter", "select"),
+    ("esc", "quit"),
+    ("ctrl-g", "quit"),
+    ("ctrl-c", "quit"),
+    ("left", "cursor-left"),
+    ("right", "cursor-right"),
+    ("ctrl-b", "cursor-left"),
+    ("ctrl-f", "cursor-right"),
+    ("ctrl-a", "cursor-start"),
+    ("ctrl-e", "cursor-end"),
+    ("backspace", "backspace"),
+    ("ctrl-h", "backspace"),
+    ("ctrl-d", "delete"),
+    ("alt-backspace", "delete-word"),
+    ("ctrl-w", "delete-word"),
+    ("ctrl-k", "kill-to-end"),
+    ("ctrl-u", "kill-line"),
+    ("ctrl-r", "recall-query"),
+    ("alt-p", "pull"),
+    ("alt-s", "status"),
+    ("alt-t", "toggle-pin"),
+    ("alt-x", "check-delete"),
+    ("alt-g", "group"),
+    ("f1", "help"),
+];
+
+const VIM_NORMAL: &[(&str, &str)] = &[
+    ("j", "down"),
+    ("k", "up"),
+    ("down", "down"),
+    ("up", "up"),
+    ("ctrl-f", "page-down"),
+    ("ctrl-b", "page-up"),
+    ("pagedown", "page-down"),
+    ("pageup", "page-up"),
+    ("g g", "first"),
+    ("G", "last"),
+    ("home", "first"),
+    ("end", "last"),
+    ("/", "search"),
+    ("i", "search"),
+    ("enter", "select"),
+    ("q", "quit"),
+    ("esc", "quit"),
+    ("ctrl-c", "quit"),
+    ("p", "pull"),
+    ("s", "status"),
+    ("m", "toggle-pin"),
+    ("x", "check-delete"),
+    ("ctrl-g", "group"),
+    ("?", "help"),
+    ("f1", "help"),
+];
+
+const VIM_INSERT: &[(&str, &str)] = &[
+    ("up", "up"),
+    ("down", "down"),
+    ("ctrl-p", "up"),
+    ("ctrl-n", "down"),
+    ("pagedown", "page-down"),
+    ("pageup", "page-up"),
+    ("enter", "select"),
+    ("esc", "normal-mode"),
+    ("ctrl-c", "quit"),
+    ("left", "cursor-left"),
+    ("right", "cursor-right"),
+    ("home", "first"),
+    ("end", "last"),
+    ("backspace", "backspace"),
+    ("delete", "delete"),
+    ("ctrl-w", "delete-word"),
+    ("ctrl-u", "kill-line"),
+    ("ctrl-r", "recall-query"),
+    ("f1", "help"),
+];
+
+/// Parses the bindings of a preset, adding invalid entries to `errors`.
+fn preset_bindings(table: &[(&str, &str)], errors: &mut Vec<String>) -> Bindings {
+    let mut bindings = Vec::new();
+    for (keys, action) in table.iter() {
+        match (parse_keys(keys), Action::parse(action)) {
+            (Ok(keys), Ok(action)) => bindings.push((keys, action)),
+            (Err(e), _) | (_, Err(e)) => errors.push(format!("preset: {}", e)),
+        }
+    }
+    bindings
+}
+
+/// Merges user bindings into `bindings`, replacing bindings of the same
+/// keys. The action `none` removes a binding.
+fn merge(bindings: &mut Bindings, user: &BTreeMap<String, String>) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    let mut seen: Vec<(Vec<KeyPress>, &str)> = Vec::new();
+    for (name, action) in user.iter() {
+        let keys = match parse_keys(name) {
+            Ok(keys) => keys,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        if let Some((_, other)) = seen.iter().find(|(k, _)| *k == keys) {
+            errors.push(format!(
+                "`{}` and `{}` are the same key `{}`",
+                name,
+                other,
+                format_keys(&keys)
+            ));
+            continue;
+        }
+        seen.push((keys.clone(), name));
+        bindings.retain(|(k, _)| *k != keys);
+        if action == "none" {
+            continue;
+        }
+        match Action::parse(action) {
+            Ok(action) => bindings.push((keys, action)),
+            Err(e) => errors.push(e),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Reports key sequences which can never be reached, because a shorter
+/// sequence starting the same way is bound, too.
+fn conflicts(bindings: &Bindings, mode: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (keys, action) in bindings.iter() {
+        for (other_keys, other_action) in bindings.iter() {
+            if other_keys.len() < keys.len() && keys.starts_with(other_keys) {
+                errors.push(format!(
+                    "{} mode: `{}` ({}) hides `{}` ({})",
+                    mode,
+                    format_keys(other_keys),
+                    other_action,
+                    format_keys(keys),
+                    action
+                ));
+            }
+        }
+    }
+    errors
+}
+
+impl Keymap {
+    /// Builds the keymap from a preset and the user's bindings.
+    ///
+    /// Returns a description of each conflict or invalid binding.
+    pub fn load(config: &KeysConfig) -> Result<Keymap, Vec<String>> {
+        let mut errors = Vec::new();
+        let mut keymap = match config.preset.as_deref().unwrap_or("default") {
+            "default" => Keymap {
+                insert: preset_bindings(DEFAULT_INSERT, &mut errors),
+                normal: None,
+            },
+            "emacs" => Keymap {
+                insert: preset_bindings(EMACS_INSERT, &mut errors),
+                normal: None,
+            },
+            "vim" => Keymap {
+                insert: preset_bindings(VIM_INSERT, &mut errors),
+                normal: Some(preset_bindings(VIM_NORMAL, &mut errors)),
+            },
+            preset => return Err(vec![format!("unknown preset `{}`", preset)]),
+        };
+        if let Err(e) = merge(&mut keymap.insert, &config.insert) {
+            errors.extend(e);
+        }
+        match keymap.normal {
+            Some(ref mut normal) => {
+                if let Err(e) = merge(normal, &config.normal) {
+                    errors.extend(e);
+                }
+            }
+            None if !config.normal.is_empty() => {
+                errors.push("normal mode bindings require the `vim` preset".to_string());
+            }
+            None => {}
+        }
+        errors.extend(conflicts(&keymap.insert, "insert"));
+        if let Some(ref normal) = keymap.normal {
+            errors.extend(conflicts(normal, "normal"));
+        }
+        if errors.is_empty() {
+            Ok(keymap)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Returns the mode the picker starts in.
+    pub fn initial_mode(&self) -> Mode {
+        if self.normal.is_some() {
+            Mode::Normal
+        } else {
+            Mode::Insert
+        }
+    }
+
+    pub fn bindings(&self, mode: Mode) -> &Bindings {
+        match (mode, &self.normal) {
+            (Mode::Normal, Some(normal)) => normal,
+            _ => &self.insert,
+        }
+    }
+
+    /// Returns the bound actions with their keys, e.g. `("j, down", "down")`,
+    /// in the order of the bindings.
+    pub fn help(&self, mode: Mode) -> Vec<(String, String)> {
+        let mut entries: Vec<(Vec<String>, &Action)> = Vec::new();
+        for (keys, action) in self.bindings(mode).iter() {
+            let keys = format_keys(keys);
+            match entries.iter_mut().find(|(_, a)| *a == action) {
+                Some((all_keys, _)) => all_keys.push(keys),
+                None => entries.push((vec![keys], action)),
+            }
+        }
+        entries
+            .into_iter()
+            .map(|(keys, action)| (keys.join(", "), action.to_string()))
+            .collect()
+    }
+
+    /// Returns the first key sequence bound to `action`.
+    pub fn key_for(&self, mode: Mode, action: &Action) -> Option<String> {
+        self.bindings(mode)
+            .iter()
+            .find(|(_, a)| a == action)
+            .map(|(keys, _)| format_keys(keys))
+    }
+
+    /// Looks up the action bound to the key sequence `keys`.
+    pub fn lookup(&self, mode: Mode, keys: &[KeyPress]) -> Lookup<'_> {
+        let mut pending = false;
+        for (bound, action) in self.bindings(mode).iter() {
+            if bound.as_slice() == keys {
+                return Lookup::Action(action);
+            }
+            if bound.starts_with(keys) {
+                pending = true;
+            }
+        }
+        if pending {
+            Lookup::Pending
+        } else {
+            Lookup::Unbound
+        }
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        // The presets are checked by the tests
+        Keymap {
+            insert: preset_bindings(DEFAULT_INSERT, &mut Vec::new()),
+            normal: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> KeyPress {
+        KeyPress::parse(name).unwrap()
+    }
+
+    fn keys_config(preset: &str, insert: &[(&str, &str)], normal: &[(&str, &str)]) -> KeysConfig {
+        let map = |bindings: &[(&str, &str)]| {
+            bindings
+                .iter()
+                .map(|(k, a)| (k.to_string(), a.to_string()))
+                .collect()
+        };
+        KeysConfig {
+            preset: Some(preset.to_string()),
+            insert: map(insert),
+            normal: map(normal),
+        }
+    }
+
+    fn action(keymap: &Keymap, mode: Mode, keys: &str) -> Option<Action> {
+        match keymap.lookup(mode, &parse_keys(keys).unwrap()) {
+            Lookup::Action(action) => Some(action.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parse_keys_and_modifiers() {
+        assert_eq!(
+            key("x"),
+            KeyPress::new(KeyCode::Char('x'), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            key("G"),
+            KeyPress::new(KeyCode::Char('G'), KeyModifiers::SHIFT)
+        );
+        assert_eq!(
+            key("ctrl-P"),
+            KeyPress::new(KeyCode::Char('p'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            key("Ctrl-Alt-v"),
+            KeyPress::new(
+                KeyCode::Char('v'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            )
+        );
+        assert_eq!(
+            key("alt-<"),
+            KeyPress::new(KeyCode::Char('<'), KeyModifiers::ALT)
+        );
+        assert_eq!(
+            key("PageDown"),
+            KeyPress::new(KeyCode::PageDown, KeyModifiers::NONE)
+        );
+        assert_eq!(
+            key("space"),
+            KeyPress::new(KeyCode::Char(' '), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            key("f12"),
+            KeyPress::new(KeyCode::F(12), KeyModifiers::NONE)
+        );
+        // A single `-` is a key, not a modifier
+        assert_eq!(
+            key("-"),
+            KeyPress::new(KeyCode::Char('-'), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            key("ctrl--"),
+            KeyPress::new(KeyCode::Char('-'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(parse_keys("g  g").unwrap(), vec![key("g"), key("g")]);
+    }
+
+    #[test]
+    fn format_parsed_keys() {
+        for name in &["ctrl-p", "alt-v", "G", "enter", "space", "f1", "ctrl-alt-x"] {
+            assert_eq!(key(name).to_string(), *name);
+        }
+        assert_eq!(format_keys(&parse_keys("g g").unwrap()), "g g");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            KeyPress::parse("ctrl-foo"),
+            Err("unknown key `ctrl-foo`".to_string())
+        );
+        assert!(KeyPress::parse("fx").is_err());
+        assert!(KeyPress::parse("").is_err());
+        assert_eq!(parse_keys(" "), Err("empty key".to_string()));
+        assert!(parse_keys("g nokey").is_err());
+        assert_eq!(
+            Action::parse("jump"),
+            Err("unknown action `jump`".to_string())
+        );
+        assert_eq!(
+            Action::parse("run: git fetch"),
+            Ok(Action::Run("git fetch".to_string()))
+        );
+    }
+
+    #[test]
+    fn text_of_keys() {
+        assert_eq!(key("G").text(), Some('G'));
+        assert_eq!(key("?").text(), Some('?'));
+        assert_eq!(key("ctrl-h").text(), None);
+        assert_eq!(key("enter").text(), None);
+    }
+
+    #[test]
+    fn presets_are_valid() {
+        for preset in &["default", "emacs", "vim"] {
+            assert!(
+                Keymap::load(&keys_config(preset, &[], &[])).is_ok(),
+                "{}",
+                preset
+            );
+        }
+        let errors = Keymap::load(&keys_config("helix", &[], &[])).err().unwrap();
+        assert_eq!(errors, vec!["unknown preset `helix`"]);
+    }
+
+    #[test]
+    fn default_preset() {
+        let keymap = Keymap::load(&KeysConfig::default()).unwrap();
+        assert!(keymap.initial_mode() == Mode::Insert);
+        assert_eq!(
+            action(&keymap, Mode::Insert, "ctrl-h"),
+            Some(Action::Backspace)
+        );
+        assert_eq!(action(&keymap, Mode::Insert, "f1"), Some(Action::Help));
+        // Printable characters edit the query
+        for ch in "?gjq/".chars() {
+            assert_eq!(action(&keymap, Mode::Insert, &ch.to_string()), None);
+        }
+        assert_eq!(
+            keymap.key_for(Mode::Insert, &Action::Pull).unwrap(),
+            "ctrl-p"
+        );
+    }
+
+    #[test]
+    fn emacs_preset() {
+        let keymap = Keymap::load(&keys_config("emacs", &[], &[])).unwrap();
+        assert!(keymap.normal.is_none());
+        assert_eq!(action(&keymap, Mode::Insert, "ctrl-n"), Some(Action::Down));
+        assert_eq!(action(&keymap, Mode::Insert, "alt-<"), Some(Action::First));
+        assert_eq!(
+            action(&keymap, Mode::Insert, "ctrl-h"),
+            Some(Action::Backspace)
+        );
+        assert_eq!(
+            action(&keymap, Mode::Insert, "ctrl-k"),
+            Some(Action::KillToEnd)
+        );
+        assert_eq!(action(&keymap, Mode::Insert, "alt-p"), Some(Action::Pull));
+        assert_eq!(action(&keymap, Mode::Insert, "f1"), Some(Action::Help));
+        assert_eq!(action(&keymap, Mode::Insert, "?"), None);
+    }
+
+    #[test]
+    fn vim_preset() {
+        let keymap = Keymap::load(&keys_config("vim", &[], &[])).unwrap();
+        assert!(keymap.initial_mode() == Mode::Normal);
+        assert_eq!(action(&keymap, Mode::Normal, "j"), Some(Action::Down));
+        assert_eq!(action(&keymap, Mode::Normal, "G"), Some(Action::Last));
+        assert_eq!(action(&keymap, Mode::Normal, "?"), Some(Action::Help));
+        assert!(matches!(
+            keymap.lookup(Mode::Normal, &[key("g")]),
+            Lookup::Pending
+        ));
+        assert_eq!(action(&keymap, Mode::Normal, "g g"), Some(Action::First));
+        assert!(matches!(
+            keymap.lookup(Mode::Normal, &parse_keys("g j").unwrap()),
+            Lookup::Unbound
+        ));
+        assert_eq!(
+            action(&keymap, Mode::Insert, "esc"),
+            Some(Action::NormalMode)
+        );
+        assert_eq!(action(&keymap, Mode::Insert, "j"), None);
+        assert_eq!(
+            keymap.help(Mode::Normal)[0],
+            ("j, down".to_string(), "down".to_string())
+        );
+    }
+
+    #[test]
+    fn user_bindings_replace_preset_bindings() {
+        let config = keys_config(
+            "vim",
+            &[("ctrl-j", "down")],
+            &[
+                ("f", "run:git fetch --all"),
+                ("ctrl-f", "none"),
+                ("j", "up"),
+            ],
+        );
+        let keymap = Keymap::load(&config).unwrap();
+        assert_eq!(action(&keymap, Mode::Insert, "ctrl-j"), Some(Action::Down));
+        assert_eq!(
+            action(&keymap, Mode::Normal, "f"),
+            Some(Action::Run("git fetch --all".to_string()))
+        );
+        assert_eq!(action(&keymap, Mode::Normal, "ctrl-f"), None);
+        assert_eq!(action(&keymap, Mode::Normal, "j"), Some(Action::Up));
+        // Only one binding per key
+        let bound = |keys: &str| {
+            let keys = parse_keys(keys).unwrap();
+            keymap
+                .bindings(Mode::Normal)
+                .iter()
+                .filter(|(k, _)| *k == keys)
+                .count()
+        };
+        assert_eq!(bound("j"), 1);
+    }
+
+    #[test]
+    fn invalid_user_bindings() {
+        let config = keys_config(
+            "default",
+            &[
+                ("ctrl-P", "up"),
+                ("ctrl-p", "down"),
+                ("hyper-x", "up"),
+                ("f2", "fly"),
+            ],
+            &[],
+        );
+        let errors = Keymap::load(&config).err().unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                "`ctrl-p` and `ctrl-P` are the same key `ctrl-p`",
+                "unknown action `fly`",
+                "unknown key `hyper-x`",
+            ]
+        );
+        let config = keys_config("emacs", &[], &[("j", "down")]);
+        let errors = Keymap::load(&config).err().unwrap();
+        assert_eq!(
+            errors,
+            vec!["normal mode bindings require the `vim` preset"]
+        );
+    }
+
+    #[test]
+    fn hidden_sequences_conflict() {
+        let config = keys_config("vim", &[], &[("g", "group"), ("z z", "first")]);
+        let errors = Keymap::load(&config).err().unwrap();
+        assert_eq!(errors, vec!["normal mode: `g` (group) hides `g g` (first)"]);
+        let config = keys_config(
+            "default",
+            &[("ctrl-x", "none"), ("ctrl-x d", "check-delete")],
+            &[],
+        );
+        assert!(Keymap::load(&config).is_ok());
+        let config = keys_config("default", &[("ctrl-x d", "check-delete")], &[]);
+        let errors = Keymap::load(&config).err().unwrap();
+        assert_eq!(
+            errors,
+            vec!["insert mode: `ctrl-x` (check-delete) hides `ctrl-x d` (check-delete)"]
+        );
+    }
+}
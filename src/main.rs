@@ -1,149 +1,637 @@
+mod cleanup;
+mod config;
+mod error;
+mod history;
 mod index;
+mod keymap;
+mod manifest;
 #[allow(dead_code)]
 mod path_util;
+mod query;
+mod rank;
+mod relocate;
+mod remote;
 mod repo;
-#[allow(dead_code)]
+mod report;
+mod safety;
+mod screen;
+mod search;
 mod select;
+mod server;
+mod stats;
+mod tags;
+#[cfg(test)]
+mod test_util;
 #[allow(dead_code)]
 mod theme;
+mod viewport;
 
+use config::Config;
 use console::{Style, Term};
-use select::{Item, SelectCommand};
-use std::io::{BufRead, BufReader, Error, ErrorKind};
-use std::path::Path;
+use error::{Error, FileContext, Result};
+use history::History;
+use keymap::{Action, Keymap};
+use screen::Screen;
+use search::{search, Searcher};
+use select::SelectCommand;
+use server::Listen;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use sublime_fuzzy::{best_match, Match};
-
-fn search(input: &String, repos: &Vec<String>) -> Vec<Item> {
-    if input.is_empty() {
-        return repos
-            .iter()
-            .enumerate()
-            .map(|(i, repo)| Item {
-                text: repo.clone(),
-                item_key: i,
-                match_info: Match::new(),
-            })
-            .collect();
-    }
-    let mut matches = repos
-        .iter()
-        .enumerate()
-        .map(|(idx, repo)| (idx, best_match(&input, repo)))
-        .filter(|(_i, m)| m.is_some())
-        .map(|(i, m)| (i, m.unwrap()))
-        .collect::<Vec<_>>();
-    matches.sort_by(|a, b| b.1.score().cmp(&a.1.score()));
+use tags::Tags;
 
-    let highlight = Style::new().on_blue();
-    let items: Vec<Item> = matches
-        .iter()
-        .map(|(i, m)| {
-            let r = &repos[*i];
-            let mut outstr = String::new();
-            let mut idx = 0;
-            for (match_idx, len) in m.continuous_matches() {
-                outstr.push_str(&r[idx..match_idx]);
-                idx = match_idx + len;
-                outstr.push_str(&format!("{}", highlight.apply_to(&r[match_idx..idx])));
-            }
-            if idx < r.len() {
-                outstr.push_str(&r[idx..r.len()]);
-            }
-            Item {
-                text: outstr,
-                item_key: *i,
-                match_info: m.clone(),
-            }
-        })
-        .collect();
-    items
-}
-
-fn exec_command(dir: &str, cmd: &str, args: &[&str]) -> Result<(), Error> {
-    std::env::set_current_dir(&Path::new(dir))?;
-    let term = Term::stdout();
+/// Runs a command in the given directory and returns its output lines,
+/// preceded by the command line.
+fn run_command(dir: &str, cmd: &str, args: &[&str]) -> std::io::Result<Vec<String>> {
     let cmd_style = Style::new().cyan();
-    println!(
+    let mut lines = vec![format!(
         "{}",
         cmd_style.apply_to(&format!("{} {}", cmd, args.join(" ")))
-    );
-    let mut numlines = 1;
-    let stdout = Command::new(cmd)
+    )];
+    let output = Command::new(cmd)
         .args(args)
-        .stdout(Stdio::piped())
-        .spawn()?
-        .stdout
-        .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
-
-    let reader = BufReader::new(stdout);
-
-    reader
-        .lines()
-        .filter_map(|line| line.ok())
-        .for_each(|line| {
-            numlines += 1;
-            println!("{}", line);
-        });
-
-    term.move_cursor_up(numlines)?;
+        .current_dir(Path::new(dir))
+        .stdin(Stdio::null())
+        .output()?;
+    for stream in [&output.stdout, &output.stderr].iter() {
+        lines.extend(String::from_utf8_lossy(stream).lines().map(String::from));
+    }
+    Ok(lines)
+}
+
+const USAGE: &str = "Usage:
+    git quick                       Navigate between repositories
+    git quick tag <tag> [<repo>]    Assign a tag to a repository
+    git quick untag <tag> [<repo>]  Remove a tag from a repository
+    git quick each <query> -- <command>...
+                                    Run a command in all matching repositories,
+                                    e.g. `git quick each '#backend' -- git pull`
+    git quick serve [--port <port> | --socket <path>]
+                                    Serve the index for editor integrations
+    git quick clone <url>           Clone into <root>/<host>/<owner>/<repo>, or
+                                    change into an existing clone
+    git quick check [<repo>]        Check whether a repository can be deleted
+                                    without losing commits or files
+    git quick cleanup [--older-than <age>] [--archive-dir <dir>] [--list]
+                                    Archive or delete repositories without commits
+                                    and visits for <age>, e.g. `6m`
+    git quick duplicates            List clones of the same remote repository
+    git quick relocate [--template <template>] [--dry-run] [--yes]
+                                    Move repositories to a location based on their
+                                    remote URL, e.g. `~/src/{host}/{owner}/{name}`
+    git quick export [--json] [<file>]
+                                    Write a manifest of all repositories, their
+                                    remotes, branches and tags as TOML or JSON
+    git quick restore [--jobs <n>] <file>
+                                    Clone the missing repositories of a manifest
+                                    into the same layout
+    git quick stats [--since <age>] [--mine | --author <email>] [--top <n>] [--json]
+                                    Show commits per day and the most active
+                                    repositories, e.g. `--since 1w` or `2024-05-01`
+    git quick report [--months <n>] [--json | --markdown]
+                                    List uncommitted, unpushed and other local-only
+                                    work, and repos without commits in <n> months";
+
+/// Default directory of `git quick clone`.
+const DEFAULT_CLONE_ROOT: &str = "~/src";
+
+fn usage() -> Result<()> {
+    Err(Error::Usage)
+}
+
+/// Assigns (`add`) or removes a tag of the given repo, or of the repo
+/// containing the current directory.
+fn tag(args: &[String], add: bool) -> Result<()> {
+    let (tag, repo) = match args {
+        [tag] => (tag, "."),
+        [tag, repo] => (tag, repo.as_str()),
+        _ => return usage(),
+    };
+    let tag = tag.trim_start_matches('#');
+    let root = repo::repo_root(repo).ok_or_else(|| Error::NotARepository(repo.to_string()))?;
+    let mut tags = Tags::load()?;
+    if add {
+        tags.add(&root, tag);
+    } else {
+        tags.remove(&root, tag);
+    }
+    tags.save()
+}
+
+/// Runs a command in every repo matching the query.
+fn each(args: &[String]) -> Result<()> {
+    let sep = match args.iter().position(|a| a == "--") {
+        Some(sep) if sep + 1 < args.len() => sep,
+        _ => return usage(),
+    };
+    let query = args[..sep].join(" ");
+    let (cmd, cmd_args) = (&args[sep + 1], &args[sep + 2..]);
+    let repos = index::Index::new()?.get_ranked_repos(&Config::load())?;
+    let header_style = Style::new().cyan();
+    for item in search(&query, &repos) {
+        let path = &repos[item.item_key].path;
+        println!("{}", header_style.apply_to(path));
+        let status = Command::new(cmd).args(cmd_args).current_dir(path).status();
+        if let Err(e) = status {
+            eprintln!("git-quick: {}: {}", cmd, e);
+        }
+    }
     Ok(())
 }
 
-fn main() {
-    let mut index = index::Index::new();
-    let repos = index.get_repos_by_last_commit();
+/// Registers a visit of `repo` and changes into it.
+fn enter(repo: &str) -> Result<()> {
+    let mut history = History::load()?;
+    history.record_visit(repo);
+    history.save()?;
+    // cwd is only kept if script is started in same shell (source)
+    let _ = std::env::set_current_dir(Path::new(repo));
+    Ok(())
+}
+
+/// Clones a repository into the clone root, unless it was cloned before,
+/// and changes into the clone.
+fn clone(args: &[String]) -> Result<()> {
+    let url = match args {
+        [url] => url.clone(),
+        _ => return usage(),
+    };
+    // Local paths are cloned by absolute path, so the layout doesn't depend
+    // on the current directory
+    let url = match Path::new(&url).canonicalize() {
+        Ok(path) if !url.contains("://") => path.display().to_string(),
+        _ => url,
+    };
+    let remote = remote::RemoteUrl::parse(&url).ok_or_else(|| Error::InvalidUrl(url.clone()))?;
+    let config = Config::load();
+    let mut index = index::Index::new()?;
+    let repos = index.get_ranked_repos(&config)?;
+    let canonical = remote::canonical_url(&url, &std::env::current_dir()?);
+    let existing = repos.iter().find(|repo| {
+        repo.remote_url
+            .as_ref()
+            .is_some_and(|u| remote::canonical_url(u, Path::new(&repo.path)) == canonical)
+    });
+    if let Some(repo) = existing {
+        eprintln!("Already cloned at {}", repo.display);
+        return enter(&repo.path);
+    }
+    let root = config.clone.root.as_deref().unwrap_or(DEFAULT_CLONE_ROOT);
+    let target = PathBuf::from(path_util::expand_tilde(root)).join(remote.clone_path());
+    if let Some(root) = remote::existing_clone(&target)? {
+        eprintln!("Already cloned at {}", root);
+        index.add_repo(&root)?;
+        return enter(&root);
+    }
+    let target_str = target.display().to_string();
+    let status = Command::new("git")
+        .args(["clone", "--", &url, &target_str])
+        .status()?;
+    if !status.success() {
+        // Remove the directories created for the clone
+        path_util::remove_empty_parents(&target, Path::new(&path_util::expand_tilde(root)));
+        return Err(Error::CommandFailed(format!("git clone {}", url)));
+    }
+    index.add_repo(&target_str)?;
+    enter(&target_str)
+}
+
+/// Explains whether the given repo, or the repo containing the current
+/// directory, can be deleted without losing work. Exits with status 1 if
+/// not.
+fn check(args: &[String]) -> Result<()> {
+    let repo = match args {
+        [] => ".",
+        [repo] => repo.as_str(),
+        _ => return usage(),
+    };
+    let root = repo::repo_root(repo).ok_or_else(|| Error::NotARepository(repo.to_string()))?;
+    let report = safety::SafetyReport::new(&root)?;
+    for line in report.lines(&root) {
+        println!("{}", line);
+    }
+    if !report.is_safe() {
+        return Err(Error::Unsafe(root));
+    }
+    Ok(())
+}
+
+/// Lists stale repositories and offers to archive or delete them.
+fn cleanup(args: &[String]) -> Result<()> {
+    let config = Config::load();
+    let mut older_than = config.cleanup.older_than.clone();
+    let mut archive_dir = config.cleanup.archive_dir.clone();
+    let mut list = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--older-than" => older_than = args.next().cloned(),
+            "--archive-dir" => archive_dir = args.next().cloned(),
+            "--list" => list = true,
+            _ => return usage(),
+        }
+    }
+    let max_hours = match older_than.as_deref().map(query::parse_hours) {
+        None => query::parse_hours(cleanup::DEFAULT_AGE).unwrap_or_default(),
+        Some(Some(hours)) => hours,
+        Some(None) => return usage(),
+    };
+    let mut index = index::Index::new()?;
+    let repos = index.get_ranked_repos(&config)?;
+    let mut history = History::load()?;
+    let stale = cleanup::find_stale(&repos, &history, max_hours);
+    if stale.is_empty() {
+        println!("No stale repositories.");
+        return Ok(());
+    }
+    for line in cleanup::format_table(&stale) {
+        println!("{}", line);
+    }
+    let total = stale.iter().map(|repo| repo.size).sum();
+    println!(
+        "\n{} stale repositories, {}.",
+        stale.len(),
+        cleanup::format_size(total)
+    );
+    if list || !Term::stderr().is_term() {
+        return Ok(());
+    }
+    let archive_dir = match archive_dir {
+        Some(dir) => PathBuf::from(path_util::expand_tilde(&dir)),
+        None => cleanup::default_archive_dir()?,
+    };
+    cleanup::interact(&stale, &mut index, &mut history, &archive_dir)
+}
+
+/// Lists the clones of remote repositories cloned more than once.
+fn duplicates(args: &[String]) -> Result<()> {
+    if !args.is_empty() {
+        return usage();
+    }
+    let repos = index::Index::new()?.get_ranked_repos(&Config::load())?;
+    let groups = remote::find_duplicates(&repos);
+    if groups.is_empty() {
+        println!("No repository is cloned more than once.");
+        return Ok(());
+    }
+    let mut rows = Vec::new();
+    for group in &groups {
+        for (n, &idx) in group.repos.iter().enumerate() {
+            let repo = &repos[idx];
+            rows.push(vec![
+                if n == 0 {
+                    group.url.clone()
+                } else {
+                    String::new()
+                },
+                repo.display.clone(),
+                repo.branch
+                    .clone()
+                    .unwrap_or_else(|| "(no branch)".to_string()),
+                if repo.is_dirty() { "dirty" } else { "clean" }.to_string(),
+                repo::format_age(repo.hours_since_last_commit),
+            ]);
+        }
+    }
+    let columns = ["Remote", "Clone", "Branch", "State", "Last commit"];
+    for line in report::table(&columns, &rows) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Writes the manifest of all repositories to the given file, or to
+/// standard output.
+fn export(args: &[String]) -> Result<()> {
+    let mut json = false;
+    let mut file = None;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(PathBuf::from(arg)),
+            _ => return usage(),
+        }
+    }
+    let format = match &file {
+        _ if json => manifest::Format::Json,
+        Some(file) => manifest::Format::of(file),
+        None => manifest::Format::Toml,
+    };
+    let mut index = index::Index::new()?;
+    let repos = index.get_ranked_repos(&Config::load())?;
+    let manifest = manifest::Manifest::new(&repos, &index.basedir);
+    let text = manifest.format(&format);
+    match file {
+        Some(file) => {
+            fs::write(&file, text).file_context(&file)?;
+            eprintln!(
+                "Exported {} repositories to {}",
+                manifest.repos.len(),
+                file.display()
+            );
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
 
-    // User input for search
-    let mut input = String::new();
+/// Clones the repositories of a manifest which are missing, relative to the
+/// home directory. Exits with status 1 if any of them failed.
+fn restore(args: &[String]) -> Result<()> {
+    let mut jobs = manifest::DEFAULT_JOBS;
+    let mut file = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--jobs" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => jobs = n,
+                None => return usage(),
+            },
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(PathBuf::from(arg)),
+            _ => return usage(),
+        }
+    }
+    let file = match file {
+        Some(file) => file,
+        None => return usage(),
+    };
+    let manifest = manifest::Manifest::load(&file)?;
+    let log = manifest::log_path(&file);
+    let config = Config::load();
+    let mut index = index::Index::new()?;
+    // Scan before cloning, so that the clones are added to the index
+    index.get_repos()?;
+    let root = index.basedir.clone();
+    let mut tags = Tags::load()?;
+    let failed = manifest::restore(&manifest, &root, &log, jobs, |entry, path| {
+        index.add_repo(path)?;
+        // Tags given by the config apply anyway, only the others are assigned
+        let mut info = [repo::RepoInfo::new(path)];
+        tags.apply(&mut info, &config);
+        for tag in entry.tags.iter().filter(|tag| !info[0].tags.contains(tag)) {
+            tags.add(path, tag);
+        }
+        tags.save()
+    })?;
+    if failed > 0 {
+        return Err(Error::NotRestored(failed, log));
+    }
+    Ok(())
+}
+
+/// Moves the repositories to the location given by the path template,
+/// after confirming the plan.
+fn relocate(args: &[String]) -> Result<()> {
+    let config = Config::load();
+    let mut template = config.relocate.template.clone();
+    let mut dry_run = false;
+    let mut yes = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--template" => template = args.next().cloned(),
+            "--dry-run" => dry_run = true,
+            "--yes" => yes = true,
+            _ => return usage(),
+        }
+    }
+    // Same layout as `git quick clone` by default
+    let template = template.unwrap_or_else(|| {
+        let root = config.clone.root.as_deref().unwrap_or(DEFAULT_CLONE_ROOT);
+        format!("{}/{{host}}/{{owner}}/{{name}}", root.trim_end_matches('/'))
+    });
+    if !template.contains("{name}") {
+        return Err(Error::InvalidTemplate(template));
+    }
+    let mut index = index::Index::new()?;
+    let repos = index.get_ranked_repos(&config)?;
+    let moves = relocate::plan(&repos, &template);
+    if moves.is_empty() {
+        println!("All repositories with a remote URL are in place.");
+        return Ok(());
+    }
+    for line in relocate::format_table(&moves, &index.basedir) {
+        println!("{}", line);
+    }
+    let ready = moves.iter().filter(|m| m.problem.is_none()).count();
+    println!("\n{} of {} repositories can be moved.", ready, moves.len());
     let term = Term::stderr();
-    term.clear_screen().unwrap();
-    loop {
-        let items = search(&input, &repos);
-        let theme = theme::ColorfulTheme::default();
-        let mut select = select::Select::with_theme(&theme);
-        match select
-            .default(0)
-            .paged(true)
-            .items(items)
-            .interact_on_opt(&term)
-        {
-            Ok(SelectCommand::CharInput { ch }) => match ch {
-                _ => {
-                    input.push(ch);
-                }
+    if dry_run || ready == 0 || (!yes && !term.is_term()) {
+        return Ok(());
+    }
+    if !yes {
+        term.write_str(&format!("Move {} repositories? [y/N] ", ready))?;
+        let confirmed = term.read_char()?;
+        term.write_line(&confirmed.to_string())?;
+        if confirmed != 'y' {
+            return Ok(());
+        }
+    }
+    let moved = relocate::apply(&moves, &mut index)?;
+    if moved < ready {
+        return Err(Error::NotMoved(ready - moved, ready));
+    }
+    Ok(())
+}
+
+/// Prints the commit activity of all repositories.
+fn stats(args: &[String]) -> Result<()> {
+    let mut since = stats::DEFAULT_SINCE.to_string();
+    let mut authors = Vec::new();
+    let mut top = 10;
+    let mut json = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--since" => match args.next() {
+                Some(value) => since = value.clone(),
+                None => return usage(),
             },
-            Ok(SelectCommand::Command { ch, item_key }) => match ch {
-                '\u{7f}' => {
-                    // backspace
-                    input.pop();
-                }
-                '\u{10}' => {
-                    // ctrl-p
-                    exec_command(&repos[item_key], "git", &["pull"]).unwrap();
-                    select.reset_cursor(&term);
-                }
-                '\u{13}' => {
-                    // ctrl-s
-                    exec_command(&repos[item_key], "git", &["status"]).unwrap();
-                    select.reset_cursor(&term);
-                }
-                '\u{1b}' => {
-                    // home
-                    input.clear(); // reset search
-                    term.clear_screen().unwrap();
-                }
-                _ => {
-                    println!("Unknown command key {:?}", ch);
+            "--author" => match args.next() {
+                Some(email) => authors.push(email.clone()),
+                None => return usage(),
+            },
+            "--top" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => top = n,
+                None => return usage(),
+            },
+            "--mine" => {
+                let identity = repo::Identity::load(&Config::load());
+                if identity.emails.is_empty() {
+                    return Err(Error::NoEmail);
                 }
+                authors.extend(identity.emails);
+            }
+            "--json" => json = true,
+            _ => return usage(),
+        }
+    }
+    let first_day = match stats::parse_since(&since) {
+        Some(day) => day,
+        None => return usage(),
+    };
+    let repos = index::Index::new()?.get_ranked_repos(&Config::load())?;
+    let stats = stats::Stats::new(&repos, first_day, &authors);
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&stats).unwrap_or_default()
+        );
+        return Ok(());
+    }
+    let (_, cols) = Term::stdout().size();
+    for line in stats.format(top, cols as usize) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Prints the health report of all repositories.
+fn report(args: &[String]) -> Result<()> {
+    let mut format = report::Format::Table;
+    let mut stale_months = 6;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => format = report::Format::Json,
+            "--markdown" => format = report::Format::Markdown,
+            "--months" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(months) => stale_months = months,
+                None => return usage(),
+            },
+            _ => return usage(),
+        }
+    }
+    let mut repos = index::Index::new()?.get_ranked_repos(&Config::load())?;
+    repos.sort_by(|a, b| a.display.cmp(&b.display));
+    let reports = repos
+        .iter()
+        .map(|repo| report::RepoReport::new(&repo.path, &repo.display, stale_months))
+        .filter(|report| !report.is_clean())
+        .collect::<Vec<_>>();
+    println!("{}", report::format(&reports, repos.len(), &format));
+    Ok(())
+}
+
+fn serve(args: &[String]) -> Result<()> {
+    let mut listen = Listen::Tcp(server::DEFAULT_PORT);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--port", Some(port)) => match port.parse() {
+                Ok(port) => listen = Listen::Tcp(port),
+                Err(_) => return usage(),
             },
-            Ok(SelectCommand::Select { item_key }) => {
-                // cwd is only kept if script is started in same shell (source)
-                let _ = std::env::set_current_dir(&Path::new(&repos[item_key]));
-                break;
+            #[cfg(unix)]
+            ("--socket", Some(path)) => listen = Listen::Unix(path.into()),
+            _ => return usage(),
+        }
+    }
+    Ok(server::serve(&listen)?)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    console::set_colors_enabled(theme::use_colors(&Term::stdout()));
+    let result = match args.first().map(String::as_str) {
+        None => pick(),
+        Some("tag") => tag(&args[1..], true),
+        Some("untag") => tag(&args[1..], false),
+        Some("each") => each(&args[1..]),
+        Some("serve") => serve(&args[1..]),
+        Some("report") => report(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("clone") => clone(&args[1..]),
+        Some("cleanup") => cleanup(&args[1..]),
+        Some("duplicates") => duplicates(&args[1..]),
+        Some("export") => export(&args[1..]),
+        Some("relocate") => relocate(&args[1..]),
+        Some("restore") => restore(&args[1..]),
+        Some("stats") => stats(&args[1..]),
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(_) => usage(),
+    };
+    match result {
+        Ok(()) => {}
+        Err(Error::Usage) => {
+            eprintln!("{}", USAGE);
+            std::process::exit(Error::Usage.exit_code());
+        }
+        Err(e) => {
+            eprintln!("git-quick: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+fn pick() -> Result<()> {
+    let mut index = index::Index::new()?;
+    let config = Config::load();
+    let keymap = Keymap::load(&config.keys).map_err(Error::InvalidKeys)?;
+    let theme = theme::ColorfulTheme::from_config(&config.theme).map_err(Error::InvalidTheme)?;
+    let repos = index.get_ranked_repos(&config)?;
+    let total = repos.len();
+    let mut searcher = Searcher::new(repos);
+
+    // The picker is drawn on stderr
+    console::set_colors_enabled(theme::use_colors(&Term::stderr()));
+    let mut screen = Screen::new()?;
+    let mut select = select::Select::with_theme(&theme);
+    select
+        .default(0)
+        .keymap(keymap)
+        .total(total)
+        .query_history(history::load_queries()?);
+    loop {
+        match select.interact_query(&mut screen, &mut searcher)? {
+            SelectCommand::Command { action, item_key } => {
+                let path = &searcher.repos()[item_key].path;
+                let output = match action {
+                    Action::Pull => run_command(path, "git", &["pull"]),
+                    Action::Status => run_command(path, "git", &["status"]),
+                    Action::Run(cmd) => run_command(path, "sh", &["-c", &cmd]),
+                    Action::CheckDelete => {
+                        match safety::SafetyReport::new(path) {
+                            Ok(report) => {
+                                select.output(report.lines(&searcher.repos()[item_key].display))
+                            }
+                            Err(e) => select.error(e.message()),
+                        };
+                        continue;
+                    }
+                    Action::Group => {
+                        let group_by = searcher.group_by().next();
+                        searcher.set_group_by(group_by);
+                        select.status(group_by.describe());
+                        continue;
+                    }
+                    Action::TogglePin => {
+                        match index.toggle_pinned(path) {
+                            Ok(pinned) => {
+                                searcher.update_repos(|repos| repos[item_key].pinned = pinned)
+                            }
+                            Err(e) => {
+                                select.error(&e.to_string());
+                            }
+                        }
+                        continue;
+                    }
+                    _ => continue,
+                };
+                match output {
+                    Ok(lines) => select.output(lines),
+                    Err(e) => select.error(&e.to_string()),
+                };
+            }
+            SelectCommand::Select { item_key } => {
+                drop(screen);
+                history::save_query(select.query())?;
+                return enter(&searcher.repos()[item_key].path);
             }
-            Ok(SelectCommand::Quit) => break,
-            _ => {}
+            SelectCommand::Quit => return Ok(()),
         }
     }
 }
@@ -0,0 +1,491 @@
+//! Manifests listing all repositories with their remotes, to restore the
+//! same layout on another machine.
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::error::{Error, FileContext, Result};
+use crate::path_util::remove_empty_parents;
+use crate::repo::{self, RepoInfo};
+use serde::{Deserialize, Serialize};
+
+/// Default number of parallel clones of `restore`.
+pub const DEFAULT_JOBS: usize = 4;
+
+/// File format of a manifest.
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    /// Returns the format of the manifest file at `path`: JSON for `.json`
+    /// files, TOML otherwise.
+    pub fn of(path: &Path) -> Format {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Format::Json,
+            _ => Format::Toml,
+        }
+    }
+}
+
+/// A repository of the manifest.
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// Path relative to the root, or absolute for repos outside of it. Only
+    /// repos below the root are restored.
+    pub path: String,
+    /// The checked out branch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// URLs by remote name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub remotes: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    #[serde(default)]
+    pub repos: Vec<Entry>,
+}
+
+impl Manifest {
+    /// Lists the `repos`, with paths relative to `root`.
+    pub fn new(repos: &[RepoInfo], root: &Path) -> Manifest {
+        let mut entries = repos
+            .iter()
+            .map(|repo| {
+                let path = Path::new(&repo.path);
+                let path = path.strip_prefix(root).unwrap_or(path);
+                let remotes = match git2::Repository::open(&repo.path) {
+                    Ok(git2_repo) => repo::remotes(&git2_repo).into_iter().collect(),
+                    Err(_) => BTreeMap::new(),
+                };
+                Entry {
+                    path: path.display().to_string(),
+                    branch: repo.branch.clone(),
+                    tags: repo.tags.clone(),
+                    remotes,
+                }
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Manifest { repos: entries }
+    }
+
+    /// Reads the manifest file at `path`.
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let text = fs::read_to_string(path).file_context(path)?;
+        let manifest = match Format::of(path) {
+            Format::Json => serde_json::from_str(&text).map_err(|e| e.to_string()),
+            Format::Toml => toml::from_str(&text).map_err(|e| e.to_string()),
+        };
+        manifest.map_err(|e| Error::InvalidManifest(path.to_path_buf(), e))
+    }
+
+    /// Formats the manifest as TOML or JSON.
+    pub fn format(&self, format: &Format) -> String {
+        match format {
+            Format::Json => serde_json::to_string_pretty(self).unwrap_or_default(),
+            Format::Toml => toml::to_string(self).unwrap_or_default(),
+        }
+    }
+}
+
+/// Returns the path of the progress log of restoring the manifest at
+/// `path`, e.g. `repos.toml.log`.
+pub fn log_path(path: &Path) -> PathBuf {
+    let mut log = path.as_os_str().to_owned();
+    log.push(".log");
+    PathBuf::from(log)
+}
+
+/// Reads the paths of the entries restored before from the progress log.
+fn read_log(log: &Path) -> HashSet<String> {
+    let mut done = HashSet::new();
+    if let Ok(f) = File::open(log) {
+        for line in BufReader::new(f).lines().map_while(io::Result::ok) {
+            // done\t<path> or failed\t<path>\t<reason>
+            if let Some(path) = line.strip_prefix("done\t") {
+                done.insert(path.to_string());
+            }
+        }
+    }
+    done
+}
+
+/// Clones the repos of `manifest` which were not restored before into
+/// `root`, running `jobs` clones in parallel. Repos which already exist get
+/// the missing remotes and branch. `restored` is called for every restored
+/// repo with its absolute path. Progress is appended to the log at `log`,
+/// so an interrupted restore continues where it stopped.
+///
+/// Returns the number of repos which could not be restored.
+pub fn restore<F>(
+    manifest: &Manifest,
+    root: &Path,
+    log: &Path,
+    jobs: usize,
+    mut restored: F,
+) -> Result<usize>
+where
+    F: FnMut(&Entry, &str) -> Result<()>,
+{
+    let done = read_log(log);
+    let skipped = manifest
+        .repos
+        .iter()
+        .filter(|entry| done.contains(&entry.path))
+        .count();
+    if skipped > 0 {
+        eprintln!(
+            "Skipping {} repositories restored before, see {}.",
+            skipped,
+            log.display()
+        );
+    }
+    let mut pending = Vec::new();
+    for entry in manifest.repos.iter().filter(|e| !done.contains(&e.path)) {
+        if !is_below_root(&entry.path) {
+            eprintln!("{}: skipped, not below {}", entry.path, root.display());
+        } else if entry.remotes.is_empty() && !root.join(&entry.path).exists() {
+            eprintln!("{}: skipped, no remotes", entry.path);
+        } else {
+            pending.push(entry);
+        }
+    }
+    let mut log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .file_context(log)?;
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    let mut failed = 0;
+    thread::scope(|scope| -> Result<()> {
+        for _ in 0..jobs.clamp(1, pending.len().max(1)) {
+            let tx = tx.clone();
+            let (next, pending) = (&next, &pending);
+            scope.spawn(move || loop {
+                let idx = next.fetch_add(1, Ordering::SeqCst);
+                let entry = match pending.get(idx) {
+                    Some(entry) => *entry,
+                    None => break,
+                };
+                let target = root.join(&entry.path);
+                let result = restore_entry(entry, &target, root);
+                if tx.send((entry, target, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+        for (n, (entry, target, result)) in rx.into_iter().enumerate() {
+            let progress = format!("[{}/{}] {}", n + 1, pending.len(), entry.path);
+            match result {
+                Ok(message) => {
+                    eprintln!("{}: {}", progress, message);
+                    restored(entry, &target.display().to_string())?;
+                    writeln!(log_file, "done\t{}", entry.path).file_context(log)?;
+                }
+                Err(reason) => {
+                    eprintln!("{}: failed: {}", progress, reason);
+                    writeln!(log_file, "failed\t{}\t{}", entry.path, reason).file_context(log)?;
+                    failed += 1;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    Ok(failed)
+}
+
+/// Returns `true` if the relative `path` stays below the root, i.e. it is
+/// not absolute, has no `..` and isn't the root itself.
+fn is_below_root(path: &str) -> bool {
+    let components = Path::new(path).components().collect::<Vec<_>>();
+    components.iter().any(|c| matches!(c, Component::Normal(_)))
+        && components
+            .iter()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Clones the repo of `entry` to `target`, unless it exists, and sets up its
+/// remotes and branch. Returns what was done, or why it failed.
+fn restore_entry(entry: &Entry, target: &Path, root: &Path) -> std::result::Result<String, String> {
+    let mut message = if target.exists() {
+        let target_str = target.display().to_string();
+        if repo::repo_root(&target_str).as_deref() != Some(&target_str) {
+            return Err("exists and is not a repository".to_string());
+        }
+        "already present".to_string()
+    } else {
+        let (name, url) = entry
+            .remotes
+            .get_key_value("origin")
+            .or_else(|| entry.remotes.iter().next())
+            .ok_or_else(|| "no remotes".to_string())?;
+        let parent = target.parent().unwrap_or(root);
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        let target_str = target.display().to_string();
+        let result = git(
+            root,
+            &["clone", "--quiet", "--origin", name, "--", url, &target_str],
+        );
+        if let Err(e) = result {
+            remove_empty_parents(target, root);
+            return Err(e);
+        }
+        "cloned".to_string()
+    };
+    let git2_repo = git2::Repository::open(target).map_err(|e| e.message().to_string())?;
+    for (name, url) in &entry.remotes {
+        if git2_repo.find_remote(name).is_err() {
+            git2_repo
+                .remote(name, url)
+                .map_err(|e| e.message().to_string())?;
+        }
+    }
+    if let Some(branch) = &entry.branch {
+        if repo::current_branch(&git2_repo).as_ref() != Some(branch)
+            && git(target, &["switch", "--quiet", "--", branch]).is_err()
+        {
+            message.push_str(&format!(", branch {} not found", branch));
+        }
+    }
+    Ok(message)
+}
+
+/// Runs git in `dir`. Fails with the last line of its error output.
+fn git(dir: &Path, args: &[&str]) -> std::result::Result<(), String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        // Parallel clones can't share the terminal for credential prompts
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| e.to_string())?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(stderr
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("git failed")
+        .trim()
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn entry(path: &str, branch: Option<&str>, remotes: &[(&str, &str)]) -> Entry {
+        Entry {
+            path: path.to_string(),
+            branch: branch.map(String::from),
+            tags: Vec::new(),
+            remotes: remotes
+                .iter()
+                .map(|(name, url)| (name.to_string(), url.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut api = entry(
+            "work/api",
+            Some("main"),
+            &[
+                ("origin", "git@github.com:acme/api.git"),
+                ("upstream", "https://github.com/upstream/api"),
+            ],
+        );
+        api.tags = vec!["backend".to_string()];
+        let manifest = Manifest {
+            repos: vec![api, entry("/srv/notes", None, &[])],
+        };
+        for name in ["repos.toml", "repos.json", "repos.JSON"] {
+            let path = dir.path().join(name);
+            let text = manifest.format(&Format::of(&path));
+            fs::write(&path, &text).unwrap();
+            let loaded = Manifest::load(&path).unwrap();
+            assert_eq!(loaded.format(&Format::of(&path)), text, "{}", name);
+            assert_eq!(loaded.repos[0].remotes.len(), 2);
+            assert_eq!(loaded.repos[0].tags, vec!["backend"]);
+            assert_eq!(loaded.repos[1].branch, None);
+        }
+        let toml = manifest.format(&Format::Toml);
+        assert!(toml.starts_with("[[repos]]\npath = \"work/api\"\nbranch = \"main\"\n"));
+        assert!(toml.contains("[repos.remotes]\norigin = \"git@github.com:acme/api.git\"\n"));
+    }
+
+    #[test]
+    fn invalid_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repos.json");
+        fs::write(&path, "{\"repos\": [{}]}").unwrap();
+        assert!(matches!(
+            Manifest::load(&path),
+            Err(Error::InvalidManifest(..))
+        ));
+        assert!(Manifest::load(&dir.path().join("missing.toml")).is_err());
+    }
+
+    #[test]
+    fn log_next_to_the_manifest() {
+        assert_eq!(
+            log_path(Path::new("/tmp/repos.toml")),
+            PathBuf::from("/tmp/repos.toml.log")
+        );
+    }
+
+    #[test]
+    fn only_paths_below_the_root() {
+        assert!(is_below_root("work/api"));
+        assert!(is_below_root("./api"));
+        assert!(!is_below_root("../api"));
+        assert!(!is_below_root("work/../../api"));
+        assert!(!is_below_root("/srv/api"));
+        assert!(!is_below_root("."));
+        assert!(!is_below_root(""));
+    }
+
+    #[test]
+    fn restore_and_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let upstream = dir.path().join("upstream");
+        let git2_repo = test_util::init(&upstream);
+        let head = test_util::commit(&git2_repo, "README");
+        let main = test_util::head_branch(&git2_repo);
+        git2_repo
+            .branch("feature", &git2_repo.find_commit(head).unwrap(), false)
+            .unwrap();
+        let upstream = upstream.display().to_string();
+        let missing = dir.path().join("missing").display().to_string();
+        let manifest = Manifest {
+            repos: vec![
+                entry(
+                    "work/api",
+                    Some("feature"),
+                    &[("origin", &upstream), ("fork", "https://example.com/a")],
+                ),
+                entry("work/web", Some(&main), &[("upstream", &upstream)]),
+                entry("work/gone", None, &[("origin", &missing)]),
+                entry("work/local", None, &[]),
+                entry("../outside", None, &[("origin", &upstream)]),
+                entry(&upstream, None, &[("origin", &upstream)]),
+            ],
+        };
+        let root = dir.path().join("home");
+        let log = dir.path().join("repos.toml.log");
+        let mut restored = Vec::new();
+        let failed = restore(&manifest, &root, &log, 2, |entry, path| {
+            restored.push((entry.path.clone(), path.to_string()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(failed, 1);
+        restored.sort();
+        assert_eq!(
+            restored,
+            vec![
+                (
+                    "work/api".to_string(),
+                    root.join("work/api").display().to_string()
+                ),
+                (
+                    "work/web".to_string(),
+                    root.join("work/web").display().to_string()
+                ),
+            ]
+        );
+        let api = git2::Repository::open(root.join("work/api")).unwrap();
+        assert_eq!(test_util::head_branch(&api), "feature");
+        assert_eq!(
+            repo::remotes(&api)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>(),
+            vec!["origin", "fork"]
+        );
+        let web = git2::Repository::open(root.join("work/web")).unwrap();
+        assert!(web.find_remote("upstream").is_ok());
+        assert!(!root.join("work/gone").exists());
+        assert!(!dir.path().join("outside").exists());
+
+        // Only the failed repo is retried
+        let mut retried = Vec::new();
+        let failed = restore(&manifest, &root, &log, 2, |entry, _| {
+            retried.push(entry.path.clone());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!((failed, retried.len()), (1, 0));
+        let text = fs::read_to_string(&log).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|line| line.starts_with("failed\twork/gone\t"))
+                .count(),
+            2
+        );
+        assert_eq!(read_log(&log).len(), 2);
+    }
+
+    #[test]
+    fn existing_repos_are_completed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let git2_repo = test_util::init(&root.join("api"));
+        test_util::commit(&git2_repo, "README");
+        fs::create_dir_all(root.join("notes")).unwrap();
+        let manifest = Manifest {
+            repos: vec![
+                entry(
+                    "api",
+                    Some("missing"),
+                    &[
+                        ("origin", "https://example.com/other.git"),
+                        ("fork", "https://example.com/fork.git"),
+                    ],
+                ),
+                entry("notes", None, &[("origin", "https://example.com/notes")]),
+            ],
+        };
+        let failed = restore(&manifest, root, &root.join("log"), 1, |_, _| Ok(())).unwrap();
+        assert_eq!(failed, 1);
+        // The existing origin is kept
+        assert_eq!(
+            repo::remotes(&git2_repo),
+            vec![
+                (
+                    "origin".to_string(),
+                    "https://example.com/acme/api.git".to_string()
+                ),
+                (
+                    "fork".to_string(),
+                    "https://example.com/fork.git".to_string()
+                ),
+            ]
+        );
+        let log = fs::read_to_string(root.join("log")).unwrap();
+        assert!(log.contains("done\tapi\n"));
+        assert!(log.contains("failed\tnotes\texists and is not a repository\n"));
+    }
+}
@@ -13,7 +13,7 @@ pub fn is_hidden(dent: &DirEntry) -> bool {
     use std::os::unix::ffi::OsStrExt;
 
     if let Some(name) = file_name(dent.path()) {
-        name.as_bytes().get(0) == Some(&b'.')
+        name.as_bytes().first() == Some(&b'.')
     } else {
         false
     }
@@ -72,7 +72,7 @@ pub fn strip_prefix<'a, P: AsRef<Path> + ?Sized>(
     if prefix.len() > path.len() || prefix != &path[0..prefix.len()] {
         None
     } else {
-        Some(&Path::new(OsStr::from_bytes(&path[prefix.len()..])))
+        Some(Path::new(OsStr::from_bytes(&path[prefix.len()..])))
     }
 }
 
@@ -113,18 +113,16 @@ pub fn is_file_name<P: AsRef<Path>>(path: P) -> bool {
 /// If the path terminates in ., .., or consists solely of a root of prefix,
 /// file_name will return None.
 #[cfg(unix)]
-pub fn file_name<'a, P: AsRef<Path> + ?Sized>(path: &'a P) -> Option<&'a OsStr> {
+pub fn file_name<P: AsRef<Path> + ?Sized>(path: &P) -> Option<&OsStr> {
     use memchr::memrchr;
     use std::os::unix::ffi::OsStrExt;
 
     let path = path.as_ref().as_os_str().as_bytes();
-    if path.is_empty() {
-        return None;
-    } else if path.len() == 1 && path[0] == b'.' {
-        return None;
-    } else if path.last() == Some(&b'.') {
-        return None;
-    } else if path.len() >= 2 && &path[path.len() - 2..] == &b".."[..] {
+    if path.is_empty()
+        || (path.len() == 1 && path[0] == b'.')
+        || path.last() == Some(&b'.')
+        || (path.len() >= 2 && path[path.len() - 2..] == b".."[..])
+    {
         return None;
     }
     let last_slash = memrchr(b'/', path).map(|i| i + 1).unwrap_or(0);
@@ -139,3 +137,101 @@ pub fn file_name<'a, P: AsRef<Path> + ?Sized>(path: &'a P) -> Option<&'a OsStr>
 pub fn file_name<'a, P: AsRef<Path> + ?Sized>(path: &'a P) -> Option<&'a OsStr> {
     path.as_ref().file_name()
 }
+
+/// Replaces a leading `~` with the user's home directory.
+pub fn expand_tilde(path: &str) -> String {
+    if path == "~" || path.starts_with("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return format!("{}{}", home.display(), &path[1..]);
+        }
+    }
+    path.to_string()
+}
+
+/// Shortens `path` for display.
+///
+/// Paths below `basedir` are shown relative to it, paths below the home
+/// directory start with `~`.
+pub fn display_path(path: &str, basedir: &Path) -> String {
+    let home = dirs::home_dir();
+    let path = Path::new(path);
+    if home.as_deref() != Some(basedir) {
+        if let Ok(rel) = path.strip_prefix(basedir) {
+            if !rel.as_os_str().is_empty() {
+                return rel.display().to_string();
+            }
+        }
+    }
+    if let Some(home) = home {
+        if let Ok(rel) = path.strip_prefix(&home) {
+            return Path::new("~").join(rel).display().to_string();
+        }
+    }
+    path.display().to_string()
+}
+
+/// Removes the empty parent directories of `path`, stopping at `root`.
+pub fn remove_empty_parents(path: &Path, root: &Path) {
+    for dir in path.ancestors().skip(1) {
+        if dir == root || std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+/// Returns `true` if `path` is `dir` or below it.
+pub fn is_within(path: &str, dir: &str) -> bool {
+    rebase_path(path, dir, dir).is_some()
+}
+
+/// Returns `path` moved from `from` to `to`, if it is `from` or below it.
+pub fn rebase_path(path: &str, from: &str, to: &str) -> Option<String> {
+    let rest = path.strip_prefix(from)?;
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(format!("{}{}", to, rest))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebase_paths() {
+        assert_eq!(
+            rebase_path("/work/api", "/work/api", "/src/api"),
+            Some("/src/api".to_string())
+        );
+        assert_eq!(
+            rebase_path("/work/api/wt", "/work/api", "/src/api"),
+            Some("/src/api/wt".to_string())
+        );
+        assert_eq!(rebase_path("/work/api-docs", "/work/api", "/src/api"), None);
+        assert_eq!(rebase_path("/work", "/work/api", "/src/api"), None);
+        assert!(is_within("/work/api", "/work/api"));
+        assert!(is_within("/work/api/vendor/lib", "/work/api"));
+        assert!(!is_within("/work/api-docs", "/work/api"));
+        assert!(!is_within("/work", "/work/api"));
+    }
+
+    #[test]
+    fn remove_empty_parent_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("a/b/c/d")).unwrap();
+        std::fs::write(root.join("a/file"), "").unwrap();
+        remove_empty_parents(&root.join("a/b/c/d"), root);
+        // `d` itself is left
+        assert!(root.join("a/b/c/d").exists());
+        std::fs::remove_dir(root.join("a/b/c/d")).unwrap();
+        remove_empty_parents(&root.join("a/b/c/d"), root);
+        assert!(!root.join("a/b").exists());
+        assert!(root.join("a").exists());
+        std::fs::remove_file(root.join("a/file")).unwrap();
+        remove_empty_parents(&root.join("a/b"), root);
+        assert!(!root.join("a").exists());
+        assert!(root.exists());
+    }
+}
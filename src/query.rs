@@ -0,0 +1,580 @@
+//! Query language for filtering repositories.
+//!
+//! A query consists of space-separated terms which must all match:
+//!
+//! * `term` - fuzzy match against the displayed repo path, see `rank`
+//! * `'term` - exact substring of the path
+//! * `^term` - path (e.g. `^~/work`) or repo name starts with `term`
+//! * `term$` - path ends with `term`
+//! * `!term` - path does not contain `term`; `!` negates any other term, too
+//! * `#tag` - repo has the given tag
+//! * `branch:main` - checked out branch
+//! * `dirty:yes` / `dirty:no` - uncommitted changes in the working tree
+//! * `remote:github.com/org` - remote URL contains the given text
+//! * `host:github` - host of the remote URL contains the given text
+//! * `owner:acme` - user or organization of the remote URL, including its
+//!   subgroups
+//! * `age:<7d` / `age:>1y` - age of the last commit (units: h, d, w, m, y)
+//! * `mine:` / `mine:2w` - you authored a commit in the given period, 30 days
+//!   by default; repos are sorted by your last commit, after the score of
+//!   fuzzy terms
+use crate::rank::fuzzy_score;
+use crate::repo::{RepoInfo, DEFAULT_MINE_WITHIN};
+
+/// Range of matched characters, as (char index, char count).
+pub type Highlight = (usize, usize);
+
+#[derive(PartialEq)]
+enum Term {
+    Fuzzy(String),
+    Exact(String),
+    Prefix(String),
+    Suffix(String),
+    Tag(String),
+    Branch(String),
+    Dirty(bool),
+    Remote(String),
+    Host(String),
+    Owner(String),
+    /// Commit age comparison: `true` for "younger than", and hours.
+    Age(bool, i64),
+    /// Your last commit is younger than the hours, or the configured period.
+    Mine(Option<i64>),
+}
+
+#[derive(PartialEq)]
+struct Filter {
+    term: Term,
+    negated: bool,
+}
+
+/// A parsed query.
+pub struct Query {
+    filters: Vec<Filter>,
+}
+
+/// Result of matching a query against a repo.
+pub struct QueryMatch {
+    /// Matched ranges of the displayed path, sorted and non-overlapping.
+    pub highlights: Vec<Highlight>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Query {
+        Query {
+            filters: input.split_whitespace().filter_map(parse_filter).collect(),
+        }
+    }
+
+    /// Returns `true` if the query contains at least one fuzzy term.
+    pub fn is_fuzzy(&self) -> bool {
+        self.filters
+            .iter()
+            .any(|f| !f.negated && matches!(f.term, Term::Fuzzy(_)))
+    }
+
+    /// Returns `true` if the query contains a `mine:` term, so that the
+    /// results are sorted by your last commit.
+    pub fn is_mine(&self) -> bool {
+        self.filters
+            .iter()
+            .any(|f| !f.negated && matches!(f.term, Term::Mine(_)))
+    }
+
+    /// Evaluates all terms against `repo`.
+    pub fn matches(&self, repo: &RepoInfo) -> Option<QueryMatch> {
+        let mut highlights = Vec::new();
+        self.evaluate(repo, Some(&mut highlights))?;
+        Some(QueryMatch {
+            highlights: merge_highlights(highlights),
+        })
+    }
+
+    /// Returns the sum of the fuzzy match scores, if the repo matches.
+    pub fn score(&self, repo: &RepoInfo) -> Option<isize> {
+        self.evaluate(repo, None)
+    }
+
+    fn evaluate(
+        &self,
+        repo: &RepoInfo,
+        mut highlights: Option<&mut Vec<Highlight>>,
+    ) -> Option<isize> {
+        let mut score = 0;
+        for filter in self.filters.iter() {
+            let matched = filter
+                .term
+                .matches(repo, &mut score, highlights.as_deref_mut());
+            if matched == filter.negated {
+                return None;
+            }
+        }
+        Some(score)
+    }
+
+    /// Returns `true` if every repo matching this query also matches
+    /// `previous`, e.g. if a term was extended or added.
+    pub fn narrows(&self, previous: &Query) -> bool {
+        let (last, rest) = match previous.filters.split_last() {
+            Some(split) => split,
+            None => return true,
+        };
+        if self.filters.len() < previous.filters.len() {
+            return false;
+        }
+        let same = rest
+            .iter()
+            .zip(self.filters.iter())
+            .all(|(prev, filter)| prev == filter);
+        same && self.filters[rest.len()].narrows(last)
+    }
+}
+
+impl Filter {
+    fn narrows(&self, previous: &Filter) -> bool {
+        if self == previous {
+            return true;
+        }
+        if self.negated || previous.negated {
+            return false;
+        }
+        match (&self.term, &previous.term) {
+            // A fuzzy pattern matches wherever a longer pattern does
+            (Term::Fuzzy(text), Term::Fuzzy(prev))
+            | (Term::Exact(text), Term::Exact(prev))
+            | (Term::Prefix(text), Term::Prefix(prev))
+            | (Term::Remote(text), Term::Remote(prev))
+            | (Term::Host(text), Term::Host(prev)) => text.starts_with(prev.as_str()),
+            _ => false,
+        }
+    }
+}
+
+fn parse_filter(word: &str) -> Option<Filter> {
+    let (negated, word) = match word.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, word),
+    };
+    if word.is_empty() {
+        return None;
+    }
+    let term = if let Some(tag) = word.strip_prefix('#') {
+        Term::Tag(tag.to_string())
+    } else if let Some(exact) = word.strip_prefix('\'') {
+        Term::Exact(exact.to_ascii_lowercase())
+    } else if let Some(prefix) = word.strip_prefix('^') {
+        Term::Prefix(prefix.to_ascii_lowercase())
+    } else if let Some(suffix) = word.strip_suffix('$') {
+        Term::Suffix(suffix.to_ascii_lowercase())
+    } else if let Some(term) = parse_field(word) {
+        term
+    } else if negated {
+        // Like fzf, negated plain terms are exact matches
+        Term::Exact(word.to_ascii_lowercase())
+    } else {
+        Term::Fuzzy(word.to_string())
+    };
+    Some(Filter { term, negated })
+}
+
+fn parse_field(word: &str) -> Option<Term> {
+    let (field, value) = word.split_once(':')?;
+    match field {
+        "branch" => Some(Term::Branch(value.to_string())),
+        "dirty" => match value {
+            "yes" | "y" | "true" => Some(Term::Dirty(true)),
+            "no" | "n" | "false" => Some(Term::Dirty(false)),
+            _ => None,
+        },
+        "remote" => Some(Term::Remote(normalize_url(value))),
+        "host" => Some(Term::Host(value.to_ascii_lowercase())),
+        "owner" => Some(Term::Owner(value.trim_matches('/').to_ascii_lowercase())),
+        "age" => {
+            let (younger, duration) = if let Some(d) = value.strip_prefix('<') {
+                (true, d)
+            } else if let Some(d) = value.strip_prefix('>') {
+                (false, d)
+            } else {
+                (true, value)
+            };
+            parse_hours(duration).map(|hours| Term::Age(younger, hours))
+        }
+        "mine" if value.is_empty() => Some(Term::Mine(None)),
+        "mine" => parse_hours(value).map(|hours| Term::Mine(Some(hours))),
+        _ => None,
+    }
+}
+
+/// Lowercases `url` and replaces `:` with `/`, so that `github.com/org`
+/// also matches scp-like URLs such as `git@github.com:org/repo.git`.
+fn normalize_url(url: &str) -> String {
+    url.to_ascii_lowercase().replace(':', "/")
+}
+
+/// Parses a duration like `7d` into hours.
+pub fn parse_hours(duration: &str) -> Option<i64> {
+    let unit_pos = duration.find(|c: char| !c.is_ascii_digit())?;
+    let (count, unit) = duration.split_at(unit_pos);
+    let count: i64 = count.parse().ok()?;
+    let hours = match unit {
+        "h" => 1,
+        "d" => 24,
+        "w" => 24 * 7,
+        "m" => 24 * 30,
+        "y" => 24 * 365,
+        _ => return None,
+    };
+    Some(count * hours)
+}
+
+impl Term {
+    /// Matches the term against `repo`, adding to the score and the
+    /// highlights, if given.
+    fn matches(
+        &self,
+        repo: &RepoInfo,
+        score: &mut isize,
+        mut highlights: Option<&mut Vec<Highlight>>,
+    ) -> bool {
+        let path = &repo.display;
+        let mut highlight = |pos: usize, len: usize| {
+            if let Some(highlights) = highlights.as_deref_mut() {
+                highlights.push(char_range(path, pos, len));
+            }
+        };
+        match self {
+            Term::Fuzzy(pattern) => match fuzzy_score(pattern, path) {
+                Some((fuzzy_score, fuzzy_highlights)) => {
+                    *score += fuzzy_score;
+                    if let Some(highlights) = highlights {
+                        highlights.extend(fuzzy_highlights);
+                    }
+                    true
+                }
+                None => false,
+            },
+            Term::Exact(text) => match path.to_ascii_lowercase().find(text.as_str()) {
+                Some(pos) => {
+                    highlight(pos, text.len());
+                    true
+                }
+                None => false,
+            },
+            Term::Prefix(prefix) => {
+                let lower = path.to_ascii_lowercase();
+                let name_pos = lower.rfind('/').map(|p| p + 1).unwrap_or(0);
+                if lower.starts_with(prefix.as_str()) {
+                    highlight(0, prefix.len());
+                    true
+                } else if lower[name_pos..].starts_with(prefix.as_str()) {
+                    highlight(name_pos, prefix.len());
+                    true
+                } else {
+                    false
+                }
+            }
+            Term::Suffix(suffix) => {
+                let lower = path.to_ascii_lowercase();
+                if lower.ends_with(suffix.as_str()) {
+                    highlight(lower.len() - suffix.len(), suffix.len());
+                    true
+                } else {
+                    false
+                }
+            }
+            Term::Tag(tag) => repo.tags.iter().any(|t| t == tag),
+            Term::Branch(branch) => repo.branch.as_ref() == Some(branch),
+            Term::Dirty(dirty) => repo.is_dirty() == *dirty,
+            Term::Remote(text) => match &repo.remote_url {
+                Some(url) => normalize_url(url).contains(text.as_str()),
+                None => false,
+            },
+            Term::Host(text) => match &repo.remote {
+                Some(remote) => !remote.host.is_empty() && remote.host.contains(text.as_str()),
+                None => false,
+            },
+            Term::Owner(owner) => match &repo.remote {
+                Some(remote) => {
+                    let lower = remote.owner.to_ascii_lowercase();
+                    lower == *owner || lower.starts_with(&format!("{}/", owner))
+                }
+                None => false,
+            },
+            Term::Age(younger, hours) => {
+                if *younger {
+                    repo.hours_since_last_commit < *hours
+                } else {
+                    repo.hours_since_last_commit > *hours
+                }
+            }
+            Term::Mine(hours) => {
+                let hours = hours
+                    .or_else(|| repo.identity.as_ref().map(|i| i.within_hours))
+                    .or_else(|| parse_hours(DEFAULT_MINE_WITHIN))
+                    .unwrap_or_default();
+                repo.hours_since_my_last_commit() < hours
+            }
+        }
+    }
+}
+
+/// Converts a byte range of `s` into a char range.
+fn char_range(s: &str, byte_pos: usize, byte_len: usize) -> Highlight {
+    let start = s[..byte_pos].chars().count();
+    let len = s[byte_pos..byte_pos + byte_len].chars().count();
+    (start, len)
+}
+
+fn merge_highlights(mut highlights: Vec<Highlight>) -> Vec<Highlight> {
+    highlights.sort();
+    let mut merged: Vec<Highlight> = Vec::with_capacity(highlights.len());
+    for (start, len) in highlights {
+        if let Some(last) = merged.last_mut() {
+            if start <= last.0 + last.1 {
+                last.1 = last.1.max(start + len - last.0);
+                continue;
+            }
+        }
+        merged.push((start, len));
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::RemoteUrl;
+    use crate::repo::Identity;
+
+    fn repo(display: &str) -> RepoInfo {
+        let mut repo = RepoInfo::new(&format!("/nonexistent/{}", display));
+        repo.display = display.to_string();
+        repo
+    }
+
+    fn remote_repo(display: &str, url: &str) -> RepoInfo {
+        let mut repo = repo(display);
+        repo.remote_url = Some(url.to_string());
+        repo.remote = RemoteUrl::parse(url);
+        repo
+    }
+
+    fn matches(query: &str, repo: &RepoInfo) -> bool {
+        Query::parse(query).matches(repo).is_some()
+    }
+
+    fn highlights(query: &str, display: &str) -> Vec<Highlight> {
+        Query::parse(query)
+            .matches(&repo(display))
+            .unwrap()
+            .highlights
+    }
+
+    fn narrows(query: &str, previous: &str) -> bool {
+        Query::parse(query).narrows(&Query::parse(previous))
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        let query = Query::parse("   ");
+        assert!(query.filters.is_empty());
+        assert!(!query.is_fuzzy());
+        assert_eq!(query.score(&repo("~/work/api")), Some(0));
+    }
+
+    #[test]
+    fn fuzzy_terms() {
+        let query = Query::parse("wrk api");
+        assert!(query.is_fuzzy());
+        assert!(query.score(&repo("~/work/api")).unwrap() > 0);
+        assert!(!matches("wrk api", &repo("~/code/api")));
+        assert!(!Query::parse("!api").is_fuzzy());
+    }
+
+    #[test]
+    fn exact_prefix_and_suffix() {
+        assert!(matches("'PAY", &repo("~/work/payments-api")));
+        assert!(!matches("'pya", &repo("~/work/payments-api")));
+        assert_eq!(highlights("'pay", "~/work/payments-api"), vec![(7, 3)]);
+
+        assert!(matches("^~/work", &repo("~/work/payments-api")));
+        assert!(matches("^pay", &repo("~/work/payments-api")));
+        assert!(!matches("^api", &repo("~/work/payments-api")));
+        assert_eq!(highlights("^pay", "~/work/payments-api"), vec![(7, 3)]);
+        assert_eq!(highlights("^~/w", "~/work/payments-api"), vec![(0, 3)]);
+
+        assert!(matches("api$", &repo("~/work/payments-api")));
+        assert!(!matches("pay$", &repo("~/work/payments-api")));
+        assert_eq!(highlights("api$", "~/work/payments-api"), vec![(16, 3)]);
+    }
+
+    #[test]
+    fn highlights_count_chars() {
+        assert_eq!(highlights("'api", "~/wörk/api"), vec![(7, 3)]);
+        // Overlapping ranges are merged
+        assert_eq!(highlights("'pay 'ments", "~/payments"), vec![(2, 8)]);
+    }
+
+    #[test]
+    fn negation() {
+        assert!(matches("!docs", &repo("~/work/api")));
+        assert!(!matches("!docs", &repo("~/apps/api-docs")));
+        // Negated plain terms are exact, not fuzzy
+        assert!(matches("!dcs", &repo("~/apps/api-docs")));
+        assert!(!matches("!^~/apps", &repo("~/apps/api-docs")));
+        assert!(matches("api !#docs", &repo("~/apps/api-docs")));
+        // A lone `!` is ignored
+        assert!(Query::parse("!").filters.is_empty());
+    }
+
+    #[test]
+    fn tags_and_branches() {
+        let mut api = repo("~/work/api");
+        api.tags = vec!["backend".to_string()];
+        api.branch = Some("main".to_string());
+        assert!(matches("#backend", &api));
+        assert!(!matches("#back", &api));
+        assert!(matches("branch:main", &api));
+        assert!(!matches("branch:dev", &api));
+        assert!(!matches("branch:main", &repo("~/detached")));
+    }
+
+    #[test]
+    fn dirty() {
+        let dir = tempfile::tempdir().unwrap();
+        git2::Repository::init(dir.path()).unwrap();
+        let mut repo = RepoInfo::new(dir.path().to_str().unwrap());
+        assert!(matches("dirty:no", &repo));
+        std::fs::write(dir.path().join("new.txt"), "").unwrap();
+        // The state is determined once
+        assert!(matches("dirty:n", &repo));
+        repo = RepoInfo::new(dir.path().to_str().unwrap());
+        assert!(matches("dirty:yes", &repo));
+        assert!(matches("dirty:true", &repo));
+        assert!(!matches("!dirty:y", &repo));
+    }
+
+    #[test]
+    fn remote() {
+        let api = remote_repo("~/work/api", "git@GitHub.com:acme/platform/api.git");
+        assert!(matches("remote:github.com/acme", &api));
+        assert!(matches("remote:GITHUB.COM:ACME", &api));
+        assert!(!matches("remote:gitlab", &api));
+        assert!(!matches("remote:github", &repo("~/no-remote")));
+    }
+
+    #[test]
+    fn host_and_owner() {
+        let api = remote_repo("~/work/api", "git@GitHub.com:Acme/platform/api.git");
+        assert!(matches("host:github", &api));
+        assert!(matches("host:GitHub.com", &api));
+        assert!(!matches("host:gitlab", &api));
+        assert!(matches("owner:acme", &api));
+        assert!(matches("owner:ACME/platform/", &api));
+        assert!(!matches("owner:acm", &api));
+        assert!(!matches("owner:platform", &api));
+        let local = remote_repo("~/work/lib", "/srv/git/lib.git");
+        assert!(!matches("host:git", &local));
+        assert!(matches("owner:git", &local));
+        assert!(!matches("host:git", &repo("~/no-remote")));
+        assert!(!matches("owner:acme", &repo("~/no-remote")));
+    }
+
+    #[test]
+    fn commit_age() {
+        let mut api = repo("~/work/api");
+        api.hours_since_last_commit = 48;
+        assert!(matches("age:<3d", &api));
+        assert!(matches("age:3d", &api));
+        assert!(!matches("age:<1d", &api));
+        assert!(matches("age:>1d", &api));
+        assert!(!matches("age:>1w", &api));
+        // Repos without commits are older than any age
+        assert!(matches("age:>10y", &repo("~/empty")));
+    }
+
+    fn my_repo(hours_ago: i64, within_hours: i64) -> (tempfile::TempDir, RepoInfo) {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = crate::test_util::init(dir.path());
+        crate::test_util::commit_as(&git2_repo, "README", "me@example.com", hours_ago);
+        let mut repo = RepoInfo::new(dir.path().to_str().unwrap());
+        repo.identity = Some(std::sync::Arc::new(Identity {
+            emails: vec!["me@example.com".to_string()],
+            within_hours,
+        }));
+        (dir, repo)
+    }
+
+    #[test]
+    fn mine() {
+        let (_dir, api) = my_repo(48, 30 * 24);
+        assert!(matches("mine:", &api));
+        assert!(matches("mine:3d", &api));
+        assert!(!matches("mine:1d", &api));
+        assert!(matches("!mine:1d", &api));
+        assert!(Query::parse("mine:").is_mine());
+        assert!(Query::parse("api mine:2w").is_mine());
+        assert!(!Query::parse("!mine:").is_mine());
+        assert!(!Query::parse("mine:").is_fuzzy());
+        // The period of the config applies without a duration
+        let (_dir, api) = my_repo(48, 24);
+        assert!(!matches("mine:", &api));
+        // Without commits of yours
+        let (_dir, old) = my_repo(24 * 400, 30 * 24);
+        assert!(!matches("mine:10y", &old));
+        assert!(!matches("mine:", &repo("~/no-repo")));
+    }
+
+    #[test]
+    fn unknown_fields_and_bad_values_are_fuzzy() {
+        let query = Query::parse("age:7x");
+        assert!(query.is_fuzzy());
+        assert!(matches("age:7x", &repo("~/age:7x")));
+        assert!(Query::parse("dirty:maybe").is_fuzzy());
+        assert!(Query::parse("color:red").is_fuzzy());
+        assert!(Query::parse("mine:soon").is_fuzzy());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_hours("12h"), Some(12));
+        assert_eq!(parse_hours("7d"), Some(7 * 24));
+        assert_eq!(parse_hours("2w"), Some(2 * 7 * 24));
+        assert_eq!(parse_hours("6m"), Some(6 * 30 * 24));
+        assert_eq!(parse_hours("1y"), Some(365 * 24));
+        assert_eq!(parse_hours("0d"), Some(0));
+        assert_eq!(parse_hours("7"), None);
+        assert_eq!(parse_hours("d"), None);
+        assert_eq!(parse_hours("7days"), None);
+        assert_eq!(parse_hours("-7d"), None);
+        assert_eq!(parse_hours(""), None);
+    }
+
+    #[test]
+    fn extended_and_added_terms_narrow() {
+        assert!(narrows("ap", ""));
+        assert!(narrows("api", "ap"));
+        assert!(narrows("api", "api"));
+        assert!(narrows("api web", "api"));
+        assert!(narrows("api web", "api we"));
+        assert!(narrows("'paym", "'pay"));
+        assert!(narrows("^~/wo", "^~/w"));
+        assert!(narrows("remote:github.com/acme", "remote:github"));
+        assert!(narrows("#backend api", "#backend"));
+        assert!(narrows("host:github.com", "host:git"));
+    }
+
+    #[test]
+    fn other_changes_do_not_narrow() {
+        assert!(!narrows("ap", "api"));
+        assert!(!narrows("", "api"));
+        assert!(!narrows("web api", "api"));
+        assert!(!narrows("api$", "ap$"));
+        assert!(!narrows("!docs", "!doc"));
+        assert!(!narrows("#backends", "#backend"));
+        assert!(!narrows("age:<1d", "age:<2d"));
+        assert!(!narrows("branch:mainline", "branch:main"));
+        assert!(!narrows("owner:acme", "owner:acm"));
+        assert!(!narrows("host:git", "!host:gitlab"));
+    }
+}
@@ -0,0 +1,121 @@
+//! Ranking model for fuzzy matches.
+//!
+//! Matches within the repository name (the last path component) always
+//! rank above matches spanning parent directories. Equal scores keep the
+//! order of the input list, which is sorted by frecency and commit recency.
+use crate::query::Highlight;
+use sublime_fuzzy::best_match;
+
+/// Added to matches within the repository name.
+const BASENAME_BONUS: isize = 10_000;
+/// Added if the repository name starts with the pattern.
+const BASENAME_PREFIX_BONUS: isize = 1_000;
+/// Added if the repository name equals the pattern.
+const BASENAME_EXACT_BONUS: isize = 1_000;
+
+/// Scores the fuzzy `pattern` against a repo path.
+///
+/// Returns the score and the matched char ranges of `path`.
+pub fn fuzzy_score(pattern: &str, path: &str) -> Option<(isize, Vec<Highlight>)> {
+    let name_pos = path.rfind('/').map(|p| p + 1).unwrap_or(0);
+    let name = &path[name_pos..];
+    if let Some(m) = best_match(pattern, name) {
+        let offset = path[..name_pos].chars().count();
+        let mut score = BASENAME_BONUS + m.score();
+        let lower_name = name.to_lowercase();
+        let lower_pattern = pattern.to_lowercase();
+        if lower_name.starts_with(&lower_pattern) {
+            score += BASENAME_PREFIX_BONUS;
+        }
+        if lower_name == lower_pattern {
+            score += BASENAME_EXACT_BONUS;
+        }
+        let highlights = m
+            .continuous_matches()
+            .into_iter()
+            .map(|(start, len)| (start + offset, len))
+            .collect();
+        return Some((score, highlights));
+    }
+    best_match(pattern, path).map(|m| (m.score(), m.continuous_matches()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fuzzy_score;
+
+    /// Returns `paths` matching `pattern`, best first. Equal scores keep
+    /// the input order, like `search`.
+    fn rank<'a>(pattern: &str, paths: &[&'a str]) -> Vec<&'a str> {
+        let mut matches = paths
+            .iter()
+            .filter_map(|p| fuzzy_score(pattern, p).map(|(score, _)| (score, *p)))
+            .collect::<Vec<_>>();
+        matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        matches.into_iter().map(|(_, p)| p).collect()
+    }
+
+    const CORPUS: &[&str] = &[
+        "~/apps/old/api-docs",
+        "~/work/payments-api",
+        "~/work/api",
+        "~/api-clients/python-sdk",
+        "~/code/git-quick",
+        "~/code/quickcheck",
+        "~/work/infra/terraform",
+        "~/work/terraform-modules",
+        "~/src/github.com/acme/web",
+        "~/src/github.com/acme/website",
+    ];
+
+    #[test]
+    fn exact_name_before_prefix_before_substring() {
+        assert_eq!(
+            rank("api", CORPUS),
+            vec![
+                "~/work/api",
+                "~/apps/old/api-docs",
+                "~/work/payments-api",
+                "~/api-clients/python-sdk",
+            ]
+        );
+    }
+
+    #[test]
+    fn name_before_parent_directory() {
+        assert_eq!(
+            rank("terraform", CORPUS),
+            vec!["~/work/infra/terraform", "~/work/terraform-modules"]
+        );
+        assert_eq!(
+            rank("acme", CORPUS),
+            vec!["~/src/github.com/acme/web", "~/src/github.com/acme/website"]
+        );
+    }
+
+    #[test]
+    fn prefix_of_name() {
+        assert_eq!(
+            rank("quick", CORPUS),
+            vec!["~/code/quickcheck", "~/code/git-quick"]
+        );
+        assert_eq!(
+            rank("web", CORPUS),
+            vec!["~/src/github.com/acme/web", "~/src/github.com/acme/website"]
+        );
+    }
+
+    #[test]
+    fn ties_keep_input_order() {
+        let recent_first = ["~/a/tool", "~/b/tool"];
+        assert_eq!(rank("tool", &recent_first), recent_first);
+        let recent_first = ["~/b/tool", "~/a/tool"];
+        assert_eq!(rank("tool", &recent_first), recent_first);
+    }
+
+    #[test]
+    fn highlights_are_offset_to_name() {
+        let (_, highlights) = fuzzy_score("api", "~/work/payments-api").unwrap();
+        assert_eq!(highlights, vec![(16, 3)]);
+    }
+}
@@ -0,0 +1,334 @@
+//! Moving repositories to the location given by a template based on their
+//! remote URL, e.g. `~/src/{host}/{owner}/{name}`.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{FileContext, Result};
+use crate::history::History;
+use crate::index::Index;
+use crate::path_util::{display_path, expand_tilde, is_within, rebase_path, remove_empty_parents};
+use crate::remote::RemoteUrl;
+use crate::repo::RepoInfo;
+use crate::report::table;
+use crate::tags::Tags;
+
+/// A planned move of a repository.
+pub struct Move {
+    pub from: String,
+    pub to: String,
+    /// Why the repo can't be moved, if it can't.
+    pub problem: Option<String>,
+}
+
+/// Returns the location of a clone of `remote` according to `template`.
+/// Clones of local repositories use `local` as host.
+pub fn target_path(template: &str, remote: &RemoteUrl) -> String {
+    let host = if remote.host.is_empty() {
+        "local"
+    } else {
+        &remote.host
+    };
+    let path = template
+        .replace("{host}", host)
+        .replace("{owner}", &remote.owner)
+        .replace("{name}", &remote.name);
+    // Drops the empty segment of a missing owner
+    let path = Path::new(&expand_tilde(&path))
+        .components()
+        .collect::<PathBuf>();
+    path.display().to_string()
+}
+
+/// Plans moving the `repos` to their location according to `template`.
+/// Repos without a remote URL and repos in place already are left out.
+///
+/// If several repos belong in the same location, the first of `repos` gets
+/// it, i.e. the pinned or most visited one of ranked repos.
+pub fn plan(repos: &[RepoInfo], template: &str) -> Vec<Move> {
+    let mut moves = repos
+        .iter()
+        .filter_map(|repo| {
+            let remote = RemoteUrl::parse(repo.remote_url.as_ref()?)?;
+            let to = target_path(template, &remote);
+            if to == repo.path {
+                return None;
+            }
+            Some(Move {
+                from: repo.path.clone(),
+                to,
+                problem: None,
+            })
+        })
+        .collect::<Vec<_>>();
+    let displays = repos
+        .iter()
+        .map(|repo| (repo.path.as_str(), repo.display.as_str()))
+        .collect::<HashMap<_, _>>();
+    let mut taken = HashMap::new();
+    for idx in 0..moves.len() {
+        let (from, to) = (&moves[idx].from, &moves[idx].to);
+        let outer = moves
+            .iter()
+            .find(|other| other.from != *from && is_within(from, &other.from));
+        let problem = if Path::new(from).join(".git").is_file() {
+            Some("linked worktree or submodule".to_string())
+        } else if is_within(to, from) {
+            Some("location is inside the repository".to_string())
+        } else if let Some(outer) = outer {
+            Some(format!("inside {}", displays[outer.from.as_str()]))
+        } else if let Some(first) = taken.get(to) {
+            Some(format!("location taken by {}", first))
+        } else if Path::new(to).exists() {
+            Some("location exists".to_string())
+        } else {
+            None
+        };
+        if problem.is_none() {
+            taken.insert(to.clone(), displays[from.as_str()]);
+        }
+        moves[idx].problem = problem;
+    }
+    moves.sort_by(|a, b| a.from.cmp(&b.from));
+    moves
+}
+
+/// Formats the planned moves as a table, with paths relative to `basedir`.
+pub fn format_table(moves: &[Move], basedir: &Path) -> Vec<String> {
+    let rows = moves
+        .iter()
+        .map(|m| {
+            vec![
+                display_path(&m.from, basedir),
+                display_path(&m.to, basedir),
+                m.problem.clone().unwrap_or_default(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    table(&["Repository", "New location", "Problem"], &rows)
+}
+
+/// Moves the repos without problems and updates their linked worktrees, the
+/// index, the history and the tags. Directories left empty are removed, up
+/// to `basedir`.
+///
+/// Returns the number of moved repos.
+pub fn apply(moves: &[Move], index: &mut Index) -> Result<usize> {
+    let basedir = index.basedir.clone();
+    let mut history = History::load()?;
+    let mut tags = Tags::load()?;
+    let mut moved = 0;
+    for m in moves.iter().filter(|m| m.problem.is_none()) {
+        let (from, to) = (Path::new(&m.from), Path::new(&m.to));
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).file_context(parent)?;
+        }
+        if let Err(e) = fs::rename(from, to) {
+            // E.g. when moving to another file system
+            eprintln!("git-quick: cannot move {}: {}", m.from, e);
+            remove_empty_parents(to, &basedir);
+            continue;
+        }
+        fix_worktrees(&m.from, &m.to)?;
+        remove_empty_parents(from, &basedir);
+        index.move_repo(&m.from, &m.to)?;
+        history.move_repo(&m.from, &m.to);
+        history.save()?;
+        tags.move_repo(&m.from, &m.to);
+        tags.save()?;
+        eprintln!(
+            "Moved {} to {}",
+            display_path(&m.from, &basedir),
+            display_path(&m.to, &basedir)
+        );
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+/// Points the linked worktrees of the repo moved from `from` to `to` at its
+/// new location.
+fn fix_worktrees(from: &str, to: &str) -> Result<()> {
+    let worktrees = Path::new(to).join(".git").join("worktrees");
+    let entries = match fs::read_dir(&worktrees) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(()),
+    };
+    for entry in entries.flatten() {
+        let admin_dir = entry.path();
+        // Path of the `.git` file of the worktree
+        let gitdir_file = admin_dir.join("gitdir");
+        let mut gitdir = match fs::read_to_string(&gitdir_file) {
+            Ok(gitdir) => gitdir.trim().to_string(),
+            Err(_) => continue,
+        };
+        // Worktrees inside the repo were moved along
+        if let Some(moved) = rebase_path(&gitdir, from, to) {
+            fs::write(&gitdir_file, format!("{}\n", moved)).file_context(&gitdir_file)?;
+            gitdir = moved;
+        }
+        let dot_git = Path::new(&gitdir);
+        if dot_git.is_file() {
+            fs::write(dot_git, format!("gitdir: {}\n", admin_dir.display()))
+                .file_context(dot_git)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn remote(url: &str) -> RemoteUrl {
+        RemoteUrl::parse(url).unwrap()
+    }
+
+    fn repo(path: &Path, url: Option<&str>) -> RepoInfo {
+        let path = path.display().to_string();
+        let mut repo = RepoInfo::new(&path);
+        repo.display = Path::new(&path)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        repo.remote_url = url.map(String::from);
+        repo
+    }
+
+    fn problems(moves: &[Move], root: &Path) -> Vec<(String, String, Option<String>)> {
+        moves
+            .iter()
+            .map(|m| {
+                (
+                    display_path(&m.from, root),
+                    display_path(&m.to, root),
+                    m.problem.clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expand_the_template() {
+        let api = remote("git@github.com:acme/platform/api.git");
+        assert_eq!(
+            target_path("/src/{host}/{owner}/{name}", &api),
+            "/src/github.com/acme/platform/api"
+        );
+        assert_eq!(target_path("/src/{name}", &api), "/src/api");
+        assert_eq!(
+            target_path("/src/{owner}-{name}/", &api),
+            "/src/acme/platform-api"
+        );
+        // Without owner and host
+        assert_eq!(
+            target_path(
+                "/src/{host}/{owner}/{name}",
+                &remote("https://example.com/api")
+            ),
+            "/src/example.com/api"
+        );
+        assert_eq!(
+            target_path("/src/{host}/{owner}/{name}", &remote("/srv/git/api.git")),
+            "/src/local/git/api"
+        );
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            target_path("~/src/{name}", &api),
+            home.join("src/api").display().to_string()
+        );
+    }
+
+    #[test]
+    fn plan_moves() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let template = format!("{}/src/{{host}}/{{owner}}/{{name}}", root.display());
+        let repos = [
+            repo(&root.join("api"), Some("git@github.com:acme/api.git")),
+            repo(&root.join("notes"), None),
+            repo(
+                &root.join("src/github.com/acme/web"),
+                Some("https://github.com/acme/web"),
+            ),
+            repo(&root.join("old/api"), Some("https://github.com/acme/api")),
+            repo(&root.join("docs"), Some("https://github.com/acme/docs")),
+            repo(&root.join("lib"), Some("https://github.com/acme/lib")),
+        ];
+        fs::create_dir_all(root.join("src/github.com/acme/docs")).unwrap();
+        let moves = plan(&repos, &template);
+        assert_eq!(
+            problems(&moves, root),
+            vec![
+                (
+                    "api".to_string(),
+                    "src/github.com/acme/api".to_string(),
+                    None
+                ),
+                (
+                    "docs".to_string(),
+                    "src/github.com/acme/docs".to_string(),
+                    Some("location exists".to_string())
+                ),
+                (
+                    "lib".to_string(),
+                    "src/github.com/acme/lib".to_string(),
+                    None
+                ),
+                (
+                    "old/api".to_string(),
+                    "src/github.com/acme/api".to_string(),
+                    Some("location taken by api".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_nested_repos() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let main = test_util::init(&root.join("api"));
+        test_util::commit(&main, "README");
+        // A submodule or linked worktree has a `.git` file
+        fs::create_dir_all(root.join("api/wt")).unwrap();
+        fs::write(root.join("api/wt/.git"), "gitdir: ../.git/worktrees/wt\n").unwrap();
+        let repos = [
+            repo(&root.join("api"), Some("https://github.com/acme/api")),
+            repo(&root.join("api/wt"), Some("https://github.com/acme/api-wt")),
+            repo(
+                &root.join("api/vendor/lib"),
+                Some("https://github.com/acme/lib"),
+            ),
+            repo(&root.join("web"), Some("https://github.com/acme/web")),
+        ];
+        let template = format!("{}/src/{{name}}", root.display());
+        let moves = plan(&repos, &template);
+        assert_eq!(
+            problems(&moves, root),
+            vec![
+                ("api".to_string(), "src/api".to_string(), None),
+                (
+                    "api/vendor/lib".to_string(),
+                    "src/lib".to_string(),
+                    Some("inside api".to_string())
+                ),
+                (
+                    "api/wt".to_string(),
+                    "src/api-wt".to_string(),
+                    Some("linked worktree or submodule".to_string())
+                ),
+                ("web".to_string(), "src/web".to_string(), None),
+            ]
+        );
+        // Moving a repo into itself
+        let template = format!("{}/web/{{name}}", root.display());
+        let moves = plan(&repos[3..], &template);
+        assert_eq!(
+            moves[0].problem.as_deref(),
+            Some("location is inside the repository")
+        );
+    }
+}
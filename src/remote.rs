@@ -0,0 +1,374 @@
+//! Remote URLs of repositories and clones sharing them.
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::repo::{self, RepoInfo};
+
+const SCHEMES: &[&str] = &[
+    "git+ssh://",
+    "ssh://",
+    "https://",
+    "http://",
+    "git://",
+    "file://",
+];
+
+/// Splits `url` into host and path, leaving out the scheme, user, port and
+/// `.git` suffix. The host is empty for local paths.
+fn split_url(url: &str) -> (&str, &str) {
+    let url = url.trim();
+    let lower = url.to_ascii_lowercase();
+    let (rest, has_scheme) = match SCHEMES.iter().find(|s| lower.starts_with(*s)) {
+        Some(scheme) => (&url[scheme.len()..], true),
+        None => (url, false),
+    };
+    let mut host_end = rest.find('/').unwrap_or(rest.len());
+    // scp-like syntax, e.g. `git@github.com:acme/api.git`
+    let scp_colon = rest[..host_end].find(':').filter(|_| !has_scheme);
+    match scp_colon {
+        Some(colon) => host_end = colon,
+        None if !has_scheme => host_end = 0,
+        None => {}
+    }
+    let (host, path) = rest.split_at(host_end);
+    // Drop user and port
+    let host = host.rsplit('@').next().unwrap_or(host);
+    let host = match host.find(':') {
+        Some(colon) if host[colon + 1..].chars().all(|c| c.is_ascii_digit()) => &host[..colon],
+        _ => host,
+    };
+    let path = path.trim_start_matches(':').trim_end_matches('/');
+    let path = path
+        .strip_suffix(".git")
+        .unwrap_or(path)
+        .trim_end_matches('/');
+    (host, path)
+}
+
+/// Hosts which ignore the case of owner and repository names.
+const CASE_INSENSITIVE_HOSTS: &[&str] = &["github.com", "gitlab.com", "bitbucket.org"];
+
+/// Returns `url` in a form which is equal for all URLs of the same
+/// repository, e.g. `github.com/acme/api` for both
+/// `git@GitHub.com:Acme/api.git` and `https://github.com/acme/api`. Hosts
+/// are case-insensitive, paths only on the `CASE_INSENSITIVE_HOSTS`, as
+/// other servers may tell `Api` from `api`. Relative local paths like
+/// `../api` are resolved against `base`, the repository having the remote.
+pub fn canonical_url(url: &str, base: &Path) -> String {
+    let (host, path) = split_url(url);
+    if host.is_empty() {
+        return normalize(&base.join(path)).display().to_string();
+    }
+    let host = host.to_ascii_lowercase();
+    let path = path.trim_start_matches('/');
+    if CASE_INSENSITIVE_HOSTS.contains(&host.as_str()) {
+        format!("{}/{}", host, path.to_lowercase())
+    } else {
+        format!("{}/{}", host, path)
+    }
+}
+
+/// Removes `.` and `..` components from `path` without touching the file
+/// system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Host, owner and name of a remote repository.
+#[derive(Clone)]
+pub struct RemoteUrl {
+    /// Lowercase host name, empty for local paths.
+    pub host: String,
+    /// User or organization, including subgroups like `group/subgroup`. For
+    /// local paths, the name of the parent directory.
+    pub owner: String,
+    pub name: String,
+}
+
+impl RemoteUrl {
+    /// Parses URLs like `https://github.com/acme/api.git`,
+    /// `git@github.com:acme/api`, `file:///srv/git/api.git` or local paths.
+    pub fn parse(url: &str) -> Option<RemoteUrl> {
+        let (host, path) = split_url(url);
+        let mut segments = path
+            .split('/')
+            .filter(|s| !s.is_empty() && *s != "." && *s != "..")
+            .collect::<Vec<_>>();
+        let name = segments.pop()?.to_string();
+        let owner = if host.is_empty() {
+            segments.pop().unwrap_or_default().to_string()
+        } else {
+            segments.join("/")
+        };
+        Some(RemoteUrl {
+            host: host.to_ascii_lowercase(),
+            owner,
+            name,
+        })
+    }
+
+    /// Returns the path of a clone relative to the clone root, i.e.
+    /// `<host>/<owner>/<name>`. Clones of local repositories are placed
+    /// below `local`.
+    pub fn clone_path(&self) -> PathBuf {
+        let host = if self.host.is_empty() {
+            "local"
+        } else {
+            &self.host
+        };
+        Path::new(host).join(&self.owner).join(&self.name)
+    }
+}
+
+/// Returns the repository at `target`, the path of a new clone, if it was
+/// cloned before. Anything else at `target` is an error, so it is never
+/// cloned over.
+pub fn existing_clone(target: &Path) -> Result<Option<String>> {
+    if !target.exists() {
+        return Ok(None);
+    }
+    let target_str = target.display().to_string();
+    repo::repo_root(&target_str)
+        .filter(|root| Path::new(root) == target)
+        .map(Some)
+        .ok_or(Error::NotARepository(target_str))
+}
+
+/// Clones of the same repository.
+pub struct DuplicateGroup {
+    /// Canonical URL shared by the clones, see `canonical_url`.
+    pub url: String,
+    /// Indices of the clones in the list of repos.
+    pub repos: Vec<usize>,
+}
+
+/// Groups the repos sharing the URL of their `origin` remote, see
+/// `RepoInfo::remote_url`. Other remotes, like the `upstream` of a fork, are
+/// not compared. Only groups of two or more repos are returned, ordered by
+/// URL.
+pub fn find_duplicates(repos: &[RepoInfo]) -> Vec<DuplicateGroup> {
+    let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (idx, repo) in repos.iter().enumerate() {
+        if let Some(url) = &repo.remote_url {
+            let url = canonical_url(url, Path::new(&repo.path));
+            groups.entry(url).or_default().push(idx);
+        }
+    }
+    groups
+        .into_iter()
+        .filter(|(_, repos)| repos.len() > 1)
+        .map(|(url, repos)| DuplicateGroup { url, repos })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo(path: &str, url: Option<&str>) -> RepoInfo {
+        let mut repo = RepoInfo::new(path);
+        repo.remote_url = url.map(String::from);
+        repo
+    }
+
+    #[test]
+    fn split_urls() {
+        for url in [
+            "ssh://git@github.com/acme/api.git",
+            "ssh://git@github.com:22/acme/api",
+            "git+ssh://github.com/acme/api/",
+            "https://user@github.com/acme/api.git",
+            "https://github.com:443/acme/api",
+            "  git://github.com/acme/api.git/\n",
+        ] {
+            assert_eq!(split_url(url), ("github.com", "/acme/api"), "{}", url);
+        }
+        assert_eq!(
+            split_url("git@github.com:acme/api.git"),
+            ("github.com", "acme/api")
+        );
+        assert_eq!(split_url("file:///srv/git/api.git"), ("", "/srv/git/api"));
+        assert_eq!(split_url("../lib"), ("", "../lib"));
+        // A colon after a slash is part of a local path
+        assert_eq!(split_url("./a:b"), ("", "./a:b"));
+    }
+
+    fn parse(url: &str) -> (String, String, String, PathBuf) {
+        let remote = RemoteUrl::parse(url).unwrap();
+        let path = remote.clone_path();
+        (remote.host, remote.owner, remote.name, path)
+    }
+
+    #[test]
+    fn parse_urls() {
+        for url in [
+            "git@github.com:acme/api.git",
+            "ssh://git@GitHub.com:2222/acme/api",
+            "https://user@github.com/acme/api.git/",
+            "git://github.com/acme/api",
+        ] {
+            assert_eq!(
+                parse(url),
+                (
+                    "github.com".to_string(),
+                    "acme".to_string(),
+                    "api".to_string(),
+                    PathBuf::from("github.com/acme/api")
+                ),
+                "{}",
+                url
+            );
+        }
+        // Subgroups are part of the owner
+        let (_, owner, name, path) = parse("git@gitlab.com:acme/platform/tools/api.git");
+        assert_eq!(
+            (owner.as_str(), name.as_str()),
+            ("acme/platform/tools", "api")
+        );
+        assert_eq!(path, PathBuf::from("gitlab.com/acme/platform/tools/api"));
+        // Repos without owner
+        assert_eq!(
+            parse("https://example.com/api.git").3,
+            PathBuf::from("example.com/api")
+        );
+    }
+
+    #[test]
+    fn parse_local_paths() {
+        assert_eq!(
+            parse("/srv/git/api.git"),
+            (
+                String::new(),
+                "git".to_string(),
+                "api".to_string(),
+                PathBuf::from("local/git/api")
+            )
+        );
+        assert_eq!(
+            parse("file:///srv/git/api/").3,
+            PathBuf::from("local/git/api")
+        );
+        assert_eq!(parse("../api").3, PathBuf::from("local/api"));
+        assert!(RemoteUrl::parse("").is_none());
+        assert!(RemoteUrl::parse("https://github.com/").is_none());
+    }
+
+    #[test]
+    fn clones_are_not_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("github.com/acme/api");
+        assert_eq!(existing_clone(&target).unwrap(), None);
+        std::fs::create_dir_all(&target).unwrap();
+        assert!(matches!(
+            existing_clone(&target),
+            Err(Error::NotARepository(_))
+        ));
+        // A directory inside another repository isn't a clone either
+        crate::test_util::init(dir.path());
+        assert!(matches!(
+            existing_clone(&target),
+            Err(Error::NotARepository(_))
+        ));
+        crate::test_util::init(&target);
+        assert_eq!(
+            existing_clone(&target).unwrap(),
+            Some(target.display().to_string())
+        );
+    }
+
+    #[test]
+    fn canonical_urls() {
+        let base = Path::new("/work/api");
+        for url in [
+            "git@GitHub.com:acme/api.git",
+            "ssh://git@github.com:22/acme/api",
+            "https://github.com/acme/api.git",
+            "HTTPS://GITHUB.COM/acme/api/",
+        ] {
+            assert_eq!(canonical_url(url, base), "github.com/acme/api", "{}", url);
+        }
+        // Paths are case-sensitive, except on well-known hosts
+        assert_eq!(
+            canonical_url("https://github.com/Acme/API", base),
+            "github.com/acme/api"
+        );
+        assert_eq!(
+            canonical_url("git@GitLab.com:Acme/Tools/API.git", base),
+            "gitlab.com/acme/tools/api"
+        );
+        assert_eq!(
+            canonical_url("https://git.example.com/Acme/API", base),
+            "git.example.com/Acme/API"
+        );
+        assert_eq!(canonical_url("/srv/Git/API", base), "/srv/Git/API");
+        assert_eq!(canonical_url("../lib", base), "/work/lib");
+        assert_eq!(canonical_url("./lib.git/", base), "/work/api/lib");
+        assert_eq!(canonical_url("/srv/git/lib.git", base), "/srv/git/lib");
+        assert_eq!(canonical_url("file:///srv/git/lib", base), "/srv/git/lib");
+    }
+
+    #[test]
+    fn duplicates_share_the_origin() {
+        let repos = [
+            repo("/work/api", Some("git@github.com:acme/api.git")),
+            repo("/work/web", Some("https://github.com/acme/web")),
+            repo("/tmp/api", Some("https://github.com/acme/api")),
+            // A fork is no clone of its upstream
+            repo("/old/api", Some("ssh://git@bitbucket.org/fork/api.git")),
+            repo("/work/lib", Some("../shared")),
+            repo("/old/lib", Some("../shared")),
+            repo("/work/shared", None),
+            repo("/notes", None),
+            repo("/work/web2", Some("https://github.com/Acme/web")),
+            repo("/new/api", Some("https://github.com/acme/api.git")),
+        ];
+        let groups = find_duplicates(&repos);
+        let groups = groups
+            .iter()
+            .map(|group| (group.url.as_str(), group.repos.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            vec![
+                ("github.com/acme/api", vec![0, 2, 9]),
+                ("github.com/acme/web", vec![1, 8]),
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicates_are_ordered_by_url() {
+        let repos = [
+            repo("/a/web", Some("https://github.com/acme/web")),
+            repo("/a/api", Some("https://github.com/acme/api")),
+            repo("/b/web", Some("git@github.com:acme/web")),
+            repo("/b/api", Some("git@github.com:acme/api")),
+            repo("/b/lib", Some("/srv/lib")),
+            repo("/a/lib", Some("../../srv/lib/")),
+        ];
+        let groups = find_duplicates(&repos);
+        let groups = groups
+            .iter()
+            .map(|group| (group.url.as_str(), group.repos.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            vec![
+                ("/srv/lib", vec![4, 5]),
+                ("github.com/acme/api", vec![1, 3]),
+                ("github.com/acme/web", vec![0, 2]),
+            ]
+        );
+    }
+}
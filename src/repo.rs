@@ -1,5 +1,10 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::query::parse_hours;
+use crate::remote::RemoteUrl;
 use chrono::{TimeZone, Utc};
-use git2;
+use serde::Serialize;
+use std::sync::{Arc, OnceLock};
 
 /// Returns the age of the last commit in hours.
 pub fn num_hours_since_last_commit(git2_repo: &git2::Repository) -> i64 {
@@ -13,5 +18,382 @@ pub fn num_hours_since_last_commit(git2_repo: &git2::Repository) -> i64 {
             }
         }
     }
-    i64::max_value()
+    i64::MAX
+}
+
+/// Returns the age in hours of the last commit authored with one of the
+/// lowercase `emails` or the configured `user.email` of the repo, if there is
+/// one in the last `MINE_MAX_HOURS` hours. The age is that of the commit
+/// time, which the walk is sorted by, so a rebased commit counts as recent.
+fn num_hours_since_last_commit_by(
+    git2_repo: &git2::Repository,
+    emails: &[String],
+) -> Result<i64, git2::Error> {
+    let mut emails = emails.to_vec();
+    if let Ok(email) = git2_repo.config()?.get_string("user.email") {
+        emails.push(email.to_lowercase());
+    }
+    let mut walk = git2_repo.revwalk()?;
+    walk.set_sorting(git2::Sort::TIME);
+    for branch in git2_repo.branches(Some(git2::BranchType::Local))? {
+        if let Some(tip) = branch?.0.get().target() {
+            walk.push(tip)?;
+        }
+    }
+    if let Some(oid) = git2_repo.head().ok().and_then(|head| head.target()) {
+        walk.push(oid)?;
+    }
+    let now = Utc::now().timestamp();
+    for oid in walk {
+        let commit = git2_repo.find_commit(oid?)?;
+        let hours = (now - commit.time().seconds()) / 3600;
+        if hours > MINE_MAX_HOURS {
+            break;
+        }
+        let email = commit.author().email().unwrap_or("").to_lowercase();
+        if emails.contains(&email) {
+            return Ok(hours);
+        }
+    }
+    Ok(i64::MAX)
+}
+
+/// Returns the name of the checked out branch, `None` for a detached or
+/// unborn HEAD.
+pub fn current_branch(git2_repo: &git2::Repository) -> Option<String> {
+    let head = git2_repo.head().ok()?;
+    if head.is_branch() {
+        head.shorthand().map(|s| s.to_string())
+    } else {
+        None
+    }
+}
+
+/// Returns `true` if the working tree has modified, staged or untracked files.
+pub fn is_dirty(git2_repo: &git2::Repository) -> bool {
+    let mut opts = git2::StatusOptions::new();
+    opts.include_untracked(true).include_ignored(false);
+    match git2_repo.statuses(Some(&mut opts)) {
+        Ok(statuses) => !statuses.is_empty(),
+        Err(_) => false,
+    }
+}
+
+/// Returns the working directory of the repository containing `path`.
+pub fn repo_root(path: &str) -> Option<String> {
+    let git2_repo = git2::Repository::discover(path).ok()?;
+    let workdir = git2_repo.workdir()?.to_str()?;
+    Some(workdir.trim_end_matches('/').to_string())
+}
+
+/// Returns the URL of the `origin` remote, or of the first remote if there
+/// is no `origin`.
+pub fn remote_url(git2_repo: &git2::Repository) -> Option<String> {
+    let remote = match git2_repo.find_remote("origin") {
+        Ok(remote) => remote,
+        Err(_) => {
+            let names = git2_repo.remotes().ok()?;
+            let name = names.get(0)?;
+            git2_repo.find_remote(name).ok()?
+        }
+    };
+    remote.url().map(|url| url.to_string())
+}
+
+/// Returns the names and URLs of all remotes, `origin` first.
+pub fn remotes(git2_repo: &git2::Repository) -> Vec<(String, String)> {
+    let mut remotes = Vec::new();
+    if let Ok(names) = git2_repo.remotes() {
+        let mut names = names.iter().flatten().collect::<Vec<_>>();
+        names.sort_by_key(|name| *name != "origin");
+        for name in names {
+            if let Some(url) = git2_repo
+                .find_remote(name)
+                .ok()
+                .and_then(|r| r.url().map(String::from))
+            {
+                remotes.push((name.to_string(), url));
+            }
+        }
+    }
+    remotes
+}
+
+/// Formats an age in hours like `3d ago`, using the units of `age:`
+/// queries.
+pub fn format_age(hours: i64) -> String {
+    match hours {
+        i64::MAX => "never".to_string(),
+        h if h < 24 => format!("{}h ago", h.max(0)),
+        h if h < 24 * 14 => format!("{}d ago", h / 24),
+        h if h < 24 * 60 => format!("{}w ago", h / (24 * 7)),
+        h if h < 24 * 365 => format!("{}m ago", h / (24 * 30)),
+        h => format!("{}y ago", h / (24 * 365)),
+    }
+}
+
+/// Default period of `mine:` queries, see `query::parse_hours`.
+pub const DEFAULT_MINE_WITHIN: &str = "30d";
+/// How far back the history is searched for your last commit, in hours.
+const MINE_MAX_HOURS: i64 = 24 * 365;
+
+/// Your commit identities, for `mine:` queries.
+pub struct Identity {
+    /// Lowercase author emails.
+    pub emails: Vec<String>,
+    /// Default period of `mine:` queries, in hours.
+    pub within_hours: i64,
+}
+
+impl Identity {
+    /// Reads the emails of the `[mine]` config and `user.email` of the
+    /// global git config. An invalid `within` period is reported and replaced
+    /// by the default, like an invalid config file.
+    pub fn load(config: &Config) -> Identity {
+        let mut emails = config.mine.emails.clone();
+        if let Ok(email) = git2::Config::open_default().and_then(|c| c.get_string("user.email")) {
+            emails.push(email);
+        }
+        let within_hours = within_hours(config).unwrap_or_else(|e| {
+            eprintln!("git-quick: {}, using {}", e, DEFAULT_MINE_WITHIN);
+            parse_hours(DEFAULT_MINE_WITHIN).unwrap_or_default()
+        });
+        Identity {
+            emails: emails.iter().map(|e| e.to_lowercase()).collect(),
+            within_hours,
+        }
+    }
+}
+
+/// Returns the `within` period of the `[mine]` config in hours, the default
+/// if it is not set.
+fn within_hours(config: &Config) -> crate::error::Result<i64> {
+    match &config.mine.within {
+        Some(within) => parse_hours(within).ok_or_else(|| {
+            Error::InvalidMine(format!("within = `{}` is not a period like `2w`", within))
+        }),
+        None => Ok(parse_hours(DEFAULT_MINE_WITHIN).unwrap_or_default()),
+    }
+}
+
+/// A known repository together with the metadata used for searching.
+#[derive(Clone)]
+pub struct RepoInfo {
+    pub path: String,
+    /// Path relative to the scan root or the home directory, for display and
+    /// matching.
+    pub display: String,
+    pub remote_url: Option<String>,
+    /// Host, owner and name parsed from `remote_url`.
+    pub remote: Option<RemoteUrl>,
+    pub branch: Option<String>,
+    /// Working tree state, determined on first use.
+    dirty: OnceLock<bool>,
+    /// Age of the last commit in hours, `i64::MAX` if unknown.
+    pub hours_since_last_commit: i64,
+    /// Your identities, if set by the index.
+    pub identity: Option<Arc<Identity>>,
+    /// Age of your last commit in hours, determined on first use.
+    my_last_commit: OnceLock<i64>,
+    pub tags: Vec<String>,
+    /// Pinned repos are listed above the ranked results.
+    pub pinned: bool,
+    /// Number of clones of the same remote repository, including this one,
+    /// or 0 if there are no others. See `remote::find_duplicates`.
+    pub clones: usize,
+}
+
+impl RepoInfo {
+    /// Reads the metadata of the repository at `path`.
+    pub fn new(path: &str) -> RepoInfo {
+        let mut info = RepoInfo {
+            path: path.to_string(),
+            display: path.to_string(),
+            remote_url: None,
+            remote: None,
+            branch: None,
+            dirty: OnceLock::new(),
+            hours_since_last_commit: i64::MAX,
+            identity: None,
+            my_last_commit: OnceLock::new(),
+            tags: Vec::new(),
+            pinned: false,
+            clones: 0,
+        };
+        if let Ok(git2_repo) = git2::Repository::open(path) {
+            info.remote_url = remote_url(&git2_repo);
+            info.remote = info.remote_url.as_deref().and_then(RemoteUrl::parse);
+            info.branch = current_branch(&git2_repo);
+            info.hours_since_last_commit = num_hours_since_last_commit(&git2_repo);
+        }
+        info
+    }
+
+    /// Returns the text shown next to the path in the list, if any: the
+    /// `owner/name` of the remote repository, with the host if `with_host`
+    /// is set, and the number of clones.
+    pub fn label(&self, with_host: bool) -> String {
+        let mut parts = Vec::new();
+        if let Some(remote) = self.remote.as_ref().filter(|r| !r.host.is_empty()) {
+            let host = Some(remote.host.as_str()).filter(|_| with_host);
+            let owner = Some(remote.owner.as_str()).filter(|o| !o.is_empty());
+            let name = host
+                .into_iter()
+                .chain(owner)
+                .chain(Some(remote.name.as_str()))
+                .collect::<Vec<_>>()
+                .join("/");
+            // Left out if the path shows it already
+            if !self.display.to_lowercase().ends_with(&name.to_lowercase()) {
+                parts.push(name);
+            }
+        }
+        if self.clones > 1 {
+            parts.push(format!("{} clones", self.clones));
+        }
+        parts.join(", ")
+    }
+
+    /// Returns the age in hours of the last commit on a local branch or HEAD
+    /// authored with one of your emails or the `user.email` of the repo,
+    /// `i64::MAX` if there is none in the last year.
+    pub fn hours_since_my_last_commit(&self) -> i64 {
+        *self.my_last_commit.get_or_init(|| {
+            let emails = self
+                .identity
+                .as_ref()
+                .map(|identity| identity.emails.as_slice())
+                .unwrap_or_default();
+            match git2::Repository::open(&self.path) {
+                Ok(git2_repo) => {
+                    num_hours_since_last_commit_by(&git2_repo, emails).unwrap_or(i64::MAX)
+                }
+                Err(_) => i64::MAX,
+            }
+        })
+    }
+
+    /// Returns `true` if the working tree has uncommitted changes.
+    pub fn is_dirty(&self) -> bool {
+        *self
+            .dirty
+            .get_or_init(|| match git2::Repository::open(&self.path) {
+                Ok(git2_repo) => is_dirty(&git2_repo),
+                Err(_) => false,
+            })
+    }
+}
+
+/// Summary of the state of a repository.
+#[derive(Serialize)]
+pub struct RepoStatus {
+    pub path: String,
+    pub branch: Option<String>,
+    pub dirty: bool,
+    pub hours_since_last_commit: Option<i64>,
+}
+
+/// Collects the status of the repository at `path`.
+pub fn status(path: &str) -> Result<RepoStatus, git2::Error> {
+    let git2_repo = git2::Repository::open(path)?;
+    let age_h = num_hours_since_last_commit(&git2_repo);
+    Ok(RepoStatus {
+        path: path.to_string(),
+        branch: current_branch(&git2_repo),
+        dirty: is_dirty(&git2_repo),
+        hours_since_last_commit: if age_h == i64::MAX { None } else { Some(age_h) },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn my_last_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = test_util::init(dir.path());
+        let emails = ["me@example.com".to_string()];
+        assert_eq!(
+            num_hours_since_last_commit_by(&git2_repo, &emails).unwrap(),
+            i64::MAX
+        );
+        test_util::commit_as(&git2_repo, "README", "Me@Example.com", 50);
+        test_util::commit_as(&git2_repo, "README", "other@example.com", 2);
+        assert_eq!(
+            num_hours_since_last_commit_by(&git2_repo, &emails).unwrap(),
+            50
+        );
+        assert_eq!(
+            num_hours_since_last_commit_by(&git2_repo, &[]).unwrap(),
+            i64::MAX
+        );
+        // The `user.email` of the repo counts, too
+        git2_repo
+            .config()
+            .unwrap()
+            .set_str("user.email", "Other@example.com")
+            .unwrap();
+        assert_eq!(num_hours_since_last_commit_by(&git2_repo, &[]).unwrap(), 2);
+    }
+
+    #[test]
+    fn my_commits_on_other_branches() {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = test_util::init(dir.path());
+        let emails = ["me@example.com".to_string()];
+        let first = test_util::commit_as(&git2_repo, "README", "other@example.com", 30);
+        test_util::commit_as(&git2_repo, "README", "me@example.com", 20);
+        // My commit is only on the branch, not on the detached HEAD
+        git2_repo.set_head_detached(first).unwrap();
+        assert_eq!(
+            num_hours_since_last_commit_by(&git2_repo, &emails).unwrap(),
+            20
+        );
+    }
+
+    #[test]
+    fn rebased_commits_are_recent() {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = test_util::init(dir.path());
+        let emails = ["me@example.com".to_string()];
+        test_util::commit_as(&git2_repo, "README", "me@example.com", MINE_MAX_HOURS + 24);
+        // Rebasing keeps the author time, but sets the commit time
+        let commit = git2_repo.head().unwrap().peel_to_commit().unwrap();
+        let committer = git2::Signature::now("Me", "me@example.com").unwrap();
+        commit
+            .amend(Some("HEAD"), None, Some(&committer), None, None, None)
+            .unwrap();
+        assert_eq!(
+            num_hours_since_last_commit_by(&git2_repo, &emails).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn old_commits_are_not_searched() {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = test_util::init(dir.path());
+        let emails = ["me@example.com".to_string()];
+        test_util::commit_as(&git2_repo, "README", "me@example.com", MINE_MAX_HOURS + 24);
+        assert_eq!(
+            num_hours_since_last_commit_by(&git2_repo, &emails).unwrap(),
+            i64::MAX
+        );
+    }
+
+    #[test]
+    fn identity() {
+        let config: Config =
+            toml::from_str("[mine]\nemails = [\"Me@Example.com\"]\nwithin = \"2w\"").unwrap();
+        let identity = Identity::load(&config);
+        assert_eq!(identity.emails[0], "me@example.com");
+        assert_eq!(identity.within_hours, 14 * 24);
+        let identity = Identity::load(&Config::default());
+        assert_eq!(identity.within_hours, 30 * 24);
+        let config: Config = toml::from_str("[mine]\nwithin = \"2 weeks\"").unwrap();
+        assert!(matches!(within_hours(&config), Err(Error::InvalidMine(_))));
+        assert_eq!(Identity::load(&config).within_hours, 30 * 24);
+    }
 }
@@ -0,0 +1,440 @@
+//! Health report across all repositories, listing work which only exists
+//! locally: uncommitted changes, unpushed commits, branches without upstream,
+//! stashes and the like.
+use console::{measure_text_width, Style};
+use git2::{BranchType, Oid, Repository};
+use serde::Serialize;
+
+use crate::repo::{self, num_hours_since_last_commit};
+
+/// Hours per month, as used by `age:` queries.
+const MONTH_HOURS: i64 = 24 * 30;
+
+const COLUMNS: [&str; 8] = [
+    "Repository",
+    "Changes",
+    "Unpushed",
+    "No upstream",
+    "Merged",
+    "Detached",
+    "Stashes",
+    "Last commit",
+];
+
+/// Output format of the report.
+pub enum Format {
+    Table,
+    Json,
+    Markdown,
+}
+
+/// A local branch with commits missing in its upstream branch.
+#[derive(Serialize)]
+pub struct Unpushed {
+    pub branch: String,
+    pub commits: usize,
+}
+
+/// Findings for one repository.
+#[derive(Serialize)]
+pub struct RepoReport {
+    pub path: String,
+    #[serde(skip)]
+    pub display: String,
+    /// Modified, staged or untracked files.
+    pub uncommitted: bool,
+    pub unpushed: Vec<Unpushed>,
+    /// Local branches without an upstream branch.
+    pub no_upstream: Vec<String>,
+    /// Local branches already merged into the default branch.
+    pub merged: Vec<String>,
+    pub detached: bool,
+    pub stashes: usize,
+    /// Age of the last commit in months, if older than the threshold.
+    pub stale_months: Option<i64>,
+    /// Reason why the repository could not be checked.
+    pub error: Option<String>,
+}
+
+impl RepoReport {
+    /// Checks the repository at `path`. Repos without commits in
+    /// `stale_months` months are reported as stale.
+    pub fn new(path: &str, display: &str, stale_months: i64) -> RepoReport {
+        let mut report = RepoReport {
+            path: path.to_string(),
+            display: display.to_string(),
+            uncommitted: false,
+            unpushed: Vec::new(),
+            no_upstream: Vec::new(),
+            merged: Vec::new(),
+            detached: false,
+            stashes: 0,
+            stale_months: None,
+            error: None,
+        };
+        let result = Repository::open(path)
+            .and_then(|mut git2_repo| report.check(&mut git2_repo, stale_months));
+        if let Err(e) = result {
+            report.error = Some(e.message().to_string());
+        }
+        report
+    }
+
+    fn check(&mut self, git2_repo: &mut Repository, stale_months: i64) -> Result<(), git2::Error> {
+        self.uncommitted = repo::is_dirty(git2_repo);
+        self.detached = git2_repo.head_detached().unwrap_or(false);
+        let current = repo::current_branch(git2_repo);
+        let default = default_branch(git2_repo);
+        for branch in git2_repo.branches(Some(BranchType::Local))? {
+            let (branch, _) = branch?;
+            let (name, tip) = match (branch.name()?, branch.get().target()) {
+                (Some(name), Some(tip)) => (name.to_string(), tip),
+                _ => continue,
+            };
+            match branch.upstream() {
+                Ok(upstream) => {
+                    if let Some(upstream_tip) = upstream.get().target() {
+                        let (ahead, _) = git2_repo.graph_ahead_behind(tip, upstream_tip)?;
+                        if ahead > 0 {
+                            self.unpushed.push(Unpushed {
+                                branch: name.clone(),
+                                commits: ahead,
+                            });
+                        }
+                    }
+                }
+                Err(_) => self.no_upstream.push(name.clone()),
+            }
+            if let Some((ref default_name, default_tip)) = default {
+                let is_merged =
+                    tip == default_tip || git2_repo.graph_descendant_of(default_tip, tip)?;
+                if is_merged && *default_name != name && current.as_ref() != Some(&name) {
+                    self.merged.push(name);
+                }
+            }
+        }
+        git2_repo.stash_foreach(|_, _, _| {
+            self.stashes += 1;
+            true
+        })?;
+        let age_h = num_hours_since_last_commit(git2_repo);
+        if age_h != i64::MAX && age_h >= stale_months * MONTH_HOURS {
+            self.stale_months = Some(age_h / MONTH_HOURS);
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if nothing needs attention.
+    pub fn is_clean(&self) -> bool {
+        !self.uncommitted
+            && self.unpushed.is_empty()
+            && self.no_upstream.is_empty()
+            && self.merged.is_empty()
+            && !self.detached
+            && self.stashes == 0
+            && self.stale_months.is_none()
+            && self.error.is_none()
+    }
+
+    /// Returns the table cells of the report, see `COLUMNS`.
+    fn cells(&self) -> Vec<String> {
+        let flag = |set: bool| if set { "yes" } else { "" }.to_string();
+        let unpushed = self
+            .unpushed
+            .iter()
+            .map(|u| format!("{} +{}", u.branch, u.commits))
+            .collect::<Vec<_>>();
+        let last_commit = match (&self.error, self.stale_months) {
+            (Some(e), _) => format!("error: {}", e),
+            (None, Some(months)) => format!("{} months ago", months),
+            (None, None) => String::new(),
+        };
+        vec![
+            self.display.clone(),
+            flag(self.uncommitted),
+            unpushed.join(", "),
+            self.no_upstream.join(", "),
+            self.merged.join(", "),
+            flag(self.detached),
+            if self.stashes > 0 {
+                self.stashes.to_string()
+            } else {
+                String::new()
+            },
+            last_commit,
+        ]
+    }
+}
+
+/// Returns the name and tip of the default branch: the target of
+/// `origin/HEAD`, or else the local `main` or `master` branch.
+fn default_branch(git2_repo: &Repository) -> Option<(String, Oid)> {
+    if let Ok(head) = git2_repo.find_reference("refs/remotes/origin/HEAD") {
+        if let Ok(target) = head.resolve() {
+            let name = target
+                .shorthand()?
+                .trim_start_matches("origin/")
+                .to_string();
+            return Some((name, target.target()?));
+        }
+    }
+    ["main", "master"].iter().find_map(|name| {
+        let branch = git2_repo.find_branch(name, BranchType::Local).ok()?;
+        Some((name.to_string(), branch.get().target()?))
+    })
+}
+
+/// Returns the summary line, counting the repos of every finding.
+fn summary(reports: &[RepoReport], total: usize) -> String {
+    let count = |f: &dyn Fn(&RepoReport) -> bool| reports.iter().filter(|r| f(r)).count();
+    let findings = [
+        (count(&|r| r.uncommitted), "uncommitted changes"),
+        (count(&|r| !r.unpushed.is_empty()), "unpushed commits"),
+        (
+            count(&|r| !r.no_upstream.is_empty()),
+            "branches without upstream",
+        ),
+        (count(&|r| !r.merged.is_empty()), "merged branches"),
+        (count(&|r| r.detached), "detached HEAD"),
+        (count(&|r| r.stashes > 0), "stashes"),
+        (count(&|r| r.stale_months.is_some()), "no recent commits"),
+        (count(&|r| r.error.is_some()), "errors"),
+    ];
+    let details = findings
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, what)| format!("{} with {}", n, what))
+        .collect::<Vec<_>>();
+    if details.is_empty() {
+        format!("All {} repositories are clean.", total)
+    } else {
+        format!(
+            "{} of {} repositories need attention: {}.",
+            reports.len(),
+            total,
+            details.join(", ")
+        )
+    }
+}
+
+/// Formats the reports of the repositories needing attention. `total` is
+/// the number of checked repositories.
+pub fn format(reports: &[RepoReport], total: usize, format: &Format) -> String {
+    match format {
+        Format::Json => serde_json::to_string_pretty(reports).unwrap_or_default(),
+        Format::Markdown => {
+            let mut lines = vec![
+                format!("| {} |", COLUMNS.join(" | ")),
+                format!("|{}", "---|".repeat(COLUMNS.len())),
+            ];
+            for report in reports {
+                let cells = report
+                    .cells()
+                    .iter()
+                    .map(|cell| cell.replace('|', "\\|"))
+                    .collect::<Vec<_>>();
+                lines.push(format!("| {} |", cells.join(" | ")));
+            }
+            lines.push(String::new());
+            lines.push(summary(reports, total));
+            lines.join("\n")
+        }
+        Format::Table => {
+            let rows = reports.iter().map(RepoReport::cells).collect::<Vec<_>>();
+            // Columns without any finding are left out
+            let shown = (0..COLUMNS.len())
+                .filter(|&col| col == 0 || rows.iter().any(|row| !row[col].is_empty()))
+                .collect::<Vec<_>>();
+            let columns = shown.iter().map(|&col| COLUMNS[col]).collect::<Vec<_>>();
+            let rows = rows
+                .into_iter()
+                .map(|row| shown.iter().map(|&col| row[col].clone()).collect())
+                .collect::<Vec<_>>();
+            let mut lines = Vec::new();
+            if !rows.is_empty() {
+                lines.extend(table(&columns, &rows));
+                lines.push(String::new());
+            }
+            lines.push(summary(reports, total));
+            lines.join("\n")
+        }
+    }
+}
+
+/// Formats rows of cells as a table with aligned columns and a bold header.
+pub fn table(columns: &[&str], rows: &[Vec<String>]) -> Vec<String> {
+    let widths = (0..columns.len())
+        .map(|col| {
+            rows.iter()
+                .map(|row| measure_text_width(&row[col]))
+                .chain(Some(measure_text_width(columns[col])))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let format_row = |cells: &[String], style: &Style| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| {
+                let padding = width - measure_text_width(cell);
+                format!("{}{}", style.apply_to(cell), " ".repeat(padding))
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        line.trim_end().to_string()
+    };
+    let header = columns.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+    let mut lines = vec![format_row(&header, &Style::new().bold())];
+    lines.extend(rows.iter().map(|row| format_row(row, &Style::new())));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{commit, commit_as, head_branch, init, push};
+    use git2::Signature;
+
+    fn report(path: &str) -> RepoReport {
+        RepoReport {
+            path: path.to_string(),
+            display: path.to_string(),
+            uncommitted: false,
+            unpushed: Vec::new(),
+            no_upstream: Vec::new(),
+            merged: Vec::new(),
+            detached: false,
+            stashes: 0,
+            stale_months: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn pushed_repo_is_clean() {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = init(dir.path());
+        let tip = commit(&git2_repo, "README");
+        push(&git2_repo, &head_branch(&git2_repo), tip);
+        let report = RepoReport::new(dir.path().to_str().unwrap(), "~/api", 6);
+        assert!(report.is_clean(), "{:?}", report.cells());
+    }
+
+    #[test]
+    fn finds_local_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut git2_repo = init(dir.path());
+        let pushed = commit(&git2_repo, "README");
+        let main = head_branch(&git2_repo);
+        push(&git2_repo, &main, pushed);
+        git2_repo
+            .branch("merged", &git2_repo.find_commit(pushed).unwrap(), false)
+            .unwrap();
+        commit(&git2_repo, "README");
+        commit(&git2_repo, "src.rs");
+        std::fs::write(dir.path().join("README"), "wip").unwrap();
+        let sig = Signature::now("Me", "me@example.com").unwrap();
+        git2_repo.stash_save(&sig, "wip", None).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+        let report = RepoReport::new(dir.path().to_str().unwrap(), "~/api", 6);
+        assert!(report.uncommitted);
+        assert_eq!(report.unpushed.len(), 1);
+        assert_eq!(report.unpushed[0].branch, main);
+        assert_eq!(report.unpushed[0].commits, 2);
+        assert_eq!(report.no_upstream, vec!["merged"]);
+        assert_eq!(report.merged, vec!["merged"]);
+        assert_eq!(report.stashes, 1);
+        assert!(!report.detached);
+        assert_eq!(report.stale_months, None);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn detached_and_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = init(dir.path());
+        let tip = commit_as(&git2_repo, "README", "me@example.com", 13 * MONTH_HOURS + 1);
+        push(&git2_repo, &head_branch(&git2_repo), tip);
+        git2_repo.set_head_detached(tip).unwrap();
+        let report = RepoReport::new(dir.path().to_str().unwrap(), "~/api", 6);
+        assert!(report.detached);
+        assert_eq!(report.stale_months, Some(13));
+        let report = RepoReport::new(dir.path().to_str().unwrap(), "~/api", 14);
+        assert_eq!(report.stale_months, None);
+    }
+
+    #[test]
+    fn missing_repo_is_an_error() {
+        let report = RepoReport::new("/nonexistent/api", "~/api", 6);
+        assert!(report.error.is_some());
+        assert!(!report.is_clean());
+        assert!(report.cells()[7].starts_with("error: "));
+    }
+
+    #[test]
+    fn summary_counts_repos_per_finding() {
+        assert_eq!(summary(&[], 3), "All 3 repositories are clean.");
+        let mut api = report("~/api");
+        api.uncommitted = true;
+        api.stashes = 2;
+        let mut web = report("~/web");
+        web.uncommitted = true;
+        assert_eq!(
+            summary(&[api, web], 5),
+            "2 of 5 repositories need attention: 2 with uncommitted changes, 1 with stashes."
+        );
+    }
+
+    #[test]
+    fn table_shows_columns_with_findings() {
+        let mut api = report("~/api");
+        api.unpushed.push(Unpushed {
+            branch: "main".to_string(),
+            commits: 3,
+        });
+        let mut web = report("~/web");
+        web.stale_months = Some(7);
+        let output = format(&[api, web], 4, &Format::Table);
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "Repository  Unpushed  Last commit");
+        assert_eq!(lines[1], "~/api       main +3");
+        assert_eq!(lines[2], "~/web                 7 months ago");
+        assert_eq!(lines[3], "");
+        assert_eq!(
+            format(&[], 4, &Format::Table),
+            "All 4 repositories are clean."
+        );
+    }
+
+    #[test]
+    fn markdown_and_json() {
+        let mut api = report("/home/me/a|b");
+        api.display = "~/a|b".to_string();
+        api.detached = true;
+        let markdown = format(&[api], 1, &Format::Markdown);
+        let lines = markdown.lines().collect::<Vec<_>>();
+        assert_eq!(lines[1], "|---|---|---|---|---|---|---|---|");
+        assert_eq!(lines[2], "| ~/a\\|b |  |  |  |  | yes |  |  |");
+        let mut api = report("/home/me/api");
+        api.merged.push("old".to_string());
+        let json: serde_json::Value =
+            serde_json::from_str(&format(&[api], 1, &Format::Json)).unwrap();
+        assert_eq!(json[0]["path"], "/home/me/api");
+        assert_eq!(json[0]["merged"][0], "old");
+        assert!(json[0].get("display").is_none());
+    }
+
+    #[test]
+    fn aligned_table() {
+        let rows = vec![
+            vec!["~/api".to_string(), "3".to_string()],
+            vec!["~/wörk".to_string(), String::new()],
+        ];
+        assert_eq!(
+            table(&["Repo", "Count"], &rows),
+            vec!["Repo    Count", "~/api   3", "~/wörk"]
+        );
+    }
+}
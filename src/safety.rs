@@ -0,0 +1,349 @@
+//! Checks whether a repository can be deleted without losing work.
+//!
+//! A repository is safe to delete if every commit of its local branches,
+//! stashes and a detached HEAD is reachable from a remote-tracking ref and
+//! the working tree has neither changes nor untracked files. Ignored files
+//! don't count, unless they contain another repository.
+use std::fs;
+use std::path::Path;
+
+use console::Style;
+use git2::{BranchType, Oid, Repository, Status, StatusOptions};
+
+/// Maximal number of commits listed per ref.
+const MAX_COMMITS: usize = 5;
+
+/// A local ref with commits which are not on any remote.
+pub struct UnsyncedRef {
+    /// E.g. `branch main` or `stash@{0}`.
+    pub name: String,
+    /// Short ids and summaries, newest first.
+    pub commits: Vec<String>,
+}
+
+/// Everything which would be lost by deleting a repository.
+pub struct SafetyReport {
+    pub unsynced: Vec<UnsyncedRef>,
+    /// Modified, staged or deleted files.
+    pub changed: Vec<String>,
+    pub untracked: Vec<String>,
+    pub ignored: Vec<String>,
+    /// Repositories in untracked or ignored directories, e.g. `vendor/lib/`.
+    pub nested: Vec<String>,
+    /// Whether the repository has any remote-tracking refs.
+    pub has_remote_refs: bool,
+}
+
+impl SafetyReport {
+    /// Checks the repository at `path`.
+    pub fn new(path: &str) -> Result<SafetyReport, git2::Error> {
+        let mut git2_repo = Repository::open(path)?;
+        let mut remote_tips = Vec::new();
+        for branch in git2_repo.branches(Some(BranchType::Remote))? {
+            remote_tips.extend(branch?.0.get().target());
+        }
+        let mut report = SafetyReport {
+            unsynced: Vec::new(),
+            changed: Vec::new(),
+            untracked: Vec::new(),
+            ignored: Vec::new(),
+            nested: Vec::new(),
+            has_remote_refs: !remote_tips.is_empty(),
+        };
+        let mut tips = Vec::new();
+        for branch in git2_repo.branches(Some(BranchType::Local))? {
+            let (branch, _) = branch?;
+            if let (Some(name), Some(tip)) = (branch.name()?, branch.get().target()) {
+                tips.push((format!("branch {}", name), tip));
+            }
+        }
+        let branch_tips = tips.iter().map(|(_, tip)| *tip).collect::<Vec<_>>();
+        if git2_repo.head_detached().unwrap_or(false) {
+            if let Some(tip) = git2_repo.head()?.target() {
+                tips.push(("detached HEAD".to_string(), tip));
+            }
+        }
+        for (name, tip) in tips {
+            let commits = unsynced_commits(&git2_repo, tip, &remote_tips)?;
+            if !commits.is_empty() {
+                report.unsynced.push(UnsyncedRef { name, commits });
+            }
+        }
+        let mut stashes = Vec::new();
+        git2_repo.stash_foreach(|index, message, &oid| {
+            stashes.push((index, message.to_string(), oid));
+            true
+        })?;
+        // Stashes are always local. Their base commits are listed, too, if
+        // they are on neither a branch nor a remote.
+        for (index, message, oid) in stashes {
+            let stash = git2_repo.find_commit(oid)?;
+            let mut commits = vec![format!("{} {}", short_id(oid), message)];
+            let hidden = remote_tips
+                .iter()
+                .chain(&branch_tips)
+                .cloned()
+                .collect::<Vec<_>>();
+            commits.extend(unsynced_commits(&git2_repo, stash.parent_id(0)?, &hidden)?);
+            report.unsynced.push(UnsyncedRef {
+                name: format!("stash@{{{}}}", index),
+                commits,
+            });
+        }
+
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true)
+            .include_ignored(true)
+            .recurse_untracked_dirs(false)
+            .recurse_ignored_dirs(false);
+        let workdir = git2_repo.workdir().map(Path::to_path_buf);
+        for entry in git2_repo.statuses(Some(&mut opts))?.iter() {
+            let path = entry.path().unwrap_or("?").to_string();
+            let status = entry.status();
+            // Directories are not recursed into, so look for repos in them
+            if let (Some(workdir), true) = (&workdir, path.ends_with('/')) {
+                if status.intersects(Status::IGNORED | Status::WT_NEW) {
+                    find_nested_repos(&workdir.join(&path), &path, &mut report.nested);
+                }
+            }
+            if status.contains(Status::IGNORED) {
+                report.ignored.push(path);
+            } else if status.contains(Status::WT_NEW) {
+                report.untracked.push(path);
+            } else {
+                report.changed.push(path);
+            }
+        }
+        report.nested.sort();
+        Ok(report)
+    }
+
+    /// Returns `true` if no commits, repositories or files other than
+    /// ignored files would be lost.
+    pub fn is_safe(&self) -> bool {
+        self.unsynced.is_empty()
+            && self.changed.is_empty()
+            && self.untracked.is_empty()
+            && self.nested.is_empty()
+    }
+
+    /// Explains what would be lost, followed by the verdict for the
+    /// repository shown as `display`.
+    pub fn lines(&self, display: &str) -> Vec<String> {
+        let heading = Style::new().bold();
+        let mut lines = Vec::new();
+        if !self.has_remote_refs && !self.unsynced.is_empty() {
+            lines.push("The repository has no remote-tracking branches.".to_string());
+        }
+        for unsynced in &self.unsynced {
+            let count = unsynced.commits.len();
+            lines.push(format!(
+                "{}: {} commit{} not on any remote",
+                heading.apply_to(&unsynced.name),
+                if count > MAX_COMMITS {
+                    format!("{}+", MAX_COMMITS)
+                } else {
+                    count.to_string()
+                },
+                if count == 1 { "" } else { "s" }
+            ));
+            for commit in unsynced.commits.iter().take(MAX_COMMITS) {
+                lines.push(format!("  {}", commit));
+            }
+        }
+        for (title, files) in [
+            ("Uncommitted changes", &self.changed),
+            ("Untracked files", &self.untracked),
+            ("Ignored files, which would be lost, too", &self.ignored),
+            ("Repositories inside, which are not checked", &self.nested),
+        ]
+        .iter()
+        {
+            if !files.is_empty() {
+                lines.push(format!("{}:", heading.apply_to(title)));
+                lines.extend(files.iter().map(|file| format!("  {}", file)));
+            }
+        }
+        if self.has_remote_refs && (!self.unsynced.is_empty() || self.is_safe()) {
+            lines.push("Remote-tracking branches are as of the last fetch.".to_string());
+        }
+        let verdict = if self.is_safe() {
+            Style::new().green().apply_to("safe to delete")
+        } else {
+            Style::new().red().apply_to("NOT safe to delete")
+        };
+        lines.push(format!("{} is {}.", display, verdict));
+        lines
+    }
+}
+
+/// Returns the commits reachable from `tip` but not from the `hidden`
+/// commits. At most one more than `MAX_COMMITS` commits are returned.
+fn unsynced_commits(
+    git2_repo: &Repository,
+    tip: Oid,
+    hidden: &[Oid],
+) -> Result<Vec<String>, git2::Error> {
+    let mut walk = git2_repo.revwalk()?;
+    walk.push(tip)?;
+    for &oid in hidden {
+        walk.hide(oid)?;
+    }
+    let mut commits = Vec::new();
+    for oid in walk.take(MAX_COMMITS + 1) {
+        let commit = git2_repo.find_commit(oid?)?;
+        commits.push(format!(
+            "{} {}",
+            short_id(commit.id()),
+            commit.summary().unwrap_or("")
+        ));
+    }
+    Ok(commits)
+}
+
+/// Adds `dir` to `nested` if it is a repository, or else the repositories
+/// in its subdirectories. `name` is the path of `dir` in the working tree.
+fn find_nested_repos(dir: &Path, name: &str, nested: &mut Vec<String>) {
+    if dir.join(".git").exists() {
+        nested.push(name.to_string());
+        return;
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        // Symlinks are not followed
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            let name = format!("{}{}/", name, entry.file_name().to_string_lossy());
+            find_nested_repos(&entry.path(), &name, nested);
+        }
+    }
+}
+
+fn short_id(oid: Oid) -> String {
+    oid.to_string()[..7].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{commit, head_branch, init, push};
+    use git2::Signature;
+
+    /// Creates a repo with a pushed commit.
+    fn pushed_repo(dir: &Path) -> Repository {
+        let git2_repo = init(dir);
+        let tip = commit(&git2_repo, "README");
+        push(&git2_repo, &head_branch(&git2_repo), tip);
+        git2_repo
+    }
+
+    fn check(dir: &Path) -> SafetyReport {
+        SafetyReport::new(dir.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn pushed_repo_is_safe() {
+        let dir = tempfile::tempdir().unwrap();
+        pushed_repo(dir.path());
+        let report = check(dir.path());
+        assert!(report.is_safe());
+        assert!(report.has_remote_refs);
+        assert_eq!(
+            report.lines("~/api").last().unwrap(),
+            "~/api is safe to delete."
+        );
+    }
+
+    #[test]
+    fn unpushed_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = pushed_repo(dir.path());
+        commit(&git2_repo, "README");
+        let report = check(dir.path());
+        assert!(!report.is_safe());
+        assert_eq!(report.unsynced.len(), 1);
+        let branch = format!("branch {}", head_branch(&git2_repo));
+        assert_eq!(report.unsynced[0].name, branch);
+        assert_eq!(report.unsynced[0].commits.len(), 1);
+        let lines = report.lines("~/api");
+        assert_eq!(lines[0], format!("{}: 1 commit not on any remote", branch));
+        assert_eq!(lines.last().unwrap(), "~/api is NOT safe to delete.");
+    }
+
+    #[test]
+    fn detached_head_and_stashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut git2_repo = pushed_repo(dir.path());
+        let tip = commit(&git2_repo, "README");
+        git2_repo.set_head_detached(tip).unwrap();
+        std::fs::write(dir.path().join("README"), "wip").unwrap();
+        let sig = Signature::now("Me", "me@example.com").unwrap();
+        git2_repo.stash_save(&sig, "wip", None).unwrap();
+        let report = check(dir.path());
+        let names = report
+            .unsynced
+            .iter()
+            .map(|u| u.name.as_str())
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"detached HEAD"));
+        assert!(names.contains(&"stash@{0}"));
+        assert!(!report.is_safe());
+    }
+
+    #[test]
+    fn no_remote() {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = Repository::init(dir.path()).unwrap();
+        commit(&git2_repo, "README");
+        let report = check(dir.path());
+        assert!(!report.has_remote_refs);
+        assert!(!report.is_safe());
+        let lines = report.lines("~/api");
+        assert_eq!(lines[0], "The repository has no remote-tracking branches.");
+    }
+
+    #[test]
+    fn changed_and_untracked_files() {
+        let dir = tempfile::tempdir().unwrap();
+        pushed_repo(dir.path());
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+        let report = check(dir.path());
+        assert_eq!(report.untracked, vec!["notes.txt"]);
+        assert!(!report.is_safe());
+        std::fs::write(dir.path().join("README"), "changed").unwrap();
+        assert_eq!(check(dir.path()).changed, vec!["README"]);
+    }
+
+    #[test]
+    fn ignored_files_are_safe() {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = init(dir.path());
+        std::fs::write(dir.path().join(".gitignore"), "target/\n").unwrap();
+        let tip = commit(&git2_repo, ".gitignore");
+        push(&git2_repo, &head_branch(&git2_repo), tip);
+        std::fs::create_dir_all(dir.path().join("target/debug")).unwrap();
+        std::fs::write(dir.path().join("target/debug/api"), "").unwrap();
+        let report = check(dir.path());
+        assert_eq!(report.ignored, vec!["target/"]);
+        assert!(report.is_safe());
+    }
+
+    #[test]
+    fn nested_repos_are_not_safe() {
+        let dir = tempfile::tempdir().unwrap();
+        let git2_repo = init(dir.path());
+        std::fs::write(dir.path().join(".gitignore"), "vendor/\n").unwrap();
+        let tip = commit(&git2_repo, ".gitignore");
+        push(&git2_repo, &head_branch(&git2_repo), tip);
+        Repository::init(dir.path().join("vendor/foo")).unwrap();
+        Repository::init(dir.path().join("libs/bar")).unwrap();
+        std::fs::write(dir.path().join("vendor/notes.txt"), "").unwrap();
+        let report = check(dir.path());
+        assert_eq!(report.nested, vec!["libs/bar/", "vendor/foo/"]);
+        assert!(!report.is_safe());
+        let lines = report.lines("~/api");
+        assert!(lines.contains(&"  vendor/foo/".to_string()));
+    }
+}
@@ -0,0 +1,300 @@
+//! Full-screen terminal output on the alternate screen.
+//!
+//! Frames are drawn as a list of lines. Only lines which changed since the
+//! previous frame are written to the terminal.
+use std::io::{self, Stderr, Write};
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+use crate::query::Highlight;
+use console::{measure_text_width, Style};
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::{cursor, queue, style::Print, terminal};
+use unicode_width::UnicodeWidthChar;
+
+/// Marker for text removed by `truncate_middle`.
+const ELLIPSIS: char = '…';
+
+/// Whether a `Screen` currently owns the terminal.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static PANIC_HOOK: Once = Once::new();
+
+/// Terminal in raw mode showing the alternate screen, with mouse reporting.
+///
+/// The original screen and terminal mode are restored on drop, and by a
+/// panic hook before the panic message is printed.
+pub struct Screen {
+    out: Stderr,
+    /// Lines of the previous frame.
+    lines: Vec<String>,
+    rows: usize,
+    cols: usize,
+}
+
+impl Screen {
+    pub fn new() -> io::Result<Screen> {
+        PANIC_HOOK.call_once(|| {
+            let previous = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if ACTIVE.swap(false, Ordering::SeqCst) {
+                    restore_terminal(&mut io::stderr());
+                }
+                previous(info);
+            }));
+        });
+        terminal::enable_raw_mode()?;
+        ACTIVE.store(true, Ordering::SeqCst);
+        // From here on, errors restore the terminal when `screen` is dropped
+        let mut screen = Screen {
+            out: io::stderr(),
+            lines: Vec::new(),
+            rows: 0,
+            cols: 0,
+        };
+        queue!(
+            screen.out,
+            terminal::EnterAlternateScreen,
+            EnableMouseCapture,
+            cursor::Hide,
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+        screen.out.flush()?;
+        let (cols, rows) = terminal::size()?;
+        screen.rows = rows as usize;
+        screen.cols = cols as usize;
+        Ok(screen)
+    }
+
+    /// Returns the terminal size as (rows, columns).
+    pub fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    /// Sets the new terminal size and forces a full redraw.
+    pub fn resize(&mut self, cols: u16, rows: u16) -> io::Result<()> {
+        self.rows = rows as usize;
+        self.cols = cols as usize;
+        self.lines.clear();
+        queue!(self.out, terminal::Clear(terminal::ClearType::All))?;
+        Ok(())
+    }
+
+    /// Draws a frame. Lines must not be wider than the screen.
+    ///
+    /// The cursor is shown at `cursor` as (row, column), if given.
+    pub fn draw(&mut self, lines: &[String], cursor: Option<(usize, usize)>) -> io::Result<()> {
+        queue!(self.out, cursor::Hide)?;
+        for row in 0..self.rows {
+            let line = lines.get(row).map(String::as_str).unwrap_or("");
+            // The screen is blank where the previous frame had no lines
+            if self.lines.get(row).map(String::as_str).unwrap_or("") == line {
+                continue;
+            }
+            queue!(
+                self.out,
+                cursor::MoveTo(0, row as u16),
+                Print(line),
+                terminal::Clear(terminal::ClearType::UntilNewLine)
+            )?;
+        }
+        self.lines = lines.iter().take(self.rows).cloned().collect();
+        if let Some((row, col)) = cursor {
+            queue!(
+                self.out,
+                cursor::MoveTo(col as u16, row as u16),
+                cursor::Show
+            )?;
+        }
+        self.out.flush()
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        if ACTIVE.swap(false, Ordering::SeqCst) {
+            restore_terminal(&mut self.out);
+        }
+    }
+}
+
+/// Leaves the alternate screen and raw mode. Errors are ignored, as there is
+/// nothing left to do about them.
+fn restore_terminal(out: &mut Stderr) {
+    let _ = queue!(
+        out,
+        DisableMouseCapture,
+        cursor::Show,
+        terminal::LeaveAlternateScreen
+    );
+    let _ = out.flush();
+    let _ = terminal::disable_raw_mode();
+}
+
+/// Returns the display width of `s`, ignoring ANSI codes.
+pub fn display_width(s: &str) -> usize {
+    measure_text_width(s)
+}
+
+/// Shortens `text` to `max_width` columns by replacing its middle with an
+/// ellipsis. Highlighted char ranges are adjusted accordingly.
+pub fn truncate_middle(
+    text: &str,
+    highlights: &[Highlight],
+    max_width: usize,
+) -> (String, Vec<Highlight>) {
+    let chars = text.chars().collect::<Vec<_>>();
+    let widths = chars
+        .iter()
+        .map(|c| c.width().unwrap_or(0))
+        .collect::<Vec<_>>();
+    if widths.iter().sum::<usize>() <= max_width {
+        return (text.to_string(), highlights.to_vec());
+    }
+    if max_width == 0 {
+        return (String::new(), Vec::new());
+    }
+    // Keep more of the tail, which contains the repo name
+    let available = max_width - 1;
+    let mut head_width = available / 3;
+    let mut head = 0;
+    while head < chars.len() && widths[head] <= head_width {
+        head_width -= widths[head];
+        head += 1;
+    }
+    let mut tail_width = available - (available / 3 - head_width);
+    let mut tail = chars.len();
+    while tail > head && widths[tail - 1] <= tail_width {
+        tail_width -= widths[tail - 1];
+        tail -= 1;
+    }
+    let mut truncated = chars[..head].iter().collect::<String>();
+    truncated.push(ELLIPSIS);
+    truncated.extend(&chars[tail..]);
+
+    // Map char positions of the original text to the truncated text
+    let removed = tail - head;
+    let mut marked = vec![false; head + 1 + chars.len() - tail];
+    for &(start, len) in highlights {
+        for i in start..(start + len).min(chars.len()) {
+            if i < head {
+                marked[i] = true;
+            } else if i >= tail {
+                marked[i - removed + 1] = true;
+            } else {
+                marked[head] = true;
+            }
+        }
+    }
+    (truncated, ranges(&marked))
+}
+
+/// Converts a list of flags into ranges of consecutive set flags.
+fn ranges(marked: &[bool]) -> Vec<Highlight> {
+    let mut result: Vec<Highlight> = Vec::new();
+    for (i, _) in marked.iter().enumerate().filter(|(_, m)| **m) {
+        match result.last_mut() {
+            Some(last) if last.0 + last.1 == i => last.1 += 1,
+            _ => result.push((i, 1)),
+        }
+    }
+    result
+}
+
+/// Returns `text` with the given char ranges styled.
+pub fn apply_highlights(text: &str, highlights: &[Highlight], style: &Style) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut outstr = String::new();
+    let mut idx = 0;
+    for &(match_idx, len) in highlights {
+        outstr.extend(&chars[idx..match_idx]);
+        idx = match_idx + len;
+        let matched = chars[match_idx..idx].iter().collect::<String>();
+        outstr.push_str(&format!("{}", style.apply_to(matched)));
+    }
+    outstr.extend(&chars[idx..]);
+    outstr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn truncate(text: &str, highlights: &[Highlight], width: usize) -> (String, Vec<Highlight>) {
+        let (truncated, highlights) = truncate_middle(text, highlights, width);
+        assert!(display_width(&truncated) <= width, "{} too wide", truncated);
+        (truncated, highlights)
+    }
+
+    #[test]
+    fn short_text_is_unchanged() {
+        let text = "~/work/api";
+        assert_eq!(
+            truncate(text, &[(7, 3)], 10),
+            (text.to_string(), vec![(7, 3)])
+        );
+        assert_eq!(truncate(text, &[], 80).0, text);
+        assert_eq!(truncate(text, &[], 0).0, "");
+        assert_eq!(truncate(text, &[], 1).0, "…");
+    }
+
+    #[test]
+    fn keeps_more_of_the_tail() {
+        assert_eq!(truncate("~/work/payments-api", &[], 10).0, "~/w…ts-api");
+        assert_eq!(
+            truncate("~/work/payments-api", &[], 18).0,
+            "~/wor…payments-api"
+        );
+        assert_eq!(truncate("abcdef", &[], 2).0, "…f");
+    }
+
+    #[test]
+    fn wide_chars() {
+        assert_eq!(truncate("日本語のリポジトリ", &[], 7).0, "日…トリ");
+        // A wide char which doesn't fit leaves a column unused
+        assert_eq!(truncate("ab日本", &[], 5).0, "a…本");
+        assert_eq!(truncate("ab日本", &[], 4).0, "a…本");
+        assert_eq!(truncate("日本", &[], 3).0, "…本");
+        assert_eq!(truncate("日本", &[], 2).0, "…");
+    }
+
+    #[test]
+    fn highlights_are_remapped() {
+        let text = "~/work/payments-api";
+        // In the tail
+        assert_eq!(truncate(text, &[(16, 3)], 10).1, vec![(7, 3)]);
+        // In the head
+        assert_eq!(truncate(text, &[(0, 2)], 10).1, vec![(0, 2)]);
+        // Removed chars highlight the ellipsis
+        assert_eq!(truncate(text, &[(7, 3)], 10).1, vec![(3, 1)]);
+        // Ranges spanning the ellipsis
+        assert_eq!(truncate(text, &[(1, 4)], 10).1, vec![(1, 3)]);
+        assert_eq!(truncate(text, &[(10, 5)], 10).1, vec![(3, 3)]);
+        assert_eq!(truncate(text, &[(0, 19)], 10).1, vec![(0, 10)]);
+        // After wide chars
+        assert_eq!(truncate("日本語のリポジトリ", &[(8, 1)], 7).1, vec![(3, 1)]);
+    }
+
+    #[test]
+    fn highlights_beyond_the_text_are_ignored() {
+        assert_eq!(
+            truncate("~/work/payments-api", &[(17, 5)], 10).1,
+            vec![(8, 2)]
+        );
+        assert!(truncate("~/work/payments-api", &[(40, 2)], 10).1.is_empty());
+    }
+
+    #[test]
+    fn consecutive_ranges() {
+        assert_eq!(ranges(&[]), vec![]);
+        assert_eq!(ranges(&[true, true, false, true]), vec![(0, 2), (3, 1)]);
+        assert_eq!(ranges(&[false, false, true]), vec![(2, 1)]);
+    }
+
+    #[test]
+    fn highlights_keep_the_text() {
+        let styled = apply_highlights("~/wörk/api", &[(0, 1), (3, 2), (8, 2)], &Style::new());
+        assert_eq!(console::strip_ansi_codes(&styled), "~/wörk/api");
+        assert_eq!(apply_highlights("api", &[], &Style::new().bold()), "api");
+    }
+}
@@ -0,0 +1,484 @@
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use crate::query::{Highlight, Query};
+use crate::repo::RepoInfo;
+use crate::select::{Item, Matcher};
+
+/// Matches the query `input` against all repos.
+///
+/// See `query` for the query syntax.
+/// Returns the matching repos as items without highlights, pinned repos
+/// first, then best match first. Equal scores keep the order of `repos`.
+pub fn search(input: &str, repos: &[RepoInfo]) -> Vec<Item> {
+    match_repos(&Query::parse(input), repos, None, GroupBy::None, &|| false)
+        .map(|(items, _)| items)
+        .unwrap_or_default()
+}
+
+/// Returns the sort key of a match with `score`: best fuzzy match first,
+/// then most recent commit of yours for `mine:` queries. Equal keys keep the
+/// ranking.
+fn sort_key(query: &Query, repo: &RepoInfo, score: isize) -> (Reverse<isize>, i64) {
+    let score = if query.is_fuzzy() { score } else { 0 };
+    let mine = if query.is_mine() {
+        repo.hours_since_my_last_commit()
+    } else {
+        0
+    };
+    (Reverse(score), mine)
+}
+
+/// Grouping of the listed repos by their remote repository.
+#[derive(Clone, Copy, PartialEq)]
+pub enum GroupBy {
+    None,
+    Host,
+    /// By host and owner.
+    Owner,
+}
+
+impl GroupBy {
+    /// Returns the next grouping, cycling through all.
+    pub fn next(self) -> GroupBy {
+        match self {
+            GroupBy::None => GroupBy::Host,
+            GroupBy::Host => GroupBy::Owner,
+            GroupBy::Owner => GroupBy::None,
+        }
+    }
+
+    /// Describes the grouping for the footer of the picker.
+    pub fn describe(self) -> Option<String> {
+        match self {
+            GroupBy::None => None,
+            GroupBy::Host => Some("by host".to_string()),
+            GroupBy::Owner => Some("by owner".to_string()),
+        }
+    }
+
+    /// Returns the sort key of the group of `repo`, ignoring case. Repos
+    /// without remote host come last.
+    fn key(self, repo: &RepoInfo) -> (bool, String, String) {
+        match repo.remote.as_ref().filter(|r| !r.host.is_empty()) {
+            Some(remote) if self == GroupBy::Owner => (
+                false,
+                remote.host.to_ascii_lowercase(),
+                remote.owner.to_ascii_lowercase(),
+            ),
+            Some(remote) => (false, remote.host.to_ascii_lowercase(), String::new()),
+            None => (true, String::new(), String::new()),
+        }
+    }
+}
+
+/// Number of repos matched between checks for a newer query.
+const CANCEL_CHECK_INTERVAL: usize = 256;
+
+struct Job {
+    generation: u64,
+    query: String,
+    repos: Arc<Vec<RepoInfo>>,
+    /// Indices of the repos to match, all repos if `None`.
+    candidates: Option<Arc<Vec<usize>>>,
+    group_by: GroupBy,
+}
+
+struct Results {
+    generation: u64,
+    items: Vec<Item>,
+    /// Indices of the matching repos, in ascending order.
+    matched: Arc<Vec<usize>>,
+}
+
+/// Matches queries against the repos on a background thread.
+///
+/// A new query cancels the matching of the previous one. If the new query
+/// narrows the previous one, only the previous matches are searched.
+pub struct Searcher {
+    repos: Arc<Vec<RepoInfo>>,
+    /// The last query, for highlighting.
+    query: String,
+    parsed: Query,
+    generation: Arc<AtomicU64>,
+    jobs: Sender<Job>,
+    results: Receiver<Results>,
+    /// Last query with finished results, and its matching repos.
+    last: Option<(String, Arc<Vec<usize>>)>,
+    group_by: GroupBy,
+}
+
+impl Searcher {
+    pub fn new(repos: Vec<RepoInfo>) -> Searcher {
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (result_sender, results) = mpsc::channel();
+        let generation = Arc::new(AtomicU64::new(0));
+        let current = generation.clone();
+        thread::spawn(move || {
+            while let Ok(mut job) = job_receiver.recv() {
+                // Skip queries which were replaced while matching
+                while let Ok(newer) = job_receiver.try_recv() {
+                    job = newer;
+                }
+                if let Some(results) = run_job(&job, &current) {
+                    if result_sender.send(results).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Searcher {
+            repos: Arc::new(repos),
+            query: String::new(),
+            parsed: Query::parse(""),
+            generation,
+            jobs,
+            results,
+            last: None,
+            group_by: GroupBy::None,
+        }
+    }
+
+    pub fn repos(&self) -> &[RepoInfo] {
+        &self.repos
+    }
+
+    pub fn group_by(&self) -> GroupBy {
+        self.group_by
+    }
+
+    /// Sets the grouping of the results of the next search.
+    pub fn set_group_by(&mut self, group_by: GroupBy) {
+        self.group_by = group_by;
+    }
+
+    /// Modifies the repos. Previous results are not reused afterwards.
+    pub fn update_repos<F: FnOnce(&mut Vec<RepoInfo>)>(&mut self, f: F) {
+        f(Arc::make_mut(&mut self.repos));
+        self.last = None;
+    }
+}
+
+impl Matcher for Searcher {
+    fn search(&mut self, query: &str) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.query = query.to_string();
+        self.parsed = Query::parse(query);
+        let candidates = match self.last {
+            Some((ref last, ref matched)) if self.parsed.narrows(&Query::parse(last)) => {
+                Some(matched.clone())
+            }
+            _ => None,
+        };
+        let _ = self.jobs.send(Job {
+            generation,
+            query: query.to_string(),
+            repos: self.repos.clone(),
+            candidates,
+            group_by: self.group_by,
+        });
+    }
+
+    fn poll(&mut self) -> Option<Vec<Item>> {
+        let mut items = None;
+        while let Ok(results) = self.results.try_recv() {
+            if results.generation == self.generation.load(Ordering::SeqCst) {
+                items = Some(results.items);
+                self.last = Some((self.query.clone(), results.matched));
+            }
+        }
+        items
+    }
+
+    fn wait(&mut self) -> Vec<Item> {
+        loop {
+            match self.results.recv() {
+                Ok(results) if results.generation == self.generation.load(Ordering::SeqCst) => {
+                    self.last = Some((self.query.clone(), results.matched));
+                    return results.items;
+                }
+                Ok(_) => {}
+                Err(_) => return Vec::new(),
+            }
+        }
+    }
+
+    fn highlights(&self, item: &Item) -> Vec<Highlight> {
+        match self.repos.get(item.item_key) {
+            Some(repo) => self
+                .parsed
+                .matches(repo)
+                .map(|m| m.highlights)
+                .unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Matches the query of `job`. Returns `None` if the job was cancelled.
+fn run_job(job: &Job, current: &AtomicU64) -> Option<Results> {
+    let cancelled = || current.load(Ordering::SeqCst) != job.generation;
+    let (items, matched) = match_repos(
+        &Query::parse(&job.query),
+        &job.repos,
+        job.candidates
+            .as_ref()
+            .map(|candidates| candidates.as_slice()),
+        job.group_by,
+        &cancelled,
+    )?;
+    Some(Results {
+        generation: job.generation,
+        items,
+        matched: Arc::new(matched),
+    })
+}
+
+/// Matches `query` against the `candidates` of `repos`, or all repos, and
+/// sorts the matches into groups. Returns the items and the indices of the
+/// matching repos in ascending order, or `None` as soon as `cancelled`.
+fn match_repos(
+    query: &Query,
+    repos: &[RepoInfo],
+    candidates: Option<&[usize]>,
+    group_by: GroupBy,
+    cancelled: &dyn Fn() -> bool,
+) -> Option<(Vec<Item>, Vec<usize>)> {
+    let all;
+    let candidates = match candidates {
+        Some(candidates) => candidates,
+        None => {
+            all = (0..repos.len()).collect::<Vec<_>>();
+            &all
+        }
+    };
+    let mut matches = Vec::new();
+    for (n, &idx) in candidates.iter().enumerate() {
+        if n % CANCEL_CHECK_INTERVAL == 0 && cancelled() {
+            return None;
+        }
+        if let Some(score) = query.score(&repos[idx]) {
+            matches.push((idx, score));
+        }
+    }
+    let matched = matches.iter().map(|(idx, _)| *idx).collect();
+    if query.is_fuzzy() || query.is_mine() {
+        matches.sort_by_cached_key(|(idx, score)| sort_key(query, &repos[*idx], *score));
+    }
+    let mut items = matches
+        .into_iter()
+        .map(|(idx, score)| Item {
+            text: repos[idx].display.clone(),
+            // Highlights are computed for visible items only
+            highlights: Vec::new(),
+            item_key: idx,
+            score,
+            pinned: repos[idx].pinned,
+            label: repos[idx].label(group_by == GroupBy::Host),
+        })
+        .collect::<Vec<_>>();
+    // The sorts are stable, so the ranking is kept within the sections
+    if group_by == GroupBy::None {
+        items.sort_by_key(|item| !item.pinned);
+    } else {
+        items.sort_by_cached_key(|item| (!item.pinned, group_by.key(&repos[item.item_key])));
+    }
+    Some((items, matched))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::RemoteUrl;
+    use crate::repo::Identity;
+    use crate::test_util;
+
+    /// Returns repos with the given display paths, pinned if marked with `*`.
+    fn repos(paths: &[&str]) -> Vec<RepoInfo> {
+        paths
+            .iter()
+            .map(|path| {
+                let display = path.trim_end_matches('*');
+                let mut repo = RepoInfo::new(&format!("/nonexistent{}", display));
+                repo.display = display.to_string();
+                repo.pinned = path.ends_with('*');
+                repo
+            })
+            .collect()
+    }
+
+    fn displays(items: &[Item], repos: &[RepoInfo]) -> Vec<String> {
+        items
+            .iter()
+            .map(|item| repos[item.item_key].display.clone())
+            .collect()
+    }
+
+    #[test]
+    fn pinned_first_keeping_the_ranking() {
+        let repos = repos(&["~/a", "~/b*", "~/c", "~/d*"]);
+        assert_eq!(
+            displays(&search("", &repos), &repos),
+            vec!["~/b", "~/d", "~/a", "~/c"]
+        );
+        let items = search("", &repos);
+        assert!(items[0].pinned && items[1].pinned && !items[2].pinned);
+    }
+
+    #[test]
+    fn pinned_first_among_matches() {
+        let repos = repos(&["~/work/api", "~/apps/api-docs*", "~/work/web*"]);
+        assert_eq!(
+            displays(&search("api", &repos), &repos),
+            vec!["~/apps/api-docs", "~/work/api"]
+        );
+    }
+
+    #[test]
+    fn mine_sorts_by_my_last_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Arc::new(Identity {
+            emails: vec!["me@example.com".to_string()],
+            within_hours: 30 * 24,
+        });
+        let mut repos = Vec::new();
+        for (name, display, hours_ago) in [
+            ("a", "~/top/api", 100),
+            ("b", "~/apps/a-p-i", 1),
+            ("c", "~/old/api", 200),
+            ("d", "~/new/api", 10),
+        ] {
+            let path = dir.path().join(name);
+            let git2_repo = test_util::init(&path);
+            test_util::commit_as(&git2_repo, "README", "me@example.com", hours_ago);
+            let mut repo = RepoInfo::new(path.to_str().unwrap());
+            repo.display = display.to_string();
+            repo.identity = Some(identity.clone());
+            repos.push(repo);
+        }
+        assert_eq!(
+            displays(&search("mine:", &repos), &repos),
+            vec!["~/apps/a-p-i", "~/new/api", "~/top/api", "~/old/api"]
+        );
+        // Equally good matches are sorted by my last commit
+        let fuzzy = displays(&search("api mine:", &repos), &repos);
+        assert_eq!(fuzzy[..3], ["~/new/api", "~/top/api", "~/old/api"]);
+        assert_eq!(fuzzy[3], "~/apps/a-p-i");
+        let mut searcher = Searcher::new(repos.clone());
+        searcher.search("api mine:");
+        assert_eq!(displays(&searcher.wait(), &repos), fuzzy);
+        // Without `mine:` the ranking is kept
+        assert_eq!(
+            displays(&search("'api", &repos), &repos),
+            vec!["~/top/api", "~/old/api", "~/new/api"]
+        );
+    }
+
+    fn job(query: &str, repos: &[RepoInfo], candidates: Option<Vec<usize>>) -> Job {
+        Job {
+            generation: 1,
+            query: query.to_string(),
+            repos: Arc::new(repos.to_vec()),
+            candidates: candidates.map(Arc::new),
+            group_by: GroupBy::None,
+        }
+    }
+
+    #[test]
+    fn searcher_matches_like_search() {
+        let repos = repos(&["~/work/api", "~/apps/api-docs*", "~/work/web*"]);
+        let mut searcher = Searcher::new(repos.clone());
+        searcher.search("api");
+        let items = searcher.wait();
+        assert_eq!(
+            displays(&items, &repos),
+            displays(&search("api", &repos), &repos)
+        );
+        // Highlights are left to the visible items
+        assert!(items[0].highlights.is_empty());
+        assert_eq!(
+            searcher.highlights(&items[0]),
+            Query::parse("api").matches(&repos[1]).unwrap().highlights
+        );
+    }
+
+    #[test]
+    fn only_the_latest_query_is_answered() {
+        let repos = repos(&["~/work/api", "~/work/web"]);
+        let mut searcher = Searcher::new(repos.clone());
+        searcher.search("api");
+        searcher.search("web");
+        assert_eq!(displays(&searcher.wait(), &repos), vec!["~/work/web"]);
+        assert!(searcher.poll().is_none());
+    }
+
+    #[test]
+    fn previous_matches_are_kept_for_narrowing() {
+        let repos = repos(&["~/work/api", "~/apps/api-docs", "~/work/web"]);
+        let mut searcher = Searcher::new(repos);
+        searcher.search("ap");
+        searcher.wait();
+        let (query, matched) = searcher.last.clone().unwrap();
+        assert_eq!((query.as_str(), matched.as_slice()), ("ap", &[0, 1][..]));
+        searcher.update_repos(|repos| repos.truncate(2));
+        assert!(searcher.last.is_none());
+    }
+
+    #[test]
+    fn jobs_match_only_the_candidates() {
+        let repos = repos(&["~/work/api", "~/apps/api-docs", "~/work/web"]);
+        let results = run_job(&job("api", &repos, Some(vec![1, 2])), &AtomicU64::new(1)).unwrap();
+        assert_eq!(displays(&results.items, &repos), vec!["~/apps/api-docs"]);
+        assert_eq!(*results.matched, vec![1]);
+        let results = run_job(&job("w", &repos, None), &AtomicU64::new(1)).unwrap();
+        assert_eq!(*results.matched, vec![0, 2]);
+    }
+
+    #[test]
+    fn groups_by_host_and_owner() {
+        let mut repos = repos(&[
+            "~/web", "~/local", "~/api*", "~/none", "~/docs", "~/lib", "~/tools*",
+        ]);
+        let urls = [
+            Some("https://gitlab.com/acme/web"),
+            Some("/srv/git/local"),
+            Some("https://gitlab.com/zeta/api"),
+            None,
+            Some("git@GitHub.com:Zeta/docs"),
+            Some("https://github.com/acme/lib"),
+            None,
+        ];
+        for (repo, url) in repos.iter_mut().zip(urls) {
+            repo.remote_url = url.map(String::from);
+            repo.remote = url.and_then(RemoteUrl::parse);
+        }
+        let grouped = |group_by| {
+            let mut job = job("", &repos, None);
+            job.group_by = group_by;
+            displays(&run_job(&job, &AtomicU64::new(1)).unwrap().items, &repos)
+        };
+        // Pinned first, then by host keeping the ranking, repos without remote
+        // host last
+        assert_eq!(
+            grouped(GroupBy::Host),
+            vec!["~/api", "~/tools", "~/docs", "~/lib", "~/web", "~/local", "~/none"]
+        );
+        assert_eq!(
+            grouped(GroupBy::Owner),
+            vec!["~/api", "~/tools", "~/lib", "~/docs", "~/web", "~/local", "~/none"]
+        );
+        assert_eq!(
+            grouped(GroupBy::None),
+            vec!["~/api", "~/tools", "~/web", "~/local", "~/none", "~/docs", "~/lib"]
+        );
+    }
+
+    #[test]
+    fn replaced_jobs_are_cancelled() {
+        let repos = repos(&["~/work/api"]);
+        assert!(run_job(&job("api", &repos, None), &AtomicU64::new(2)).is_none());
+    }
+}
@@ -1,254 +1,1146 @@
 use std::io;
+use std::time::{Duration, Instant};
 
-use std::ops::Rem;
+use crate::keymap::{Action, KeyPress, Keymap, Lookup, Mode};
+use crate::query::Highlight;
+use crate::screen::{apply_highlights, display_width, truncate_middle, Screen};
+use crate::theme::{SelectionStyle, Theme};
+use crate::viewport::Viewport;
 
-use crate::theme::{get_default_theme, SelectionStyle, TermThemeRenderer, Theme};
+use crossterm::event::{self, Event, KeyEventKind, MouseButton, MouseEvent, MouseEventKind};
 
-use console::{Key, Term};
-
-use sublime_fuzzy::Match;
+/// Prompt of the query line.
+const QUERY_PROMPT: &str = "Search";
+/// Maximal share of the screen rows used by the output pane.
+const OUTPUT_SHARE: usize = 3;
+/// Maximal time between the clicks of a double-click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+/// Number of items or output lines scrolled by the mouse wheel.
+const WHEEL_LINES: usize = 3;
+/// Minimal width of item texts next to a label.
+const MIN_TEXT_WIDTH: usize = 20;
+/// Interval of checking for results while matching in the background.
+const SEARCH_POLL: Duration = Duration::from_millis(10);
 
 pub struct Item {
+    /// Plain text of the item.
     pub text: String,
+    /// Char ranges of `text` matching the query.
+    pub highlights: Vec<Highlight>,
     pub item_key: usize,
-    pub match_info: Match,
+    pub score: isize,
+    /// Pinned items are rendered in a separate section above the others.
+    pub pinned: bool,
+    /// Additional information shown after the text, if there is room.
+    pub label: String,
 }
 
 pub enum SelectCommand {
-    CharInput { ch: char },
-    Select { item_key: usize },
-    Command { ch: char, item_key: usize },
+    Select {
+        item_key: usize,
+    },
+    /// An action on the selected item, see `Action::is_command`.
+    Command {
+        action: Action,
+        item_key: usize,
+    },
     Quit,
 }
 
-/// Renders a selection menu.
+/// Part of the screen receiving navigation keys.
+#[derive(Clone, Copy, PartialEq)]
+enum Focus {
+    Items,
+    Output,
+}
+
+/// Source of the items matching a query.
+///
+/// Matching may happen in the background. `Select` keeps handling input
+/// and polls for the results.
+pub trait Matcher {
+    /// Starts matching `query`, replacing the previous query.
+    fn search(&mut self, query: &str);
+
+    /// Returns the items matching the last query, once they are ready.
+    fn poll(&mut self) -> Option<Vec<Item>>;
+
+    /// Waits for the items matching the last query.
+    fn wait(&mut self) -> Vec<Item>;
+
+    /// Returns the char ranges of `item.text` matching the last query.
+    /// Only called for the items on the screen.
+    fn highlights(&self, item: &Item) -> Vec<Highlight> {
+        item.highlights.clone()
+    }
+}
+
+/// Renders a full-screen selection menu.
 pub struct Select<'a> {
-    default: usize,
     items: Vec<Item>,
-    prompt: Option<String>,
-    clear: bool,
-    theme: &'a Theme,
-    paged: bool,
-    page_size: usize,
+    theme: &'a dyn Theme,
+    viewport: Viewport,
+    /// Current query, edited inline if the menu has a matcher.
+    query: String,
+    /// Cursor position in `query`, in chars.
+    cursor: usize,
+    /// Previous queries, oldest first.
+    query_history: Vec<String>,
+    /// Position in `query_history` while recalling, and the input to return
+    /// to after the oldest entry.
+    history_pos: Option<(usize, String)>,
+    /// Output of the last command, shown below the items.
+    output: Vec<String>,
+    /// Number of output lines scrolled up from the end.
+    output_scroll: usize,
+    focus: Focus,
+    /// Item and time of the last click, to detect double-clicks.
+    last_click: Option<(usize, Instant)>,
+    keymap: Keymap,
+    mode: Mode,
+    /// Keys of an incomplete key sequence.
+    pending: Vec<KeyPress>,
+    /// Shows the key bindings instead of the items.
+    help: bool,
+    /// Number of all items, shown next to the number of matches.
+    total: Option<usize>,
+    /// Error shown above the footer until the next key press.
+    error: Option<String>,
+    /// Shown next to the number of matches, e.g. the grouping of the items.
+    status: Option<String>,
 }
 
 impl<'a> Select<'a> {
-    /// Creates the prompt with a specific text.
-    pub fn new() -> Select<'static> {
-        Select::with_theme(get_default_theme())
-    }
-
-    /// Same as `new` but with a specific theme.
-    pub fn with_theme(theme: &'a Theme) -> Select<'a> {
+    /// Creates the menu with a specific theme.
+    pub fn with_theme(theme: &'a dyn Theme) -> Select<'a> {
         Select {
-            default: !0,
             items: vec![],
-            prompt: None,
-            clear: true,
-            theme: theme,
-            paged: false,
-            page_size: 0,
+            theme,
+            viewport: Viewport::default(),
+            query: String::new(),
+            cursor: 0,
+            query_history: vec![],
+            history_pos: None,
+            output: vec![],
+            output_scroll: 0,
+            focus: Focus::Items,
+            last_click: None,
+            keymap: Keymap::default(),
+            mode: Mode::Insert,
+            pending: vec![],
+            help: false,
+            total: None,
+            status: None,
+            error: None,
         }
     }
-    /// Enables or disables paging
-    pub fn paged(&mut self, val: bool) -> &mut Select<'a> {
-        self.paged = val;
-        if self.paged {
-            self.page_size = 10;
-        }
+
+    /// Sets a default for the menu
+    pub fn default(&mut self, val: usize) -> &mut Select<'a> {
+        self.viewport.sel = val;
         self
     }
-    /// Sets the clear behavior of the menu.
-    ///
-    /// The default is to clear the menu.
-    pub fn clear(&mut self, val: bool) -> &mut Select<'a> {
-        self.clear = val;
+
+    /// Sets previous queries, oldest first, for recalling with ctrl-r.
+    pub fn query_history(&mut self, queries: Vec<String>) -> &mut Select<'a> {
+        self.query_history = queries;
         self
     }
 
-    /// Sets a default for the menu
-    pub fn default(&mut self, val: usize) -> &mut Select<'a> {
-        self.default = val;
+    /// Sets the key bindings.
+    pub fn keymap(&mut self, keymap: Keymap) -> &mut Select<'a> {
+        self.mode = keymap.initial_mode();
+        self.keymap = keymap;
         self
     }
 
-    /// Add a single item to the selector.
-    pub fn item(&mut self, item: Item) -> &mut Select<'a> {
-        self.items.push(item);
+    /// Sets the number of all items, shown next to the number of matches.
+    pub fn total(&mut self, total: usize) -> &mut Select<'a> {
+        self.total = Some(total);
         self
     }
 
-    /// Adds multiple items to the selector.
-    pub fn items(&mut self, items: Vec<Item>) -> &mut Select<'a> {
-        for item in items {
-            self.items.push(item);
-        }
+    /// Sets or clears the text shown next to the number of matches.
+    pub fn status(&mut self, status: Option<String>) -> &mut Select<'a> {
+        self.status = status;
         self
     }
 
-    // Move cursor up according to current number of items
-    pub fn reset_cursor(&mut self, term: &Term) -> &mut Select<'a> {
-        let capacity = self.capacity(term);
-        term.move_cursor_up(self.items.len().min(capacity)).unwrap();
+    /// Returns the current query.
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Shows the output of a command below the items.
+    pub fn output(&mut self, lines: Vec<String>) -> &mut Select<'a> {
+        self.output = lines;
+        self.output_scroll = 0;
+        self.focus = Focus::Items;
         self
     }
 
-    /// Prefaces the menu with a prompt.
-    ///
-    /// When a prompt is set the system also prints out a confirmation after
-    /// the selection.
-    pub fn with_prompt(&mut self, prompt: &str) -> &mut Select<'a> {
-        self.prompt = Some(prompt.to_string());
+    /// Shows an error above the footer until the next key press.
+    pub fn error(&mut self, error: &str) -> &mut Select<'a> {
+        self.error = Some(error.to_string());
         self
     }
 
-    /// Enables user interaction and returns the result.
+    /// Enables user interaction with a query line above the items and
+    /// returns the result.
     ///
-    /// The index of the selected item.
-    /// The dialog is rendered on stderr.
-    pub fn interact(&self) -> io::Result<SelectCommand> {
-        self.interact_on(&Term::stderr())
+    /// The items are replaced with the results of `matcher` whenever the
+    /// query is edited. The query and the selected item are kept between
+    /// interactions.
+    pub fn interact_query(
+        &mut self,
+        screen: &mut Screen,
+        matcher: &mut dyn Matcher,
+    ) -> io::Result<SelectCommand> {
+        matcher.search(&self.query);
+        let items = matcher.wait();
+        self.refilter(items);
+        self._interact(screen, Some(matcher))
     }
 
-    /// Enables user interaction and returns the result.
-    ///
-    /// The index of the selected item. None if the user
-    /// cancelled with Esc or 'q'.
-    /// The dialog is rendered on stderr.
-    pub fn interact_opt(&self) -> io::Result<SelectCommand> {
-        self._interact_on(&Term::stderr(), true)
+    /// Replaces the items, keeping the selected item on the same row if it
+    /// is still listed. Otherwise the first item is selected.
+    fn refilter(&mut self, items: Vec<Item>) {
+        let selected = self.items.get(self.viewport.sel).map(|item| item.item_key);
+        self.items = items;
+        let sel = selected.and_then(|key| self.items.iter().position(|item| item.item_key == key));
+        let pinned = self.items.iter().take_while(|item| item.pinned).count();
+        self.viewport.refilter(self.items.len(), pinned, sel);
+    }
+
+    /// Number of screen rows above the items.
+    fn header_height(&self, with_query: bool) -> usize {
+        if with_query {
+            1
+        } else {
+            0
+        }
     }
 
-    /// Like `interact` but allows a specific terminal to be set.
-    pub fn interact_on(&self, term: &Term) -> io::Result<SelectCommand> {
-        self._interact_on(term, false)
-        // io::Error::new(
-        //    io::ErrorKind::Other,
-        //    "Quit not allowed in this case",
-        //))
+    /// Number of screen rows used by the output pane, including its divider.
+    fn output_height(&self, rows: usize) -> usize {
+        if self.output.is_empty() {
+            0
+        } else {
+            (self.output.len() + 1).min(rows / OUTPUT_SHARE)
+        }
     }
 
-    /// Like `interact` but allows a specific terminal to be set.
-    pub fn interact_on_opt(&self, term: &Term) -> io::Result<SelectCommand> {
-        self._interact_on(term, true)
+    /// Number of screen rows for the items on a screen of `rows` rows.
+    fn height(&self, rows: usize, with_query: bool) -> usize {
+        rows.saturating_sub(self.header_height(with_query))
+            .saturating_sub(self.output_height(rows))
+            // Room for the error and the footer
+            .saturating_sub(self.error.iter().count() + 1)
+            .max(1)
     }
 
-    fn capacity(&self, term: &Term) -> usize {
-        let mut capacity = self.items.len();
-        if self.paged {
-            capacity = self.page_size.min(term.size().0 as usize - 1);
+    /// Formats an item, shortened to the screen width.
+    fn format_item(
+        &self,
+        item: &Item,
+        highlights: &[Highlight],
+        style: SelectionStyle,
+        width: usize,
+    ) -> String {
+        let mut prefix = String::new();
+        let _ = self.theme.format_selection(&mut prefix, "", style);
+        let width = width.saturating_sub(display_width(&prefix));
+        let mut label = String::new();
+        if !item.label.is_empty() {
+            let _ = self.theme.format_item_label(&mut label, &item.label);
+        }
+        // The label is left out if it would hide most of the text
+        let text_width = display_width(&item.text);
+        if width < text_width.min(MIN_TEXT_WIDTH) + display_width(&label) {
+            label.clear();
         }
-        capacity
+        let (text, highlights) = truncate_middle(
+            &item.text,
+            highlights,
+            width.saturating_sub(display_width(&label)),
+        );
+        let text = apply_highlights(&text, &highlights, &self.theme.highlight_style());
+        let mut buf = String::new();
+        let _ = self.theme.format_selection(&mut buf, &text, style);
+        buf.push_str(&label);
+        buf
     }
 
-    /// Like `interact` but allows a specific terminal to be set.
-    fn _interact_on(&self, term: &Term, allow_quit: bool) -> io::Result<SelectCommand> {
-        let mut page = 0;
-        let capacity = self.capacity(term);
-        let pages = (self.items.len() / capacity) + 1;
-        let mut render = TermThemeRenderer::new(term, self.theme);
-        let mut sel = self.default;
-        if let Some(ref prompt) = self.prompt {
-            render.prompt(prompt)?;
+    /// Returns the mode used for key lookups.
+    fn mode(&self, with_query: bool) -> Mode {
+        if with_query {
+            self.mode
+        } else {
+            self.keymap.initial_mode()
         }
-        let mut size_vec = Vec::new();
-        for items in self.items.iter().as_slice() {
-            let size = &items.text.len();
-            size_vec.push(size.clone());
+    }
+
+    /// Builds the lines of the key bindings help, in columns if they don't
+    /// fit on the screen.
+    fn help_lines(&self, rows: usize, cols: usize) -> Vec<String> {
+        let mut sections = vec![("Keys", Mode::Insert)];
+        if self.keymap.normal.is_some() {
+            sections = vec![("Normal mode", Mode::Normal), ("Insert mode", Mode::Insert)];
         }
-        loop {
-            for (idx, item) in self
-                .items
+        let mut lines = Vec::new();
+        let num_sections = sections.len();
+        for (section, (title, mode)) in sections.into_iter().enumerate() {
+            let entries = self.keymap.help(mode);
+            let keys_width = entries
                 .iter()
-                .enumerate()
-                .skip(page * capacity)
-                .take(capacity)
-            {
-                render.selection(
-                    &item.text,
-                    if sel == idx {
-                        SelectionStyle::MenuSelected
-                    } else {
-                        SelectionStyle::MenuUnselected
-                    },
-                )?;
-            }
-            match term.read_key()? {
-                Key::ArrowDown => {
-                    if sel == !0 {
-                        sel = 0;
-                    } else {
-                        sel = (sel as u64 + 1).rem(self.items.len() as u64) as usize;
+                .map(|(keys, _)| display_width(keys))
+                .max()
+                .unwrap_or(0);
+            let cells = entries
+                .iter()
+                .map(|(keys, action)| {
+                    let mut buf = String::new();
+                    let _ = self
+                        .theme
+                        .format_help_entry(&mut buf, keys, action, keys_width);
+                    buf
+                })
+                .collect::<Vec<_>>();
+            let cell_width = cells.iter().map(|c| display_width(c)).max().unwrap_or(0) + 2;
+            // Share the remaining rows between the remaining sections
+            let remaining = num_sections - section;
+            let available = (rows.saturating_sub(lines.len() + remaining) / remaining).max(1);
+            let columns = cells
+                .len()
+                .div_ceil(available)
+                .clamp(1, (cols / cell_width).max(1));
+            let height = cells.len().div_ceil(columns);
+            let mut buf = String::new();
+            let _ = self.theme.format_prompt(&mut buf, title);
+            lines.push(fit(buf, cols));
+            for row in 0..height {
+                let mut line = String::new();
+                for cell in cells.iter().skip(row).step_by(height) {
+                    let padding = cell_width.saturating_sub(display_width(&line) % cell_width);
+                    if !line.is_empty() {
+                        line.push_str(&" ".repeat(padding));
                     }
+                    line.push_str(cell);
                 }
-                Key::Escape => {
-                    if allow_quit {
-                        if self.clear {
-                            term.clear_last_lines(self.items.len())?;
-                        }
-                        return Ok(SelectCommand::Quit);
-                    }
+                lines.push(fit(line, cols));
+            }
+        }
+        if lines.len() > rows {
+            let hidden = lines.len() - rows + 1;
+            lines.truncate(rows.saturating_sub(1));
+            lines.push(fit(format!("  … {} more", hidden), cols));
+        }
+        lines
+    }
+
+    /// Builds the footer line with key hints and the number of matches.
+    fn footer(&self, with_query: bool, cols: usize) -> String {
+        let mode = self.mode(with_query);
+        let mut actions = vec![Action::Select];
+        match mode {
+            Mode::Normal if with_query => actions.push(Action::Search),
+            Mode::Insert if self.keymap.normal.is_some() => actions.push(Action::NormalMode),
+            _ => {}
+        }
+        actions.extend(vec![Action::Help, Action::Quit]);
+        let hints = actions
+            .iter()
+            .filter_map(|action| {
+                self.keymap
+                    .key_for(mode, action)
+                    .map(|key| (key, action.to_string()))
+            })
+            .collect::<Vec<_>>();
+        let mut count = self
+            .status
+            .as_ref()
+            .map(|status| format!("{}  ", status))
+            .unwrap_or_default();
+        let _ = self
+            .theme
+            .format_match_count(&mut count, self.items.len(), self.total);
+        let mut line = String::new();
+        let _ = self.theme.format_hints(&mut line, &hints);
+        let padding = cols.saturating_sub(display_width(&line) + display_width(&count));
+        if padding == 0 {
+            // Without room for the hints, show the number of matches only
+            return fit(count, cols);
+        }
+        line.push_str(&" ".repeat(padding));
+        line.push_str(&count);
+        line
+    }
+
+    /// Returns the items shown on the screen below the header, one entry per
+    /// row. `None` is the divider of the pinned section.
+    fn item_rows(&self, height: usize) -> Vec<Option<usize>> {
+        let Viewport { offset, .. } = self.viewport;
+        let divider = self.viewport.divider_after();
+        let mut rows = Vec::new();
+        for idx in (offset..self.items.len()).take(self.viewport.visible(offset, height)) {
+            rows.push(Some(idx));
+            if divider == Some(idx) {
+                rows.push(None);
+            }
+        }
+        rows
+    }
+
+    /// Returns the rows of the output lines on the screen.
+    fn output_rows(&self, rows: usize) -> std::ops::Range<usize> {
+        let output_height = self.output_height(rows);
+        if output_height == 0 {
+            return 0..0;
+        }
+        let end = rows.saturating_sub(1);
+        end + 1 - output_height..end
+    }
+
+    /// Scrolls the output by `delta` lines, positive towards the end.
+    fn scroll_output(&mut self, rows: usize, delta: isize) {
+        let visible = self.output_height(rows).saturating_sub(1);
+        let max = self.output.len().saturating_sub(visible) as isize;
+        self.output_scroll = (self.output_scroll as isize - delta).clamp(0, max) as usize;
+    }
+
+    /// Applies a navigation action to the focused output pane. Returns
+    /// `false` if the action doesn't apply to the output.
+    fn output_action(&mut self, rows: usize, action: &Action) -> bool {
+        let page = self.output_height(rows).saturating_sub(1).max(1) as isize;
+        match action {
+            Action::Down => self.scroll_output(rows, 1),
+            Action::Up => self.scroll_output(rows, -1),
+            Action::PageDown => self.scroll_output(rows, page),
+            Action::PageUp => self.scroll_output(rows, -page),
+            Action::First => self.scroll_output(rows, isize::MIN / 2),
+            Action::Last => self.scroll_output(rows, isize::MAX / 2),
+            Action::Quit | Action::NormalMode | Action::Select => self.focus = Focus::Items,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Handles a mouse event. Returns the result of the interaction if an
+    /// item was double-clicked.
+    fn mouse(
+        &mut self,
+        rows: usize,
+        with_query: bool,
+        mouse: &MouseEvent,
+    ) -> Option<SelectCommand> {
+        let row = mouse.row as usize;
+        let in_output = self.output_rows(rows).contains(&row)
+            || (!self.output.is_empty() && row + 1 == self.output_rows(rows).start);
+        let height = self.height(rows, with_query);
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) if in_output => self.focus = Focus::Output,
+            MouseEventKind::Down(MouseButton::Left) => {
+                self.focus = Focus::Items;
+                let item = row
+                    .checked_sub(self.header_height(with_query))
+                    .and_then(|row| self.item_rows(height).get(row).cloned())
+                    .flatten()?;
+                let now = Instant::now();
+                let double = match self.last_click {
+                    Some((last, time)) => last == item && now - time < DOUBLE_CLICK,
+                    None => false,
+                };
+                self.viewport.sel = item;
+                if double {
+                    self.last_click = None;
+                    return Some(SelectCommand::Select {
+                        item_key: self.items[item].item_key,
+                    });
                 }
-                Key::ArrowUp => {
-                    if sel == !0 {
-                        sel = self.items.len() - 1;
-                    } else {
-                        sel = ((sel as i64 - 1 + self.items.len() as i64)
-                            % (self.items.len() as i64)) as usize;
-                    }
+                self.last_click = Some((item, now));
+            }
+            MouseEventKind::ScrollDown if in_output => {
+                self.scroll_output(rows, WHEEL_LINES as isize)
+            }
+            MouseEventKind::ScrollUp if in_output => {
+                self.scroll_output(rows, -(WHEEL_LINES as isize))
+            }
+            MouseEventKind::ScrollDown => self.viewport.scroll(height, WHEEL_LINES as isize),
+            MouseEventKind::ScrollUp => self.viewport.scroll(height, -(WHEEL_LINES as isize)),
+            _ => {}
+        }
+        None
+    }
+
+    /// Builds the lines of a screen of `rows` and `cols` and the cursor
+    /// position.
+    fn frame(
+        &self,
+        (rows, cols): (usize, usize),
+        matcher: Option<&dyn Matcher>,
+    ) -> (Vec<String>, Option<(usize, usize)>) {
+        let with_query = matcher.is_some();
+        if self.help {
+            return (self.help_lines(rows, cols), None);
+        }
+        let height = self.height(rows, with_query);
+        let mut lines = Vec::with_capacity(rows);
+        let mut cursor = None;
+        if with_query {
+            let mut buf = String::new();
+            let _ = self
+                .theme
+                .format_singleline_prompt(&mut buf, QUERY_PROMPT, None);
+            let before_cursor = self.query.chars().take(self.cursor).collect::<String>();
+            if self.mode == Mode::Insert {
+                cursor = Some((
+                    lines.len(),
+                    display_width(&buf) + display_width(&before_cursor),
+                ));
+            }
+            buf.push_str(&self.query);
+            lines.push(fit(buf, cols));
+        }
+        if self.items.is_empty() {
+            let mut buf = String::new();
+            let _ = self.theme.format_no_matches(&mut buf);
+            lines.push(fit(buf, cols));
+        }
+        let item_rows = self.item_rows(height);
+        let scrollbar = self.viewport.scrollbar(height);
+        // The scrollbar takes the last column
+        let width = if scrollbar.is_some() {
+            cols.saturating_sub(1)
+        } else {
+            cols
+        };
+        for (row, item_row) in item_rows.into_iter().enumerate() {
+            let mut line = match item_row {
+                Some(idx) => {
+                    let item = &self.items[idx];
+                    let style = match (self.viewport.sel == idx, item.pinned) {
+                        (true, false) => SelectionStyle::MenuSelected,
+                        (false, false) => SelectionStyle::MenuUnselected,
+                        (true, true) => SelectionStyle::MenuPinnedSelected,
+                        (false, true) => SelectionStyle::MenuPinnedUnselected,
+                    };
+                    let highlights = match matcher {
+                        Some(matcher) => matcher.highlights(item),
+                        None => item.highlights.clone(),
+                    };
+                    self.format_item(item, &highlights, style, width)
                 }
-                Key::ArrowLeft => {
-                    if self.paged {
-                        if page == 0 {
-                            page = pages - 1;
-                        } else {
-                            page = page - 1;
-                        }
-                        sel = page * capacity;
-                    }
+                None => {
+                    let mut buf = String::new();
+                    let _ = self.theme.format_divider(&mut buf);
+                    fit(buf, width)
                 }
-                Key::ArrowRight => {
-                    if self.paged {
-                        if page == pages - 1 {
-                            page = 0;
-                        } else {
-                            page = page + 1;
-                        }
-                        sel = page * capacity;
-                    }
+            };
+            if let Some(ref thumb) = scrollbar {
+                line.push_str(&" ".repeat(width.saturating_sub(display_width(&line))));
+                let _ = self.theme.format_scrollbar(&mut line, thumb.contains(&row));
+            }
+            lines.push(line);
+        }
+        let output_height = self.output_height(rows);
+        let error_height = self.error.iter().count();
+        lines.resize(
+            rows.saturating_sub(output_height + error_height + 1),
+            String::new(),
+        );
+        if let Some(ref error) = self.error {
+            let mut buf = String::new();
+            let _ = self.theme.format_error(&mut buf, error);
+            lines.push(fit(buf, cols));
+        }
+        if output_height > 0 {
+            let mut buf = String::new();
+            let _ = self
+                .theme
+                .format_pane_divider(&mut buf, self.focus == Focus::Output);
+            lines.push(fit(buf, cols));
+            let end = self.output.len() - self.output_scroll;
+            let start = end.saturating_sub(output_height - 1);
+            for line in &self.output[start..end] {
+                let (text, _) = truncate_middle(line, &[], cols);
+                lines.push(text);
+            }
+        }
+        lines.push(self.footer(with_query, cols));
+        (lines, cursor)
+    }
+
+    /// Applies a query editing action. Returns `false` if the action
+    /// doesn't edit the query.
+    fn edit_query(&mut self, action: &Action) -> bool {
+        let mut chars = self.query.chars().collect::<Vec<_>>();
+        match action {
+            Action::CursorLeft => self.cursor = self.cursor.saturating_sub(1),
+            Action::CursorRight => self.cursor = (self.cursor + 1).min(chars.len()),
+            Action::CursorStart => self.cursor = 0,
+            Action::CursorEnd => self.cursor = chars.len(),
+            Action::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    chars.remove(self.cursor);
+                }
+            }
+            Action::Delete => {
+                if self.cursor < chars.len() {
+                    chars.remove(self.cursor);
                 }
+            }
+            // Delete word before cursor
+            Action::DeleteWord => {
+                let mut start = self.cursor;
+                while start > 0 && chars[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && chars[start - 1] != ' ' {
+                    start -= 1;
+                }
+                chars.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            // Kill line before cursor
+            Action::KillLine => {
+                chars.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Action::KillToEnd => {
+                chars.truncate(self.cursor);
+            }
+            Action::ClearQuery => {
+                chars.clear();
+                self.cursor = 0;
+            }
+            // Recall previous queries, newest first
+            Action::RecallQuery => {
+                self.recall_query();
+                return true;
+            }
+            _ => return false,
+        }
+        self.history_pos = None;
+        self.query = chars.into_iter().collect();
+        true
+    }
 
-                Key::Enter if sel != !0 => {
-                    if self.clear {
-                        render.clear()?;
+    /// Inserts `ch` into the query at the cursor.
+    fn insert_char(&mut self, ch: char) {
+        let mut chars = self.query.chars().collect::<Vec<_>>();
+        chars.insert(self.cursor, ch);
+        self.cursor += 1;
+        self.history_pos = None;
+        self.query = chars.into_iter().collect();
+    }
+
+    /// Replaces the query with the next older one from the history, or with
+    /// the original input after the oldest entry.
+    fn recall_query(&mut self) {
+        if self.query_history.is_empty() {
+            return;
+        }
+        let pos = match self.history_pos.take() {
+            None => {
+                let pos = self.query_history.len() - 1;
+                self.history_pos = Some((pos, self.query.clone()));
+                pos
+            }
+            Some((0, input)) => {
+                self.query = input;
+                self.cursor = self.query.chars().count();
+                return;
+            }
+            Some((pos, input)) => {
+                self.history_pos = Some((pos - 1, input));
+                pos - 1
+            }
+        };
+        self.query = self.query_history[pos].clone();
+        self.cursor = self.query.chars().count();
+    }
+
+    fn _interact(
+        &mut self,
+        screen: &mut Screen,
+        mut matcher: Option<&mut dyn Matcher>,
+    ) -> io::Result<SelectCommand> {
+        let with_query = matcher.is_some();
+        // Whether the matcher works on a query
+        let mut searching = false;
+        loop {
+            if searching {
+                if let Some(items) = matcher.as_deref_mut().and_then(|m| m.poll()) {
+                    self.refilter(items);
+                    searching = false;
+                }
+            }
+            let height = self.height(screen.size().0, with_query);
+            self.viewport.scroll_to_selection(height);
+            let (lines, cursor) = self.frame(screen.size(), matcher.as_deref());
+            screen.draw(&lines, cursor)?;
+
+            if searching && !event::poll(SEARCH_POLL)? {
+                continue;
+            }
+            let key = match event::read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => key,
+                Event::Mouse(mouse) if self.help => {
+                    if let MouseEventKind::Down(_) = mouse.kind {
+                        self.help = false;
                     }
-                    if let Some(ref prompt) = self.prompt {
-                        render.single_prompt_selection(prompt, &self.items[sel].text)?;
+                    continue;
+                }
+                Event::Mouse(mouse) => {
+                    if let Some(result) = self.mouse(screen.size().0, with_query, &mouse) {
+                        return Ok(result);
+                    }
+                    continue;
+                }
+                Event::Resize(cols, rows) => {
+                    screen.resize(cols, rows)?;
+                    continue;
+                }
+                _ => continue,
+            };
+            self.error = None;
+            if self.help {
+                // Any key closes the help
+                self.help = false;
+                continue;
+            }
+            self.pending.push(KeyPress::from_event(&key));
+            let mode = self.mode(with_query);
+            let action = match self.keymap.lookup(mode, &self.pending) {
+                Lookup::Action(action) => action.clone(),
+                Lookup::Pending => continue,
+                Lookup::Unbound => {
+                    let keys = std::mem::take(&mut self.pending);
+                    // Unbound single characters are typed into the query
+                    let text = keys.first().and_then(KeyPress::text);
+                    if let (Some(matcher), Mode::Insert, 1, Some(ch)) =
+                        (matcher.as_deref_mut(), mode, keys.len(), text)
+                    {
+                        self.insert_char(ch);
+                        matcher.search(&self.query);
+                        searching = true;
                     }
+                    continue;
+                }
+            };
+            self.pending.clear();
+            if self.focus == Focus::Output && self.output_action(screen.size().0, &action) {
+                continue;
+            }
+            if searching && (action == Action::Select || action.is_command()) {
+                // Act on the results of the current query
+                if let Some(matcher) = matcher.as_deref_mut() {
+                    let items = matcher.wait();
+                    self.refilter(items);
+                }
+                searching = false;
+            }
+            let len = self.items.len();
+            let sel = self.viewport.sel;
+            match action {
+                Action::Down => self.viewport.move_selection(1),
+                Action::Up => self.viewport.move_selection(-1),
+                Action::PageDown => self.viewport.page(height, true),
+                Action::PageUp => self.viewport.page(height, false),
+                Action::First => self.viewport.sel = 0,
+                Action::Last => self.viewport.sel = len.saturating_sub(1),
+                Action::Quit => return Ok(SelectCommand::Quit),
+                Action::Select if sel < len => {
                     return Ok(SelectCommand::Select {
                         item_key: self.items[sel].item_key,
                     });
                 }
-
-                Key::Char(ch) if ch.is_alphanumeric() => {
-                    if self.clear {
-                        render.clear()?;
-                    }
-                    return Ok(SelectCommand::CharInput { ch });
-                }
-
-                Key::Char(ch) if !ch.is_alphanumeric() => {
+                Action::Search if with_query => self.mode = Mode::Insert,
+                Action::Help => self.help = true,
+                Action::NormalMode => self.mode = self.keymap.initial_mode(),
+                ref action if action.is_command() && sel < len => {
                     return Ok(SelectCommand::Command {
-                        ch,
+                        action: action.clone(),
                         item_key: self.items[sel].item_key,
                     });
                 }
-                _ => {}
+                ref action => {
+                    if let Some(matcher) = matcher.as_deref_mut() {
+                        if self.edit_query(action) {
+                            matcher.search(&self.query);
+                            searching = true;
+                        }
+                    }
+                }
             }
-            if sel < page * capacity || sel >= (page + 1) * capacity {
-                page = sel / capacity;
+        }
+    }
+}
+
+/// Shortens a formatted line to `width` columns.
+fn fit(line: String, width: usize) -> String {
+    if display_width(&line) <= width {
+        line
+    } else {
+        truncate_middle(&console::strip_ansi_codes(&line), &[], width).0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::SimpleTheme;
+    use crossterm::event::KeyModifiers;
+
+    fn select() -> Select<'static> {
+        Select::with_theme(&SimpleTheme)
+    }
+
+    fn typed(text: &str) -> Select<'static> {
+        let mut select = select();
+        for ch in text.chars() {
+            select.insert_char(ch);
+        }
+        select
+    }
+
+    fn edit(select: &mut Select, actions: &[Action]) {
+        for action in actions {
+            assert!(select.edit_query(action));
+        }
+    }
+
+    /// Builds items keyed by their position. Texts ending with `*` are
+    /// pinned.
+    fn items(texts: &[&str]) -> Vec<Item> {
+        texts
+            .iter()
+            .enumerate()
+            .map(|(item_key, text)| Item {
+                text: text.trim_end_matches('*').to_string(),
+                highlights: vec![],
+                item_key,
+                score: 0,
+                pinned: text.ends_with('*'),
+                label: String::new(),
+            })
+            .collect()
+    }
+
+    fn mouse(kind: MouseEventKind, row: u16) -> MouseEvent {
+        MouseEvent {
+            kind,
+            column: 4,
+            row,
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+
+    fn click(select: &mut Select, row: u16) -> Option<SelectCommand> {
+        select.mouse(
+            12,
+            true,
+            &mouse(MouseEventKind::Down(MouseButton::Left), row),
+        )
+    }
+
+    fn keymap(config: &str) -> Keymap {
+        Keymap::load(&toml::from_str(config).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn insert_at_cursor() {
+        let mut select = typed("pi");
+        edit(&mut select, &[Action::CursorLeft]);
+        select.insert_char('ä');
+        assert_eq!(select.query(), "päi");
+        assert_eq!(select.cursor, 2);
+        edit(&mut select, &[Action::CursorStart]);
+        select.insert_char('^');
+        assert_eq!(select.query(), "^päi");
+        edit(&mut select, &[Action::CursorEnd, Action::CursorRight]);
+        assert_eq!(select.cursor, 4);
+        edit(&mut select, &[Action::CursorStart, Action::CursorLeft]);
+        assert_eq!(select.cursor, 0);
+    }
+
+    #[test]
+    fn delete_chars() {
+        let mut select = typed("wörk");
+        edit(&mut select, &[Action::CursorLeft, Action::Backspace]);
+        assert_eq!(select.query(), "wök");
+        edit(&mut select, &[Action::Delete]);
+        assert_eq!(select.query(), "wö");
+        // Nothing to delete after the end or before the start
+        edit(
+            &mut select,
+            &[Action::Delete, Action::CursorStart, Action::Backspace],
+        );
+        assert_eq!(select.query(), "wö");
+    }
+
+    #[test]
+    fn delete_words_and_lines() {
+        let mut select = typed("#backend  pay api");
+        edit(&mut select, &[Action::DeleteWord]);
+        assert_eq!(select.query(), "#backend  pay ");
+        edit(&mut select, &[Action::DeleteWord]);
+        assert_eq!(select.query(), "#backend  ");
+        edit(&mut select, &[Action::DeleteWord]);
+        assert_eq!(select.query(), "");
+
+        let mut select = typed("#backend pay api");
+        edit(&mut select, &[Action::CursorLeft, Action::CursorLeft]);
+        edit(&mut select, &[Action::CursorLeft, Action::CursorLeft]);
+        edit(&mut select, &[Action::KillToEnd]);
+        assert_eq!(select.query(), "#backend pay");
+        edit(&mut select, &[Action::CursorLeft, Action::CursorLeft]);
+        edit(&mut select, &[Action::KillLine]);
+        assert_eq!((select.query(), select.cursor), ("ay", 0));
+        edit(&mut select, &[Action::CursorEnd, Action::ClearQuery]);
+        assert_eq!((select.query(), select.cursor), ("", 0));
+    }
+
+    #[test]
+    fn other_actions_are_not_edits() {
+        let mut select = typed("api");
+        assert!(!select.edit_query(&Action::Down));
+        assert!(!select.edit_query(&Action::Select));
+        assert_eq!(select.query(), "api");
+    }
+
+    #[test]
+    fn recall_queries_newest_first() {
+        let mut select = typed("pa");
+        select.query_history(vec!["web".to_string(), "#backend api".to_string()]);
+        edit(&mut select, &[Action::RecallQuery]);
+        assert_eq!(select.query(), "#backend api");
+        assert_eq!(select.cursor, 12);
+        edit(&mut select, &[Action::RecallQuery]);
+        assert_eq!(select.query(), "web");
+        // Back to the input after the oldest query
+        edit(&mut select, &[Action::RecallQuery]);
+        assert_eq!(select.query(), "pa");
+        edit(&mut select, &[Action::RecallQuery]);
+        assert_eq!(select.query(), "#backend api");
+        // Editing a recalled query starts over from the newest one
+        select.insert_char('s');
+        edit(&mut select, &[Action::RecallQuery]);
+        assert_eq!(select.query(), "#backend api");
+        edit(&mut select, &[Action::RecallQuery, Action::RecallQuery]);
+        assert_eq!(select.query(), "#backend apis");
+    }
+
+    #[test]
+    fn recall_without_history() {
+        let mut select = typed("pa");
+        edit(&mut select, &[Action::RecallQuery]);
+        assert_eq!(select.query(), "pa");
+    }
+
+    #[test]
+    fn help_lists_bound_actions() {
+        let mut select = select();
+        select.keymap(keymap("[insert]\n\"ctrl-o\" = \"run:make test\""));
+        let lines = select.help_lines(40, 60);
+        assert_eq!(lines[0], "Keys:");
+        let line = |action: &str| {
+            lines
+                .iter()
+                .find(|line| line.ends_with(&format!("  {}", action)))
+                .cloned()
+        };
+        assert!(line("help").unwrap().starts_with("  f1  "));
+        assert!(line("run:make test").unwrap().contains("ctrl-o"));
+        assert!(line("search").is_none());
+        // Keys are padded to the widest keys
+        let column = |line: String| line.rfind("  ").unwrap();
+        assert_eq!(column(line("pull").unwrap()), column(line("help").unwrap()));
+    }
+
+    #[test]
+    fn help_sections_of_both_modes() {
+        let mut select = select();
+        select.keymap(keymap("preset = \"vim\""));
+        let lines = select.help_lines(60, 60);
+        assert_eq!(lines[0], "Normal mode:");
+        assert!(lines[1].contains("j, down"));
+        let insert = lines
+            .iter()
+            .position(|line| line == "Insert mode:")
+            .unwrap();
+        assert!(lines[insert + 1..]
+            .iter()
+            .any(|line| line.ends_with("normal-mode")));
+    }
+
+    #[test]
+    fn help_in_columns_if_too_long() {
+        let select = select();
+        let entries = select.keymap.help(Mode::Insert).len();
+        let lines = select.help_lines(10, 200);
+        assert!(lines.len() <= 10);
+        let cells = lines
+            .iter()
+            .skip(1)
+            .map(|line| line.split_whitespace().count())
+            .sum::<usize>();
+        assert!(cells >= entries * 2);
+        // Without room for columns the rest is left out
+        let lines = select.help_lines(5, 30);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[4], format!("  … {} more", entries - 3));
+    }
+
+    #[test]
+    fn footer_hints_and_match_count() {
+        let mut select = select();
+        select.refilter(items(&["~/a", "~/b", "~/c"]));
+        select.total(341);
+        let footer = select.footer(true, 60);
+        assert!(footer.starts_with("enter select  f1 help  esc quit  "));
+        assert!(footer.ends_with(" 3/341"));
+        assert_eq!(display_width(&footer), 60);
+        select.status(Some("by host".to_string()));
+        assert!(select.footer(true, 60).ends_with(" by host  3/341"));
+        // Only the count if the hints don't fit
+        assert_eq!(select.footer(true, 20), "by host  3/341");
+    }
+
+    #[test]
+    fn footer_hints_of_the_mode() {
+        let mut select = select();
+        select.keymap(keymap("preset = \"vim\""));
+        assert!(select
+            .footer(true, 80)
+            .starts_with("enter select  / search  ? help  q quit"));
+        select.mode = Mode::Insert;
+        assert!(select
+            .footer(true, 80)
+            .starts_with("enter select  esc normal-mode  f1 help  ctrl-c quit"));
+        // Without a query line the normal mode is used
+        assert!(select.footer(false, 80).starts_with("enter select  ? help"));
+        select.keymap(keymap("preset = \"emacs\""));
+        assert!(select
+            .footer(true, 80)
+            .starts_with("enter select  f1 help  esc quit"));
+    }
+
+    #[test]
+    fn refilter_keeps_the_selected_item() {
+        let mut select = select();
+        select.refilter(items(&["~/a", "~/b", "~/c", "~/d"]));
+        select.viewport.sel = 2;
+        // Keys are the positions in the original list
+        let mut refined = items(&["~/a", "~/b", "~/c", "~/d"]);
+        refined.remove(1);
+        select.refilter(refined);
+        assert_eq!(select.viewport.sel, 1);
+        assert_eq!(select.items[select.viewport.sel].text, "~/c");
+        select.refilter(items(&["~/a", "~/b"]));
+        assert_eq!(select.viewport.sel, 0);
+        select.refilter(vec![]);
+        assert_eq!(select.viewport, Viewport::default());
+    }
+
+    #[test]
+    fn click_selects_the_item_on_the_row() {
+        let mut select = select();
+        select.refilter(items(&["~/p*", "~/q*", "~/a", "~/b", "~/c"]));
+        // Below the query line: two pinned items and the divider
+        assert!(click(&mut select, 2).is_none());
+        assert_eq!(select.viewport.sel, 1);
+        click(&mut select, 4);
+        assert_eq!(select.viewport.sel, 2);
+        // The query line, the divider and rows without items
+        for row in &[0, 3, 7, 11] {
+            click(&mut select, *row);
+            assert_eq!(select.viewport.sel, 2);
+        }
+        select.viewport.offset = 2;
+        click(&mut select, 1);
+        assert_eq!(select.viewport.sel, 2);
+    }
+
+    #[test]
+    fn double_click_selects_the_item() {
+        let mut select = select();
+        select.refilter(items(&["~/a", "~/b", "~/c"]));
+        click(&mut select, 2);
+        click(&mut select, 3);
+        let result = click(&mut select, 3);
+        assert!(matches!(
+            result,
+            Some(SelectCommand::Select { item_key: 2 })
+        ));
+        // A third click starts over
+        assert!(click(&mut select, 3).is_none());
+        select.last_click = Some((2, Instant::now() - DOUBLE_CLICK));
+        assert!(click(&mut select, 3).is_none());
+    }
+
+    #[test]
+    fn wheel_scrolls_the_items() {
+        let mut select = select();
+        let texts = (0..20).map(|n| format!("~/{}", n)).collect::<Vec<_>>();
+        select.refilter(items(&texts.iter().map(String::as_str).collect::<Vec<_>>()));
+        select.mouse(12, true, &mouse(MouseEventKind::ScrollDown, 5));
+        assert_eq!((select.viewport.offset, select.viewport.sel), (3, 3));
+        for _ in 0..5 {
+            select.mouse(12, true, &mouse(MouseEventKind::ScrollDown, 5));
+        }
+        // Ten rows for the items, the query line and the footer
+        assert_eq!((select.viewport.offset, select.viewport.sel), (10, 10));
+        select.mouse(12, true, &mouse(MouseEventKind::ScrollUp, 5));
+        assert_eq!((select.viewport.offset, select.viewport.sel), (7, 10));
+    }
+
+    #[test]
+    fn click_on_the_output_focuses_it() {
+        let mut select = select();
+        select.refilter(items(&["~/a", "~/b"]));
+        select.output((0..10).map(|n| n.to_string()).collect());
+        // The output pane takes a third of the rows, including its divider
+        assert_eq!(select.output_rows(12), 8..11);
+        click(&mut select, 7);
+        assert!(select.focus == Focus::Output);
+        select.mouse(12, true, &mouse(MouseEventKind::ScrollUp, 9));
+        assert_eq!(select.output_scroll, WHEEL_LINES);
+        select.mouse(12, true, &mouse(MouseEventKind::ScrollDown, 9));
+        assert_eq!(select.output_scroll, 0);
+        click(&mut select, 2);
+        assert!(select.focus == Focus::Items);
+        assert_eq!(select.viewport.sel, 1);
+    }
+
+    #[test]
+    fn no_matches_and_error_banner() {
+        let mut select = select();
+        select.refilter(vec![]);
+        select.error("`git pull` failed");
+        let (lines, cursor) = select.frame((6, 40), None);
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "  No matches");
+        assert_eq!(lines[4], "error: `git pull` failed");
+        assert!(lines[5].ends_with(" 0"));
+        assert_eq!(cursor, None);
+    }
+
+    #[test]
+    fn tiny_screens() {
+        let mut select = select();
+        select.refilter(items(&["~/p*", "~/a", "~/b"]));
+        select.output(vec!["Already up to date.".to_string()]);
+        select.error("failed");
+        for rows in 0..5 {
+            for cols in 0..5 {
+                let (lines, _) = select.frame((rows, cols), None);
+                assert_eq!(lines.last(), Some(&select.footer(false, cols)));
+                select.help = true;
+                assert!(select.frame((rows, cols), None).0.len() <= rows.max(1));
+                select.help = false;
             }
-            render.clear_preserve_prompt(&size_vec)?;
         }
     }
 }
@@ -0,0 +1,349 @@
+//! Local JSON-RPC server exposing the repository index to editors and
+//! launchers.
+//!
+//! Requests and responses are JSON-RPC 2.0 objects, one per line.
+//! Supported methods:
+//!
+//! * `list` - all repos, most frecently visited first
+//! * `search(query)` - fuzzy matched repos, best match first
+//! * `status(repo)` - branch, dirty state and last commit age of a repo
+//! * `record_visit(repo)` - registers a visit in the shared history
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::SystemTime;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::history::History;
+use crate::index::{rank_by_frecency, Index};
+use crate::repo::{self, RepoInfo};
+use crate::search::search;
+use serde_json::{json, Value};
+
+/// Default TCP port on localhost.
+pub const DEFAULT_PORT: u16 = 7373;
+
+/// Where the server listens for connections.
+pub enum Listen {
+    Tcp(u16),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: &str) -> RpcError {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(e: Error) -> RpcError {
+        RpcError::new(SERVER_ERROR, &e.to_string())
+    }
+}
+
+/// Ranked repos shared by all connections.
+///
+/// The repos are ranked once and re-ranked when a visit is recorded, without
+/// opening them again. They are reloaded when the list of known or pinned
+/// repos changed, e.g. after `git quick clone`.
+#[derive(Default)]
+struct State {
+    repos: Mutex<Option<Ranked>>,
+}
+
+struct Ranked {
+    /// Modification times of the repo list and the pinned repos.
+    modified: Vec<Option<SystemTime>>,
+    repos: Arc<Vec<RepoInfo>>,
+}
+
+impl State {
+    /// Returns the ranked repos, loading them if necessary.
+    fn repos(&self) -> Result<Arc<Vec<RepoInfo>>, RpcError> {
+        let mut index = Index::new()?;
+        let modified = [&index.cache_file, &index.pinned_file]
+            .iter()
+            .map(|file| file.metadata().and_then(|m| m.modified()).ok())
+            .collect::<Vec<_>>();
+        let mut ranked = self.repos.lock().unwrap_or_else(PoisonError::into_inner);
+        match ranked.as_ref() {
+            Some(ranked) if ranked.modified == modified => Ok(ranked.repos.clone()),
+            _ => {
+                let repos = Arc::new(index.get_ranked_repos(&Config::load())?);
+                *ranked = Some(Ranked {
+                    modified,
+                    repos: repos.clone(),
+                });
+                Ok(repos)
+            }
+        }
+    }
+
+    /// Ranks the loaded repos by the frecency of `history`.
+    fn rerank(&self, history: &History) {
+        let mut ranked = self.repos.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(ranked) = ranked.as_mut() {
+            rank_by_frecency(Arc::make_mut(&mut ranked.repos).as_mut_slice(), history);
+        }
+    }
+}
+
+/// Accepts connections until the process is terminated. Every connection is
+/// served on its own thread.
+pub fn serve(listen: &Listen) -> io::Result<()> {
+    let state = Arc::new(State::default());
+    match listen {
+        Listen::Tcp(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", *port))?;
+            eprintln!("Listening on 127.0.0.1:{}", port);
+            for stream in listener.incoming() {
+                let stream = stream?;
+                let reader = BufReader::new(stream.try_clone()?);
+                let state = state.clone();
+                thread::spawn(move || handle_connection(&state, reader, stream));
+            }
+        }
+        #[cfg(unix)]
+        Listen::Unix(path) => {
+            use std::os::unix::net::UnixListener;
+
+            // Remove a stale socket from a previous run
+            let _ = std::fs::remove_file(path);
+            let listener = UnixListener::bind(path)?;
+            eprintln!("Listening on {}", path.display());
+            for stream in listener.incoming() {
+                let stream = stream?;
+                let reader = BufReader::new(stream.try_clone()?);
+                let state = state.clone();
+                thread::spawn(move || handle_connection(&state, reader, stream));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_connection<R: BufRead, W: Write>(
+    state: &State,
+    reader: R,
+    mut writer: W,
+) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_request(state, &line);
+        writeln!(writer, "{}", response)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn handle_request(state: &State, line: &str) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return error_response(Value::Null, RpcError::new(PARSE_ERROR, &e.to_string())),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = match request.get("method").and_then(Value::as_str) {
+        Some(method) => method,
+        None => {
+            return error_response(id, RpcError::new(INVALID_REQUEST, "Missing method"));
+        }
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    match dispatch(state, method, &params) {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(e) => error_response(id, e),
+    }
+}
+
+fn error_response(id: Value, e: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": e.code, "message": e.message}
+    })
+}
+
+/// Returns a named or positional string parameter.
+fn string_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+    params
+        .get(name)
+        .or_else(|| params.get(0))
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, &format!("Missing parameter `{}`", name)))
+}
+
+fn dispatch(state: &State, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        "list" => {
+            let history = History::load()?;
+            let repos = state.repos()?;
+            Ok(Value::Array(
+                repos
+                    .iter()
+                    .map(|repo| {
+                        json!({
+                            "path": repo.path,
+                            "tags": repo.tags,
+                            "frecency": history.frecency(&repo.path),
+                        })
+                    })
+                    .collect(),
+            ))
+        }
+        "search" => {
+            let query = string_param(params, "query")?;
+            let repos = state.repos()?;
+            Ok(Value::Array(
+                search(query, &repos)
+                    .iter()
+                    .map(|item| {
+                        json!({
+                            "path": repos[item.item_key].path,
+                            "score": item.score,
+                        })
+                    })
+                    .collect(),
+            ))
+        }
+        "status" => {
+            let path = string_param(params, "repo")?;
+            repo::status(path)
+                .map(|status| json!(status))
+                .map_err(|e| RpcError::new(SERVER_ERROR, e.message()))
+        }
+        "record_visit" => {
+            let path = string_param(params, "repo")?;
+            let mut history = History::load()?;
+            history.record_visit(path);
+            history.save()?;
+            state.rerank(&history);
+            Ok(Value::Null)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            &format!("Unknown method `{}`", method),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    #[cfg(unix)]
+    use std::os::unix::net::UnixStream;
+
+    fn error_code(response: &Value) -> Option<i64> {
+        response["error"]["code"].as_i64()
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let state = State::default();
+        let response = handle_request(&state, "{not json");
+        assert_eq!(error_code(&response), Some(PARSE_ERROR));
+        assert_eq!(response["id"], Value::Null);
+        let response = handle_request(&state, r#"{"jsonrpc": "2.0", "id": 1}"#);
+        assert_eq!(error_code(&response), Some(INVALID_REQUEST));
+        assert_eq!(response["id"], json!(1));
+        let response = handle_request(&state, r#"{"id": "a", "method": "nope"}"#);
+        assert_eq!(error_code(&response), Some(METHOD_NOT_FOUND));
+        assert_eq!(response["id"], json!("a"));
+        let response = handle_request(&state, r#"{"id": 2, "method": "status"}"#);
+        assert_eq!(error_code(&response), Some(INVALID_PARAMS));
+    }
+
+    #[test]
+    fn string_params_by_name_or_position() {
+        assert_eq!(string_param(&json!({"repo": "a"}), "repo").ok(), Some("a"));
+        assert_eq!(string_param(&json!(["a"]), "repo").ok(), Some("a"));
+        assert!(string_param(&json!({"query": "a"}), "repo").is_err());
+        assert!(string_param(&json!([1]), "repo").is_err());
+    }
+
+    #[test]
+    fn one_response_per_line() {
+        let requests = "{\"id\": 1, \"method\": \"a\"}\n\n{\"id\": 2, \"method\": \"b\"}\n";
+        let mut output = Vec::new();
+        handle_connection(&State::default(), Cursor::new(requests), &mut output).unwrap();
+        let ids = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["id"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![json!(1), json!(2)]);
+    }
+
+    #[test]
+    fn rerank_by_recorded_visits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut recent = RepoInfo::new("/nonexistent/recent");
+        recent.hours_since_last_commit = 1;
+        let mut visited = RepoInfo::new("/nonexistent/visited");
+        visited.hours_since_last_commit = 100;
+        let state = State::default();
+        *state.repos.lock().unwrap() = Some(Ranked {
+            modified: Vec::new(),
+            repos: Arc::new(vec![recent, visited]),
+        });
+        let mut history = History::load_file(&dir.path().join("history.txt"));
+        history.record_visit("/nonexistent/visited");
+        state.rerank(&history);
+        let ranked = state.repos.lock().unwrap();
+        let paths = ranked
+            .as_ref()
+            .unwrap()
+            .repos
+            .iter()
+            .map(|r| r.path.as_str());
+        assert_eq!(
+            paths.collect::<Vec<_>>(),
+            vec!["/nonexistent/visited", "/nonexistent/recent"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn open_connection_does_not_block_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("git-quick.sock");
+        let listen = Listen::Unix(socket.clone());
+        thread::spawn(move || serve(&listen));
+        let connect = || {
+            for _ in 0..100 {
+                if let Ok(stream) = UnixStream::connect(&socket) {
+                    return stream;
+                }
+                thread::sleep(std::time::Duration::from_millis(10));
+            }
+            panic!("server did not start");
+        };
+        let _idle = connect();
+        let mut client = connect();
+        writeln!(client, r#"{{"id": 7, "method": "nope"}}"#).unwrap();
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        let response: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(response["id"], json!(7));
+    }
+}
@@ -0,0 +1,468 @@
+//! Commit activity across all repositories: a calendar heatmap of commits per
+//! day, the most active repos and their activity over time.
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Duration, Local, NaiveDate, TimeZone};
+use console::Style;
+use git2::{BranchType, Repository, Sort};
+use serde::Serialize;
+
+use crate::repo::{format_age, RepoInfo};
+use crate::report::table;
+
+/// Default period of `git quick stats`, see `query::parse_hours`.
+pub const DEFAULT_SINCE: &str = "4w";
+
+/// Shades of the heatmap, from no commits to the most commits per day.
+const SHADES: [char; 5] = ['·', '░', '▒', '▓', '█'];
+/// Bars of the sparklines, from the fewest to the most commits.
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Maximal number of characters of a sparkline.
+const SPARKLINE_WIDTH: usize = 28;
+/// Width of the weekday labels of the heatmap.
+const LABEL_WIDTH: usize = 4;
+
+/// Commits of one repository.
+#[derive(Serialize)]
+pub struct RepoStats {
+    pub path: String,
+    #[serde(skip)]
+    pub display: String,
+    pub commits: usize,
+    /// Commits per day, starting at `Stats::first_day`.
+    pub per_day: Vec<usize>,
+    /// Unix timestamp of the most recent counted commit.
+    pub last_commit: Option<i64>,
+}
+
+/// Commits of all repositories since `first_day`.
+#[derive(Serialize)]
+pub struct Stats {
+    #[serde(serialize_with = "serialize_date")]
+    pub first_day: NaiveDate,
+    /// Only commits of these authors are counted, of all authors if empty.
+    pub authors: Vec<String>,
+    pub commits: usize,
+    /// Commits per day, starting at `first_day` and ending today.
+    pub per_day: Vec<usize>,
+    /// Repos with commits, most commits first.
+    pub repos: Vec<RepoStats>,
+    /// Errors by path of the repos which could not be read.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<String, String>,
+}
+
+impl Stats {
+    /// Counts the commits of the local branches of all `repos` since
+    /// `first_day`. Merge commits are left out. Repos which can't be read
+    /// are listed in `errors` and not counted.
+    pub fn new(repos: &[RepoInfo], first_day: NaiveDate, authors: &[String]) -> Stats {
+        let today = Local::now().naive_local().date();
+        let days = (today.signed_duration_since(first_day).num_days() + 1).max(1) as usize;
+        let authors = authors.iter().map(|a| a.to_lowercase()).collect::<Vec<_>>();
+        let mut stats = Stats {
+            first_day,
+            authors,
+            commits: 0,
+            per_day: vec![0; days],
+            repos: Vec::new(),
+            errors: BTreeMap::new(),
+        };
+        for repo in repos {
+            let mut repo_stats = RepoStats {
+                path: repo.path.clone(),
+                display: repo.display.clone(),
+                commits: 0,
+                per_day: vec![0; days],
+                last_commit: None,
+            };
+            if let Err(e) = repo_stats.count(first_day, &stats.authors) {
+                stats
+                    .errors
+                    .insert(repo.path.clone(), e.message().to_string());
+                continue;
+            }
+            if repo_stats.commits == 0 {
+                continue;
+            }
+            stats.commits += repo_stats.commits;
+            for (total, count) in stats.per_day.iter_mut().zip(&repo_stats.per_day) {
+                *total += count;
+            }
+            stats.repos.push(repo_stats);
+        }
+        // sort_by_key is stable, so ties keep the ranking of `repos`
+        stats
+            .repos
+            .sort_by_key(|repo| std::cmp::Reverse(repo.commits));
+        stats
+    }
+
+    /// Formats the summary, the heatmap, the errors and the `top` most
+    /// active repos for a terminal `cols` wide.
+    pub fn format(&self, top: usize, cols: usize) -> Vec<String> {
+        let mut lines = vec![self.summary(), String::new()];
+        lines.extend(self.heatmap(cols));
+        if !self.errors.is_empty() {
+            lines.push(String::new());
+            for (path, error) in &self.errors {
+                lines.push(format!("Could not read {}: {}", path, error));
+            }
+        }
+        if self.repos.is_empty() {
+            return lines;
+        }
+        lines.push(String::new());
+        let rows = self
+            .repos
+            .iter()
+            .take(top)
+            .map(|repo| {
+                let active_days = repo.per_day.iter().filter(|&&n| n > 0).count();
+                let age = repo
+                    .last_commit
+                    .map(|time| (Local::now().timestamp() - time) / 3600)
+                    .unwrap_or(i64::MAX);
+                vec![
+                    repo.display.clone(),
+                    repo.commits.to_string(),
+                    active_days.to_string(),
+                    sparkline(&repo.per_day),
+                    format_age(age),
+                ]
+            })
+            .collect::<Vec<_>>();
+        lines.extend(table(
+            &["Repository", "Commits", "Days", "Activity", "Last commit"],
+            &rows,
+        ));
+        if self.repos.len() > top {
+            lines.push(format!("… {} more", self.repos.len() - top));
+        }
+        lines
+    }
+
+    /// Returns the line summarizing the commits.
+    fn summary(&self) -> String {
+        let by = if self.authors.is_empty() {
+            String::new()
+        } else {
+            format!(" by {}", self.authors.join(", "))
+        };
+        format!(
+            "{} commits{} in {} repositories since {}.",
+            self.commits,
+            by,
+            self.repos.len(),
+            self.first_day.format("%Y-%m-%d")
+        )
+    }
+
+    /// Formats the commits per day as a calendar with a column per week and
+    /// a row per weekday. Weeks which don't fit into `cols` are left out,
+    /// oldest first.
+    fn heatmap(&self, cols: usize) -> Vec<String> {
+        let max = self.per_day.iter().cloned().max().unwrap_or(0);
+        let offset = self.first_day.weekday().num_days_from_monday() as usize;
+        let weeks = (offset + self.per_day.len()).div_ceil(7);
+        let shown = weeks.min((cols.saturating_sub(LABEL_WIDTH) / 2).max(1));
+        let first_week = weeks - shown;
+        let monday = self.first_day - Duration::days(offset as i64);
+        let active = Style::new().green();
+        let inactive = Style::new().dim();
+
+        // Month names above the first week of each month, if there is room
+        let mut months = String::new();
+        let mut last_month = None;
+        for (col, week) in (first_week..weeks).enumerate() {
+            let sunday = monday + Duration::days(week as i64 * 7 + 6);
+            let pos = LABEL_WIDTH + col * 2;
+            if last_month != Some(sunday.month()) && months.len() <= pos {
+                months.push_str(&" ".repeat(pos - months.len()));
+                months.push_str(&sunday.format("%b").to_string());
+            }
+            last_month = Some(sunday.month());
+        }
+        let mut lines = vec![months.trim_end().to_string()];
+        for weekday in 0..7 {
+            let label = match weekday {
+                0 => "Mon",
+                2 => "Wed",
+                4 => "Fri",
+                _ => "",
+            };
+            let mut line = format!("{:width$}", label, width = LABEL_WIDTH);
+            for week in first_week..weeks {
+                let day = (week * 7 + weekday).checked_sub(offset);
+                match day.and_then(|day| self.per_day.get(day)) {
+                    Some(&0) => line.push_str(&format!("{} ", inactive.apply_to(SHADES[0]))),
+                    Some(&count) => {
+                        let shade = SHADES[level(count, max, SHADES.len() - 1) + 1];
+                        line.push_str(&format!("{} ", active.apply_to(shade)));
+                    }
+                    None => line.push_str("  "),
+                }
+            }
+            lines.push(line.trim_end().to_string());
+        }
+        let legend = SHADES
+            .iter()
+            .map(|shade| shade.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        lines.push(format!("{}less {} more", " ".repeat(LABEL_WIDTH), legend));
+        lines
+    }
+}
+
+impl RepoStats {
+    /// Counts the commits reachable from the local branches and HEAD.
+    fn count(&mut self, first_day: NaiveDate, authors: &[String]) -> Result<(), git2::Error> {
+        let git2_repo = Repository::open(&self.path)?;
+        let mut walk = git2_repo.revwalk()?;
+        walk.set_sorting(Sort::TIME);
+        for branch in git2_repo.branches(Some(BranchType::Local))? {
+            if let Some(tip) = branch?.0.get().target() {
+                walk.push(tip)?;
+            }
+        }
+        if let Ok(head) = git2_repo.head() {
+            if let Some(oid) = head.target() {
+                walk.push(oid)?;
+            }
+        }
+        let since = Local
+            .from_local_date(&first_day)
+            .earliest()
+            .map(|date| date.and_hms(0, 0, 0).timestamp())
+            .unwrap_or(0);
+        for oid in walk {
+            let commit = git2_repo.find_commit(oid?)?;
+            // Commits are sorted by commit time, which is after the author
+            // time, so all older commits are before `since`, too
+            if commit.time().seconds() < since {
+                break;
+            }
+            let author = commit.author();
+            let time = author.when().seconds();
+            if commit.parent_count() > 1 || time < since {
+                continue;
+            }
+            let email = author.email().unwrap_or("").to_lowercase();
+            if !authors.is_empty() && !authors.contains(&email) {
+                continue;
+            }
+            let date = Local.timestamp(time, 0).naive_local().date();
+            let day = date.signed_duration_since(first_day).num_days() as usize;
+            if let Some(count) = self.per_day.get_mut(day) {
+                *count += 1;
+                self.commits += 1;
+                self.last_commit = Some(self.last_commit.unwrap_or(time).max(time));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serializes a date like `2024-05-01`.
+fn serialize_date<S: serde::Serializer>(date: &NaiveDate, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&date.format("%Y-%m-%d").to_string())
+}
+
+/// Returns the level of `count` between 0 and `levels - 1`, relative to
+/// `max`.
+fn level(count: usize, max: usize, levels: usize) -> usize {
+    if max == 0 {
+        return 0;
+    }
+    ((count * levels).div_ceil(max)).clamp(1, levels) - 1
+}
+
+/// Draws the commits per day as bars, combining days into at most
+/// `SPARKLINE_WIDTH` bars.
+fn sparkline(per_day: &[usize]) -> String {
+    let days_per_bar = per_day.len().div_ceil(SPARKLINE_WIDTH).max(1);
+    let bars = per_day
+        .chunks(days_per_bar)
+        .map(|days| days.iter().sum::<usize>())
+        .collect::<Vec<_>>();
+    let max = bars.iter().cloned().max().unwrap_or(0);
+    bars.iter()
+        .map(|&count| {
+            if count == 0 {
+                ' '
+            } else {
+                BARS[level(count, max, BARS.len())]
+            }
+        })
+        .collect()
+}
+
+/// Returns the first day of the period given like `4w` (see
+/// `query::parse_hours`) or as a date like `2024-05-01`.
+pub fn parse_since(since: &str) -> Option<NaiveDate> {
+    if let Ok(date) = NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        return Some(date);
+    }
+    let hours = crate::query::parse_hours(since)?;
+    let start = Local::now() - Duration::hours(hours);
+    Some(start.naive_local().date())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    fn stats(first_day: &str, per_day: Vec<usize>) -> Stats {
+        Stats {
+            first_day: date(first_day),
+            authors: Vec::new(),
+            commits: per_day.iter().sum(),
+            per_day,
+            repos: Vec::new(),
+            errors: BTreeMap::new(),
+        }
+    }
+
+    fn heatmap(stats: &Stats, cols: usize) -> Vec<String> {
+        stats
+            .heatmap(cols)
+            .iter()
+            .map(|line| console::strip_ansi_codes(line).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(level(0, 0, 4), 0);
+        assert_eq!(level(1, 4, 4), 0);
+        assert_eq!(level(2, 4, 4), 1);
+        assert_eq!(level(3, 4, 4), 2);
+        assert_eq!(level(4, 4, 4), 3);
+        // Any commit is above the lowest level of a larger max
+        assert_eq!(level(1, 100, 8), 0);
+        assert_eq!(level(13, 100, 8), 1);
+        assert_eq!(level(100, 100, 8), 7);
+    }
+
+    #[test]
+    fn sparklines() {
+        assert_eq!(sparkline(&[]), "");
+        assert_eq!(sparkline(&[0, 1, 2, 4, 8]), " ▁▂▄█");
+        assert_eq!(sparkline(&[3, 0, 3]), "█ █");
+        // Days are combined to fit
+        let per_day = (0..56).map(|day| day % 2).collect::<Vec<_>>();
+        assert_eq!(sparkline(&per_day), "█".repeat(SPARKLINE_WIDTH));
+        let per_day = (0..57).map(|day| day % 2).collect::<Vec<_>>();
+        assert_eq!(sparkline(&per_day).chars().count(), 19);
+    }
+
+    #[test]
+    fn heatmap_starts_mid_week() {
+        // 2024-05-01 is a Wednesday
+        let stats = stats("2024-05-01", vec![1, 0, 0, 0, 0, 2, 0, 0, 0, 4]);
+        assert_eq!(
+            heatmap(&stats, 80),
+            vec![
+                "    May",
+                "Mon   ▒",
+                "      ·",
+                "Wed ░ ·",
+                "    · ·",
+                "Fri · █",
+                "    ·",
+                "    ·",
+                "    less · ░ ▒ ▓ █ more",
+            ]
+        );
+    }
+
+    #[test]
+    fn heatmap_month_labels() {
+        // 13 weeks starting on Monday, 2024-01-01
+        let quarter = stats("2024-01-01", vec![0; 91]);
+        let lines = heatmap(&quarter, 80);
+        assert_eq!(lines[0], "    Jan     Feb     Mar");
+        assert_eq!(lines[1].chars().count(), 4 + 13 * 2 - 1);
+        // Only the last weeks fit
+        let lines = heatmap(&quarter, 14);
+        assert_eq!(lines[0], "    Mar");
+        assert_eq!(lines[1], "Mon · · · · ·");
+        // No room for the label of a month starting the week after
+        let late = stats("2024-01-22", vec![0; 14]);
+        assert_eq!(heatmap(&late, 80)[0], "    Jan");
+    }
+
+    #[test]
+    fn since_a_date_or_period() {
+        assert_eq!(parse_since("2024-05-01"), Some(date("2024-05-01")));
+        let today = Local::now().naive_local().date();
+        let since = parse_since("4w").unwrap();
+        assert!((27..=28).contains(&today.signed_duration_since(since).num_days()));
+        assert_eq!(parse_since("0d"), Some(today));
+        assert_eq!(parse_since("2024-13-01"), None);
+        assert_eq!(parse_since("4 weeks"), None);
+    }
+
+    #[test]
+    fn count_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut repos = Vec::new();
+        for name in ["api", "web", "docs"] {
+            let path = dir.path().join(name);
+            let git2_repo = test_util::init(&path);
+            if name != "docs" {
+                test_util::commit_as(&git2_repo, "README", "me@example.com", 0);
+                test_util::commit_as(&git2_repo, "README", "Other@example.com", 0);
+            }
+            if name == "api" {
+                test_util::commit_as(&git2_repo, "README", "me@example.com", 48);
+                // Too old to count
+                test_util::commit_as(&git2_repo, "README", "me@example.com", 24 * 30);
+            }
+            let mut repo = RepoInfo::new(path.to_str().unwrap());
+            repo.display = name.to_string();
+            repos.push(repo);
+        }
+        let mut missing = RepoInfo::new("/nonexistent/repo");
+        missing.display = "missing".to_string();
+        repos.push(missing);
+        let first_day = Local::now().naive_local().date() - Duration::days(6);
+
+        let stats = Stats::new(&repos, first_day, &[]);
+        assert_eq!(stats.commits, 5);
+        assert_eq!(stats.per_day.len(), 7);
+        assert_eq!(stats.per_day.iter().sum::<usize>(), 5);
+        assert_eq!(
+            stats
+                .repos
+                .iter()
+                .map(|repo| (repo.display.as_str(), repo.commits))
+                .collect::<Vec<_>>(),
+            vec![("api", 3), ("web", 2)]
+        );
+        assert_eq!(
+            stats.errors.keys().collect::<Vec<_>>(),
+            vec!["/nonexistent/repo"]
+        );
+        let lines = stats.format(10, 80);
+        assert!(lines[0].starts_with("5 commits in 2 repositories since "));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("Could not read /nonexistent/repo: ")));
+
+        let stats = Stats::new(&repos, first_day, &["OTHER@example.com".to_string()]);
+        assert_eq!(stats.commits, 2);
+        assert!(stats
+            .summary()
+            .starts_with("2 commits by other@example.com in 2 "));
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["repos"][0]["commits"], 1);
+        assert!(json["errors"]["/nonexistent/repo"].is_string());
+    }
+}
@@ -0,0 +1,224 @@
+//! Repository tags, assigned explicitly or by group rules.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::error::{FileContext, Result};
+use crate::index::{cache_path, create_parent_dir};
+use crate::path_util::{expand_tilde, rebase_path};
+use crate::repo::RepoInfo;
+use glob::Pattern;
+
+const TAGS_FILE: &str = "tags.txt";
+
+/// Tags assigned with `git quick tag`.
+pub struct Tags {
+    /// Path of the tags file.
+    ///
+    /// Default: `tags.txt` in the user's XDG cache directory.
+    pub tags_file: PathBuf,
+    assigned: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Tags {
+    /// Reads the tags file. A missing file yields no tags.
+    pub fn load() -> Result<Tags> {
+        Ok(Tags::load_file(&cache_path(TAGS_FILE)?))
+    }
+
+    /// Reads the tags file at `tags_file`.
+    pub fn load_file(tags_file: &Path) -> Tags {
+        let mut assigned = BTreeMap::new();
+        if let Ok(f) = File::open(tags_file) {
+            let reader = BufReader::new(f);
+            for line in reader.lines().map_while(io::Result::ok) {
+                // <tag>,<tag>\t<path>
+                if let Some((tags, path)) = line.split_once('\t') {
+                    let tags = tags.split(',').map(|t| t.to_string()).collect();
+                    assigned.insert(path.to_string(), tags);
+                }
+            }
+        }
+        Tags {
+            tags_file: tags_file.to_path_buf(),
+            assigned,
+        }
+    }
+
+    /// Writes the tags file.
+    pub fn save(&self) -> Result<()> {
+        create_parent_dir(&self.tags_file)?;
+        let file = &self.tags_file;
+        let mut f = File::create(file).file_context(file)?;
+        for (path, tags) in self.assigned.iter().filter(|(_, tags)| !tags.is_empty()) {
+            let tags = tags.iter().cloned().collect::<Vec<_>>().join(",");
+            writeln!(f, "{}\t{}", tags, path).file_context(file)?;
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, repo: &str, tag: &str) {
+        self.assigned
+            .entry(repo.to_string())
+            .or_default()
+            .insert(tag.to_string());
+    }
+
+    pub fn remove(&mut self, repo: &str, tag: &str) {
+        if let Some(tags) = self.assigned.get_mut(repo) {
+            tags.remove(tag);
+        }
+    }
+
+    /// Moves the tags of `from`, and of the repos inside it, to `to`.
+    pub fn move_repo(&mut self, from: &str, to: &str) {
+        let moved = self
+            .assigned
+            .keys()
+            .filter_map(|path| Some((path.clone(), rebase_path(path, from, to)?)))
+            .collect::<Vec<_>>();
+        for (old, new) in moved {
+            if let Some(tags) = self.assigned.remove(&old) {
+                self.assigned.insert(new, tags);
+            }
+        }
+    }
+
+    /// Sets the tags of all `repos` from the tags file, the config and the
+    /// group rules.
+    pub fn apply(&self, repos: &mut [RepoInfo], config: &Config) {
+        let config_tags = config
+            .tags
+            .iter()
+            .map(|(path, tags)| (expand_tilde(path), tags))
+            .collect::<Vec<_>>();
+        let rules = config
+            .groups
+            .iter()
+            .map(|rule| {
+                let path = rule.path.as_ref().map(|p| Pattern::new(&expand_tilde(p)));
+                let remote = rule.remote.as_ref().map(|p| Pattern::new(p));
+                (&rule.tag, path, remote)
+            })
+            .collect::<Vec<_>>();
+        for repo in repos.iter_mut() {
+            let mut tags = BTreeSet::new();
+            if let Some(assigned) = self.assigned.get(&repo.path) {
+                tags.extend(assigned.iter().cloned());
+            }
+            for (path, path_tags) in config_tags.iter() {
+                if path == &repo.path {
+                    tags.extend(path_tags.iter().cloned());
+                }
+            }
+            for (tag, path, remote) in rules.iter() {
+                let path_matches = match path {
+                    Some(Ok(pattern)) => pattern.matches(&repo.path),
+                    Some(Err(_)) => false,
+                    None => true,
+                };
+                let remote_matches = match (remote, &repo.remote_url) {
+                    (Some(Ok(pattern)), Some(url)) => pattern.matches(url),
+                    (Some(_), _) => false,
+                    (None, _) => true,
+                };
+                if path_matches && remote_matches && (path.is_some() || remote.is_some()) {
+                    tags.insert(tag.to_string());
+                }
+            }
+            repo.tags = tags.into_iter().collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo(path: &str, remote_url: Option<&str>) -> RepoInfo {
+        let mut repo = RepoInfo::new(path);
+        repo.remote_url = remote_url.map(|url| url.to_string());
+        repo
+    }
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn save_and_load_assigned_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("tags.txt");
+        let mut tags = Tags::load_file(&file);
+        tags.add("/work/api", "backend");
+        tags.add("/work/api", "customer-x");
+        tags.add("/work/web", "frontend");
+        tags.remove("/work/web", "frontend");
+        tags.save().unwrap();
+        let mut repos = [repo("/work/api", None), repo("/work/web", None)];
+        Tags::load_file(&file).apply(&mut repos, &Config::default());
+        assert_eq!(repos[0].tags, vec!["backend", "customer-x"]);
+        assert!(repos[1].tags.is_empty());
+    }
+
+    #[test]
+    fn config_tags_and_group_rules() {
+        let config = config(
+            r#"
+            [tags]
+            "/work/api" = ["backend"]
+
+            [[groups]]
+            tag = "ops"
+            path = "/ops/**"
+
+            [[groups]]
+            tag = "acme"
+            remote = "*github.com?acme/*"
+
+            [[groups]]
+            tag = "acme-ops"
+            path = "/ops/**"
+            remote = "*github.com?acme/*"
+
+            [[groups]]
+            tag = "everything"
+            "#,
+        );
+        let mut repos = [
+            repo("/work/api", Some("git@github.com:acme/api.git")),
+            repo("/ops/terraform", Some("https://github.com/acme/terraform")),
+            repo("/ops/scripts", None),
+            repo("/work/web", Some("https://gitlab.com/acme/web")),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let mut tags = Tags::load_file(&dir.path().join("tags.txt"));
+        tags.add("/work/web", "frontend");
+        tags.apply(&mut repos, &config);
+        assert_eq!(repos[0].tags, vec!["acme", "backend"]);
+        assert_eq!(repos[1].tags, vec!["acme", "acme-ops", "ops"]);
+        assert_eq!(repos[2].tags, vec!["ops"]);
+        assert_eq!(repos[3].tags, vec!["frontend"]);
+    }
+
+    #[test]
+    fn move_repo_moves_nested_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tags = Tags::load_file(&dir.path().join("tags.txt"));
+        tags.add("/old/api", "backend");
+        tags.add("/old/api/vendor/lib", "vendor");
+        tags.add("/old/api-docs", "docs");
+        tags.move_repo("/old/api", "/new/api");
+        let mut repos = [
+            repo("/new/api", None),
+            repo("/new/api/vendor/lib", None),
+            repo("/old/api-docs", None),
+        ];
+        tags.apply(&mut repos, &Config::default());
+        assert_eq!(repos[0].tags, vec!["backend"]);
+        assert_eq!(repos[1].tags, vec!["vendor"]);
+        assert_eq!(repos[2].tags, vec!["docs"]);
+    }
+}
@@ -0,0 +1,75 @@
+//! Helpers for tests on real git repositories.
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use chrono::Utc;
+use git2::{BranchType, Oid, Repository, Signature, Time};
+
+/// Creates a repository in `dir` with the remote `origin`.
+pub fn init(dir: &Path) -> Repository {
+    let git2_repo = Repository::init(dir).unwrap();
+    git2_repo
+        .remote("origin", "https://example.com/acme/api.git")
+        .unwrap();
+    git2_repo
+}
+
+/// Appends a line to `file` and commits it on the current branch.
+pub fn commit(git2_repo: &Repository, file: &str) -> Oid {
+    commit_as(git2_repo, file, "me@example.com", 0)
+}
+
+/// Like `commit`, but authored by `email` `hours_ago` hours ago.
+pub fn commit_as(git2_repo: &Repository, file: &str, email: &str, hours_ago: i64) -> Oid {
+    let workdir = git2_repo.workdir().unwrap();
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(workdir.join(file))
+        .unwrap();
+    writeln!(f, "change").unwrap();
+    let mut index = git2_repo.index().unwrap();
+    index.add_path(Path::new(file)).unwrap();
+    index.write().unwrap();
+    let tree = git2_repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let time = Time::new(Utc::now().timestamp() - hours_ago * 3600, 0);
+    let sig = Signature::new("Me", email, &time).unwrap();
+    let parent = git2_repo
+        .head()
+        .ok()
+        .and_then(|head| head.peel_to_commit().ok());
+    git2_repo
+        .commit(
+            Some("HEAD"),
+            &sig,
+            &sig,
+            "change",
+            &tree,
+            parent.iter().collect::<Vec<_>>().as_slice(),
+        )
+        .unwrap()
+}
+
+/// Returns the name of the current branch, which needs a commit.
+pub fn head_branch(git2_repo: &Repository) -> String {
+    git2_repo.head().unwrap().shorthand().unwrap().to_string()
+}
+
+/// Sets `origin/<branch>` to `target` and makes it the upstream of the local
+/// `branch`, as if `target` was pushed.
+pub fn push(git2_repo: &Repository, branch: &str, target: Oid) {
+    git2_repo
+        .reference(
+            &format!("refs/remotes/origin/{}", branch),
+            target,
+            true,
+            "push",
+        )
+        .unwrap();
+    git2_repo
+        .find_branch(branch, BranchType::Local)
+        .unwrap()
+        .set_upstream(Some(&format!("origin/{}", branch)))
+        .unwrap();
+}
@@ -2,8 +2,35 @@
 use std::fmt;
 use std::io;
 
+use crate::config::ThemeConfig;
 use console::{Style, Term};
 
+/// Terms of a style string, see `parse_style`.
+const STYLE_TERMS: &[&str] = &[
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "on_black",
+    "on_red",
+    "on_green",
+    "on_yellow",
+    "on_blue",
+    "on_magenta",
+    "on_cyan",
+    "on_white",
+    "bold",
+    "dim",
+    "underlined",
+    "blink",
+    "reverse",
+    "hidden",
+];
+
 /// Rendering style for a selected item
 #[derive(Debug, Clone, Copy)]
 pub enum SelectionStyle {
@@ -19,19 +46,23 @@ pub enum SelectionStyle {
     MenuSelected,
     /// Renders un unselected menu item
     MenuUnselected,
+    /// Renders a selected pinned menu item
+    MenuPinnedSelected,
+    /// Renders an unselected pinned menu item
+    MenuPinnedUnselected,
 }
 
 /// Implements a theme for dialoguer.
 pub trait Theme {
     /// Given a prompt this formats out what the prompt should look like (multiline).
-    fn format_prompt(&self, f: &mut fmt::Write, prompt: &str) -> fmt::Result {
+    fn format_prompt(&self, f: &mut dyn fmt::Write, prompt: &str) -> fmt::Result {
         write!(f, "{}:", prompt)
     }
 
     /// Given a prompt this formats out what the prompt should look like (singleline).
     fn format_singleline_prompt(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         default: Option<&str>,
     ) -> fmt::Result {
@@ -42,14 +73,14 @@ pub trait Theme {
     }
 
     /// Formats out an error.
-    fn format_error(&self, f: &mut fmt::Write, err: &str) -> fmt::Result {
+    fn format_error(&self, f: &mut dyn fmt::Write, err: &str) -> fmt::Result {
         write!(f, "error: {}", err)
     }
 
     /// Formats a confirmation prompt.
     fn format_confirmation_prompt(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         default: Option<bool>,
     ) -> fmt::Result {
@@ -65,7 +96,7 @@ pub trait Theme {
     /// Formats a confirmation prompt.
     fn format_confirmation_prompt_selection(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         selection: bool,
     ) -> fmt::Result {
@@ -75,7 +106,7 @@ pub trait Theme {
     /// Renders a prompt and a single selection made.
     fn format_single_prompt_selection(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         sel: &str,
     ) -> fmt::Result {
@@ -85,7 +116,7 @@ pub trait Theme {
     /// Renders a prompt and multiple selections,
     fn format_multi_prompt_selection(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         selections: &[&str],
     ) -> fmt::Result {
@@ -97,14 +128,18 @@ pub trait Theme {
     }
 
     /// Renders a prompt and multiple selections,
-    fn format_password_prompt_selection(&self, f: &mut fmt::Write, prompt: &str) -> fmt::Result {
+    fn format_password_prompt_selection(
+        &self,
+        f: &mut dyn fmt::Write,
+        prompt: &str,
+    ) -> fmt::Result {
         self.format_single_prompt_selection(f, prompt, "[hidden]")
     }
 
     /// Formats a selection.
     fn format_selection(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         text: &str,
         style: SelectionStyle,
     ) -> fmt::Result {
@@ -118,10 +153,75 @@ pub trait Theme {
                 SelectionStyle::CheckboxCheckedUnselected => "  [x] ",
                 SelectionStyle::MenuSelected => "> ",
                 SelectionStyle::MenuUnselected => "  ",
+                SelectionStyle::MenuPinnedSelected => ">*",
+                SelectionStyle::MenuPinnedUnselected => " *",
             },
             text
         )
     }
+
+    /// Formats the divider between pinned and other items.
+    fn format_divider(&self, f: &mut dyn fmt::Write) -> fmt::Result {
+        write!(f, "  --")
+    }
+
+    /// Formats the label shown after the text of an item.
+    fn format_item_label(&self, f: &mut dyn fmt::Write, label: &str) -> fmt::Result {
+        write!(f, "  [{}]", label)
+    }
+
+    /// Formats the line shown in place of the items if none match.
+    fn format_no_matches(&self, f: &mut dyn fmt::Write) -> fmt::Result {
+        write!(f, "  No matches")
+    }
+
+    /// Returns the style of characters matching the query.
+    fn highlight_style(&self) -> Style {
+        Style::new().on_blue()
+    }
+
+    /// Formats a row of the scrollbar, showing the thumb or the track.
+    fn format_scrollbar(&self, f: &mut dyn fmt::Write, thumb: bool) -> fmt::Result {
+        write!(f, "{}", if thumb { "#" } else { "|" })
+    }
+
+    /// Formats the divider above the output pane.
+    fn format_pane_divider(&self, f: &mut dyn fmt::Write, focused: bool) -> fmt::Result {
+        write!(f, "  {}", if focused { "==" } else { "--" })
+    }
+
+    /// Formats a line of the key bindings help. `keys` is padded to
+    /// `keys_width` columns.
+    fn format_help_entry(
+        &self,
+        f: &mut dyn fmt::Write,
+        keys: &str,
+        action: &str,
+        keys_width: usize,
+    ) -> fmt::Result {
+        write!(f, "  {:<width$}  {}", keys, action, width = keys_width)
+    }
+
+    /// Formats the key hints of the footer, given as (key, action) pairs.
+    fn format_hints(&self, f: &mut dyn fmt::Write, hints: &[(String, String)]) -> fmt::Result {
+        for (idx, (key, action)) in hints.iter().enumerate() {
+            write!(f, "{}{} {}", if idx == 0 { "" } else { "  " }, key, action)?;
+        }
+        Ok(())
+    }
+
+    /// Formats the number of matches and of all items, e.g. `12/341`.
+    fn format_match_count(
+        &self,
+        f: &mut dyn fmt::Write,
+        matches: usize,
+        total: Option<usize>,
+    ) -> fmt::Result {
+        match total {
+            Some(total) => write!(f, "{}/{}", matches, total),
+            None => write!(f, "{}", matches),
+        }
+    }
 }
 
 /// The default theme.
@@ -149,14 +249,14 @@ impl Default for CustomPromptCharacterTheme {
 }
 impl Theme for CustomPromptCharacterTheme {
     /// Given a prompt this formats out what the prompt should look like (multiline).
-    fn format_prompt(&self, f: &mut fmt::Write, prompt: &str) -> fmt::Result {
+    fn format_prompt(&self, f: &mut dyn fmt::Write, prompt: &str) -> fmt::Result {
         write!(f, "{}{}", prompt, self.prompt_character)
     }
 
     /// Given a prompt this formats out what the prompt should look like (singleline).
     fn format_singleline_prompt(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         default: Option<&str>,
     ) -> fmt::Result {
@@ -168,7 +268,7 @@ impl Theme for CustomPromptCharacterTheme {
     /// Renders a prompt and a single selection made.
     fn format_single_prompt_selection(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         sel: &str,
     ) -> fmt::Result {
@@ -178,7 +278,7 @@ impl Theme for CustomPromptCharacterTheme {
     /// Renders a prompt and multiple selections,
     fn format_multi_prompt_selection(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         selections: &[&str],
     ) -> fmt::Result {
@@ -207,10 +307,23 @@ pub struct ColorfulTheme {
     pub no_style: Style,
     /// The style for values embedded in prompts
     pub values_style: Style,
+    /// The style for characters matching the query
+    pub highlight_style: Style,
+    /// The style for prompts and headings
+    pub prompt_style: Style,
+    /// The style for the footer and status columns
+    pub status_style: Style,
 }
 
 impl Default for ColorfulTheme {
     fn default() -> ColorfulTheme {
+        ColorfulTheme::dark()
+    }
+}
+
+impl ColorfulTheme {
+    /// Creates the theme for terminals with a dark background.
+    pub fn dark() -> ColorfulTheme {
         ColorfulTheme {
             defaults_style: Style::new().dim(),
             error_style: Style::new().red(),
@@ -220,18 +333,87 @@ impl Default for ColorfulTheme {
             yes_style: Style::new().green(),
             no_style: Style::new().green(),
             values_style: Style::new().cyan(),
+            highlight_style: Style::new().on_blue(),
+            prompt_style: Style::new(),
+            status_style: Style::new().dim(),
+        }
+    }
+
+    /// Creates the theme for terminals with a light background.
+    pub fn light() -> ColorfulTheme {
+        ColorfulTheme {
+            defaults_style: Style::new().dim(),
+            error_style: Style::new().red(),
+            indicator_style: Style::new().blue().bold(),
+            inactive_style: Style::new(),
+            active_style: Style::new().bold(),
+            yes_style: Style::new().green(),
+            no_style: Style::new().green(),
+            values_style: Style::new().blue(),
+            highlight_style: Style::new().black().on_yellow(),
+            prompt_style: Style::new().bold(),
+            status_style: Style::new().blue(),
         }
     }
+
+    /// Creates the theme from a preset and the user's styles.
+    pub fn from_config(config: &ThemeConfig) -> Result<ColorfulTheme, String> {
+        let mut theme = match config.preset.as_deref().unwrap_or("dark") {
+            "dark" => ColorfulTheme::dark(),
+            "light" => ColorfulTheme::light(),
+            preset => return Err(format!("unknown preset `{}`", preset)),
+        };
+        let styles = [
+            (&config.selected, &mut theme.active_style),
+            (&config.unselected, &mut theme.inactive_style),
+            (&config.indicator, &mut theme.indicator_style),
+            (&config.highlight, &mut theme.highlight_style),
+            (&config.prompt, &mut theme.prompt_style),
+            (&config.status, &mut theme.status_style),
+        ];
+        for (value, style) in styles {
+            if let Some(value) = value {
+                *style = parse_style(value)?;
+            }
+        }
+        Ok(theme)
+    }
+}
+
+/// Parses a style like `black.on_yellow` or `cyan bold`. An empty string is
+/// the unstyled text.
+pub fn parse_style(value: &str) -> Result<Style, String> {
+    let terms = value
+        .split(|c: char| c == '.' || c.is_whitespace())
+        .filter(|term| !term.is_empty())
+        .collect::<Vec<_>>();
+    if let Some(term) = terms.iter().find(|term| !STYLE_TERMS.contains(term)) {
+        return Err(format!("unknown style `{}` in `{}`", term, value));
+    }
+    Ok(Style::from_dotted_str(&terms.join(".")))
+}
+
+/// Returns `true` if colors should be written to `term`, which is the
+/// case for terminals unless `NO_COLOR` is set.
+pub fn use_colors(term: &Term) -> bool {
+    colors_enabled(std::env::var_os("NO_COLOR").as_deref(), term.is_term())
+}
+
+/// Returns `true` for terminals unless `no_color`, the value of `NO_COLOR`,
+/// is set and not empty.
+fn colors_enabled(no_color: Option<&std::ffi::OsStr>, is_term: bool) -> bool {
+    let no_color = no_color.is_some_and(|value| !value.is_empty());
+    !no_color && is_term
 }
 
 impl Theme for ColorfulTheme {
-    fn format_prompt(&self, f: &mut fmt::Write, prompt: &str) -> fmt::Result {
-        write!(f, "{}:", prompt)
+    fn format_prompt(&self, f: &mut dyn fmt::Write, prompt: &str) -> fmt::Result {
+        write!(f, "{}", self.prompt_style.apply_to(format!("{}:", prompt)))
     }
 
     fn format_singleline_prompt(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         default: Option<&str>,
     ) -> fmt::Result {
@@ -239,20 +421,20 @@ impl Theme for ColorfulTheme {
             Some(default) => write!(
                 f,
                 "{} [{}]: ",
-                prompt,
+                self.prompt_style.apply_to(prompt),
                 self.defaults_style.apply_to(default)
             ),
-            None => write!(f, "{}: ", prompt),
+            None => write!(f, "{}: ", self.prompt_style.apply_to(prompt)),
         }
     }
 
-    fn format_error(&self, f: &mut fmt::Write, err: &str) -> fmt::Result {
+    fn format_error(&self, f: &mut dyn fmt::Write, err: &str) -> fmt::Result {
         write!(f, "{}: {}", self.error_style.apply_to("error"), err)
     }
 
     fn format_confirmation_prompt(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         default: Option<bool>,
     ) -> fmt::Result {
@@ -267,7 +449,7 @@ impl Theme for ColorfulTheme {
 
     fn format_confirmation_prompt_selection(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         selection: bool,
     ) -> fmt::Result {
@@ -285,7 +467,7 @@ impl Theme for ColorfulTheme {
 
     fn format_single_prompt_selection(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         sel: &str,
     ) -> fmt::Result {
@@ -294,7 +476,7 @@ impl Theme for ColorfulTheme {
 
     fn format_multi_prompt_selection(
         &self,
-        f: &mut fmt::Write,
+        f: &mut dyn fmt::Write,
         prompt: &str,
         selections: &[&str],
     ) -> fmt::Result {
@@ -310,7 +492,12 @@ impl Theme for ColorfulTheme {
         Ok(())
     }
 
-    fn format_selection(&self, f: &mut fmt::Write, text: &str, st: SelectionStyle) -> fmt::Result {
+    fn format_selection(
+        &self,
+        f: &mut dyn fmt::Write,
+        text: &str,
+        st: SelectionStyle,
+    ) -> fmt::Result {
         match st {
             SelectionStyle::CheckboxUncheckedSelected => write!(
                 f,
@@ -341,24 +528,110 @@ impl Theme for ColorfulTheme {
                 self.active_style.apply_to(text)
             ),
             SelectionStyle::MenuUnselected => write!(f, "  {}", self.inactive_style.apply_to(text)),
+            SelectionStyle::MenuPinnedSelected => write!(
+                f,
+                "{}{}",
+                self.indicator_style.apply_to(">*"),
+                self.active_style.apply_to(text)
+            ),
+            SelectionStyle::MenuPinnedUnselected => write!(
+                f,
+                " {}{}",
+                self.indicator_style.apply_to("*"),
+                self.inactive_style.apply_to(text)
+            ),
+        }
+    }
+
+    fn format_divider(&self, f: &mut dyn fmt::Write) -> fmt::Result {
+        write!(f, "  {}", self.inactive_style.apply_to("--"))
+    }
+
+    fn format_no_matches(&self, f: &mut dyn fmt::Write) -> fmt::Result {
+        write!(f, "  {}", self.status_style.apply_to("No matches"))
+    }
+
+    fn format_item_label(&self, f: &mut dyn fmt::Write, label: &str) -> fmt::Result {
+        write!(f, "  {}", self.status_style.apply_to(label))
+    }
+
+    fn highlight_style(&self) -> Style {
+        self.highlight_style.clone()
+    }
+
+    fn format_scrollbar(&self, f: &mut dyn fmt::Write, thumb: bool) -> fmt::Result {
+        if thumb {
+            write!(f, "{}", self.indicator_style.apply_to("┃"))
+        } else {
+            write!(f, "{}", self.status_style.apply_to("│"))
+        }
+    }
+
+    fn format_pane_divider(&self, f: &mut dyn fmt::Write, focused: bool) -> fmt::Result {
+        if focused {
+            write!(f, "  {}", self.indicator_style.apply_to("=="))
+        } else {
+            write!(f, "  {}", self.inactive_style.apply_to("--"))
+        }
+    }
+
+    fn format_help_entry(
+        &self,
+        f: &mut dyn fmt::Write,
+        keys: &str,
+        action: &str,
+        keys_width: usize,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "  {}  {}",
+            self.indicator_style
+                .apply_to(format!("{:<width$}", keys, width = keys_width)),
+            self.active_style.apply_to(action)
+        )
+    }
+
+    fn format_hints(&self, f: &mut dyn fmt::Write, hints: &[(String, String)]) -> fmt::Result {
+        for (idx, (key, action)) in hints.iter().enumerate() {
+            write!(
+                f,
+                "{}{} {}",
+                if idx == 0 { "" } else { "  " },
+                self.values_style.apply_to(key),
+                self.status_style.apply_to(action)
+            )?;
         }
+        Ok(())
+    }
+
+    fn format_match_count(
+        &self,
+        f: &mut dyn fmt::Write,
+        matches: usize,
+        total: Option<usize>,
+    ) -> fmt::Result {
+        let count = match total {
+            Some(total) => format!("{}/{}", matches, total),
+            None => matches.to_string(),
+        };
+        write!(f, "{}", self.status_style.apply_to(count))
     }
 }
 
 /// Helper struct to conveniently render a theme ot a term.
 pub(crate) struct TermThemeRenderer<'a> {
     term: &'a Term,
-    theme: &'a Theme,
+    theme: &'a dyn Theme,
     height: usize,
     prompt_height: usize,
     prompts_reset_height: bool,
 }
 
 impl<'a> TermThemeRenderer<'a> {
-    pub fn new(term: &'a Term, theme: &'a Theme) -> TermThemeRenderer<'a> {
+    pub fn new(term: &'a Term, theme: &'a dyn Theme) -> TermThemeRenderer<'a> {
         TermThemeRenderer {
-            term: term,
-            theme: theme,
+            term,
+            theme,
             height: 0,
             prompt_height: 0,
             prompts_reset_height: true,
@@ -377,27 +650,33 @@ impl<'a> TermThemeRenderer<'a> {
         self.height += 1;
     }
 
-    fn write_formatted_str<F: FnOnce(&mut TermThemeRenderer, &mut fmt::Write) -> fmt::Result>(
+    fn write_formatted_str<
+        F: FnOnce(&mut TermThemeRenderer, &mut dyn fmt::Write) -> fmt::Result,
+    >(
         &mut self,
         f: F,
     ) -> io::Result<()> {
         let mut buf = String::new();
-        f(self, &mut buf).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        f(self, &mut buf).map_err(io::Error::other)?;
         self.height += buf.chars().filter(|&x| x == '\n').count();
         self.term.write_str(&buf)
     }
 
-    fn write_formatted_line<F: FnOnce(&mut TermThemeRenderer, &mut fmt::Write) -> fmt::Result>(
+    fn write_formatted_line<
+        F: FnOnce(&mut TermThemeRenderer, &mut dyn fmt::Write) -> fmt::Result,
+    >(
         &mut self,
         f: F,
     ) -> io::Result<()> {
         let mut buf = String::new();
-        f(self, &mut buf).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        f(self, &mut buf).map_err(io::Error::other)?;
         self.height += buf.chars().filter(|&x| x == '\n').count() + 1;
         self.term.write_line(&buf)
     }
 
-    fn write_formatted_prompt<F: FnOnce(&mut TermThemeRenderer, &mut fmt::Write) -> fmt::Result>(
+    fn write_formatted_prompt<
+        F: FnOnce(&mut TermThemeRenderer, &mut dyn fmt::Write) -> fmt::Result,
+    >(
         &mut self,
         f: F,
     ) -> io::Result<()> {
@@ -472,24 +751,92 @@ impl<'a> TermThemeRenderer<'a> {
         self.height = 0;
         Ok(())
     }
-
-    pub fn clear_preserve_prompt(&mut self, size_vec: &Vec<usize>) -> io::Result<()> {
-        let mut new_height = self.height;
-        //Check each item size, increment on finding an overflow
-        for size in size_vec {
-            if *size > self.term.size().1 as usize {
-                new_height += 1;
-            }
-        }
-        self.term.clear_last_lines(new_height)?;
-        self.height = 0;
-        Ok(())
-    }
 }
 
 /// Returns the default theme.
 ///
 /// (This returns the simple theme)
-pub(crate) fn get_default_theme() -> &'static Theme {
+pub(crate) fn get_default_theme() -> &'static dyn Theme {
     &SimpleTheme
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn theme(config: &str) -> Result<ColorfulTheme, String> {
+        ColorfulTheme::from_config(&toml::from_str(config).unwrap())
+    }
+
+    #[test]
+    fn parse_styles() {
+        let style = parse_style("black.on_yellow").unwrap();
+        assert_eq!(style, Style::new().black().on_yellow());
+        assert_eq!(
+            parse_style(" cyan  bold ").unwrap(),
+            Style::new().cyan().bold()
+        );
+        assert_eq!(
+            parse_style("cyan.bold").unwrap(),
+            parse_style("bold cyan").unwrap()
+        );
+        assert_eq!(parse_style("").unwrap(), Style::new());
+        assert_eq!(
+            parse_style("bold.teal"),
+            Err("unknown style `teal` in `bold.teal`".to_string())
+        );
+        assert!(parse_style("Cyan").is_err());
+    }
+
+    #[test]
+    fn presets() {
+        assert_eq!(
+            theme("").unwrap().highlight_style,
+            ColorfulTheme::dark().highlight_style
+        );
+        let light = theme("preset = \"light\"").unwrap();
+        assert_eq!(light.highlight_style, Style::new().black().on_yellow());
+        assert_eq!(light.active_style, Style::new().bold());
+        assert_eq!(
+            theme("preset = \"solarized\"").err().unwrap(),
+            "unknown preset `solarized`"
+        );
+    }
+
+    #[test]
+    fn styles_replace_the_preset() {
+        let theme = theme(
+            "preset = \"light\"\n\
+             selected = \"reverse\"\n\
+             unselected = \"\"\n\
+             highlight = \"red.underlined\"\n\
+             status = \"green\"",
+        )
+        .unwrap();
+        assert_eq!(theme.active_style, Style::new().reverse());
+        assert_eq!(theme.inactive_style, Style::new());
+        assert_eq!(theme.highlight_style(), Style::new().red().underlined());
+        assert_eq!(theme.status_style, Style::new().green());
+        // Not configured
+        assert_eq!(
+            theme.indicator_style,
+            ColorfulTheme::light().indicator_style
+        );
+    }
+
+    #[test]
+    fn invalid_styles() {
+        let error = theme("highlight = \"on_purple\"").err().unwrap();
+        assert_eq!(error, "unknown style `on_purple` in `on_purple`");
+    }
+
+    #[test]
+    fn no_color() {
+        assert!(colors_enabled(None, true));
+        assert!(!colors_enabled(None, false));
+        assert!(!colors_enabled(Some(OsStr::new("1")), true));
+        // An empty value doesn't disable colors
+        assert!(colors_enabled(Some(OsStr::new("")), true));
+    }
+}
@@ -0,0 +1,285 @@
+//! Scrolling and selection of the items of the picker.
+use std::ops::Range;
+
+/// Selected item and first item on the screen of a list of `len` items.
+///
+/// The `pinned` items are listed first, followed by a divider row if there
+/// are other items.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Viewport {
+    pub len: usize,
+    /// Number of pinned items at the start of the list.
+    pub pinned: usize,
+    /// Index of the selected item.
+    pub sel: usize,
+    /// Index of the first item on the screen.
+    pub offset: usize,
+}
+
+impl Viewport {
+    /// Returns the index of the last pinned item, if there is a divider
+    /// between pinned and other items.
+    pub fn divider_after(&self) -> Option<usize> {
+        if self.pinned == 0 || self.pinned >= self.len {
+            None
+        } else {
+            Some(self.pinned - 1)
+        }
+    }
+
+    /// Returns the number of items shown on `height` rows when scrolled to
+    /// `offset`.
+    pub fn visible(&self, offset: usize, height: usize) -> usize {
+        let count = height.min(self.len.saturating_sub(offset));
+        if count == 0 {
+            return 0;
+        }
+        match self.divider_after() {
+            // The divider takes a row if it is followed by a visible item
+            // and there is no row to spare
+            Some(last) if count == height && (offset..offset + count - 1).contains(&last) => {
+                count - 1
+            }
+            _ => count,
+        }
+    }
+
+    /// Returns the largest offset which still fills the screen.
+    pub fn max_offset(&self, height: usize) -> usize {
+        let mut offset = self.len.saturating_sub(height);
+        while offset < self.len && offset + self.visible(offset, height) < self.len {
+            offset += 1;
+        }
+        offset
+    }
+
+    /// Scrolls the least distance to show the selected item, keeping the
+    /// screen filled if possible. Selects the first item if the selection
+    /// is out of range.
+    pub fn scroll_to_selection(&mut self, height: usize) {
+        if self.sel >= self.len {
+            self.sel = 0;
+        }
+        if self.sel < self.offset {
+            self.offset = self.sel;
+        }
+        while self.sel >= self.offset + self.visible(self.offset, height) {
+            self.offset += 1;
+        }
+        self.offset = self.offset.min(self.max_offset(height));
+    }
+
+    /// Scrolls by `delta` items, moving the selection along if it leaves the
+    /// screen.
+    pub fn scroll(&mut self, height: usize, delta: isize) {
+        let max = self.max_offset(height) as isize;
+        self.offset = (self.offset as isize + delta).clamp(0, max) as usize;
+        let visible = self.visible(self.offset, height);
+        self.sel = self
+            .sel
+            .clamp(self.offset, (self.offset + visible).saturating_sub(1));
+    }
+
+    /// Moves the selection by `delta` items, wrapping around at either end.
+    pub fn move_selection(&mut self, delta: isize) {
+        if self.len > 0 {
+            self.sel = (self.sel as isize + delta).rem_euclid(self.len as isize) as usize;
+        }
+    }
+
+    /// Moves the selection and the screen by the number of items on the
+    /// screen, stopping at either end.
+    pub fn page(&mut self, height: usize, forward: bool) {
+        if self.len == 0 {
+            return;
+        }
+        let page = self.visible(self.offset, height).max(1);
+        if forward {
+            self.sel = (self.sel + page).min(self.len - 1);
+            self.scroll(height, page as isize);
+        } else {
+            self.sel = self.sel.saturating_sub(page);
+            self.scroll(height, -(page as isize));
+        }
+    }
+
+    /// Replaces the list with `len` items, of which `pinned` are pinned.
+    /// `sel` is the index of the selected item in the new list, if it is
+    /// still listed. It stays on the same row, otherwise the first item is
+    /// selected.
+    pub fn refilter(&mut self, len: usize, pinned: usize, sel: Option<usize>) {
+        let row = self.sel.saturating_sub(self.offset);
+        self.len = len;
+        self.pinned = pinned;
+        match sel {
+            Some(sel) => {
+                self.sel = sel;
+                self.offset = sel.saturating_sub(row);
+            }
+            None => {
+                self.sel = 0;
+                self.offset = 0;
+            }
+        }
+    }
+
+    /// Returns the position of the scrollbar thumb on a track of `height`
+    /// rows, if not all items fit on the screen.
+    pub fn scrollbar(&self, height: usize) -> Option<Range<usize>> {
+        let max = self.max_offset(height);
+        if max == 0 {
+            return None;
+        }
+        let visible = self.visible(self.offset, height);
+        let size = (height * visible / self.len).clamp(1, height);
+        let start = self.offset * (height - size) / max;
+        Some(start..start + size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(len: usize, pinned: usize) -> Viewport {
+        Viewport {
+            len,
+            pinned,
+            ..Viewport::default()
+        }
+    }
+
+    #[test]
+    fn divider_between_pinned_and_other_items() {
+        assert_eq!(viewport(5, 0).divider_after(), None);
+        assert_eq!(viewport(5, 2).divider_after(), Some(1));
+        assert_eq!(viewport(2, 2).divider_after(), None);
+    }
+
+    #[test]
+    fn divider_takes_a_row() {
+        let list = viewport(20, 2);
+        assert_eq!(list.visible(0, 10), 9);
+        // The divider is the last row, or after the screen
+        assert_eq!(list.visible(0, 2), 2);
+        assert_eq!(list.visible(1, 2), 1);
+        assert_eq!(list.visible(2, 10), 10);
+        // Rows to spare at the end
+        assert_eq!(viewport(5, 2).visible(0, 10), 5);
+        assert_eq!(list.visible(18, 10), 2);
+        assert_eq!(list.visible(20, 10), 0);
+    }
+
+    #[test]
+    fn max_offset_fills_the_screen() {
+        assert_eq!(viewport(5, 0).max_offset(10), 0);
+        assert_eq!(viewport(20, 0).max_offset(10), 10);
+        assert_eq!(viewport(20, 0).max_offset(0), 20);
+        // With the divider on the screen the last item needs one more row
+        assert_eq!(viewport(11, 2).max_offset(10), 2);
+        assert_eq!(viewport(10, 2).max_offset(10), 1);
+    }
+
+    #[test]
+    fn scroll_to_the_selection() {
+        let mut list = viewport(20, 0);
+        list.sel = 12;
+        list.scroll_to_selection(10);
+        assert_eq!(list.offset, 3);
+        list.sel = 1;
+        list.scroll_to_selection(10);
+        assert_eq!(list.offset, 1);
+        // Shrinking the list fills the screen again
+        list.len = 5;
+        list.sel = 4;
+        list.scroll_to_selection(10);
+        assert_eq!(list.offset, 0);
+        // The selection of a shorter list starts over
+        list.sel = 7;
+        list.scroll_to_selection(10);
+        assert_eq!((list.sel, list.offset), (0, 0));
+    }
+
+    #[test]
+    fn scroll_moves_the_selection_along() {
+        let mut list = viewport(20, 0);
+        list.scroll(10, 3);
+        assert_eq!((list.sel, list.offset), (3, 3));
+        list.scroll(10, 100);
+        assert_eq!((list.sel, list.offset), (10, 10));
+        list.sel = 19;
+        list.scroll(10, -4);
+        assert_eq!((list.sel, list.offset), (15, 6));
+        list.scroll(10, -100);
+        assert_eq!((list.sel, list.offset), (9, 0));
+    }
+
+    #[test]
+    fn selection_wraps_around() {
+        let mut list = viewport(3, 0);
+        list.move_selection(-1);
+        assert_eq!(list.sel, 2);
+        list.move_selection(1);
+        assert_eq!(list.sel, 0);
+        list.move_selection(4);
+        assert_eq!(list.sel, 1);
+        let mut empty = viewport(0, 0);
+        empty.move_selection(1);
+        assert_eq!(empty.sel, 0);
+    }
+
+    #[test]
+    fn page_stops_at_the_ends() {
+        let mut list = viewport(25, 0);
+        list.page(10, true);
+        assert_eq!((list.sel, list.offset), (10, 10));
+        list.page(10, true);
+        assert_eq!((list.sel, list.offset), (20, 15));
+        list.page(10, true);
+        assert_eq!((list.sel, list.offset), (24, 15));
+        list.page(10, false);
+        assert_eq!((list.sel, list.offset), (14, 5));
+        list.page(10, false);
+        assert_eq!((list.sel, list.offset), (4, 0));
+        list.page(10, false);
+        assert_eq!((list.sel, list.offset), (0, 0));
+        let mut empty = viewport(0, 0);
+        empty.page(10, true);
+        assert_eq!(empty, viewport(0, 0));
+    }
+
+    #[test]
+    fn refilter_keeps_the_row_of_the_selection() {
+        let mut list = viewport(30, 0);
+        list.sel = 14;
+        list.offset = 10;
+        list.refilter(20, 2, Some(7));
+        assert_eq!(
+            list,
+            Viewport {
+                len: 20,
+                pinned: 2,
+                sel: 7,
+                offset: 3
+            }
+        );
+        // Too close to the start to keep the row
+        list.refilter(20, 0, Some(1));
+        assert_eq!((list.sel, list.offset), (1, 0));
+        list.refilter(5, 0, None);
+        assert_eq!((list.sel, list.offset), (0, 0));
+    }
+
+    #[test]
+    fn scrollbar_thumb() {
+        let mut list = viewport(40, 0);
+        assert_eq!(viewport(10, 0).scrollbar(10), None);
+        assert_eq!(list.scrollbar(10), Some(0..2));
+        list.offset = 15;
+        assert_eq!(list.scrollbar(10), Some(4..6));
+        list.offset = 30;
+        assert_eq!(list.scrollbar(10), Some(8..10));
+        // At least one row
+        assert_eq!(viewport(1000, 0).scrollbar(10), Some(0..1));
+    }
+}
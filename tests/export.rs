@@ -0,0 +1,33 @@
+//! Runs `git quick export` in a temporary home directory.
+use std::process::Command;
+
+#[test]
+fn export_prints_only_the_manifest() {
+    // Hidden directories are not scanned, like the default `.tmp` prefix
+    let home = tempfile::Builder::new().prefix("home").tempdir().unwrap();
+    let git2_repo = git2::Repository::init(home.path().join("work").join("api")).unwrap();
+    git2_repo
+        .remote("origin", "https://example.com/acme/api.git")
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_git-quick"))
+        .arg("export")
+        .env("HOME", home.path())
+        .env_remove("XDG_CACHE_HOME")
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("XDG_DATA_HOME")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    // The scan of the home directory is reported on stderr
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Scanning for git repos"));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let manifest: toml::Value = toml::from_str(&stdout).unwrap();
+    let repos = manifest["repos"].as_array().unwrap();
+    assert_eq!(repos.len(), 1);
+    assert_eq!(repos[0]["path"].as_str(), Some("work/api"));
+    assert_eq!(
+        repos[0]["remotes"]["origin"].as_str(),
+        Some("https://example.com/acme/api.git")
+    );
+}